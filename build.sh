@@ -1,27 +1,10 @@
 CARGO="cargo"
-COMPILER="run"
-CC="gcc"
-
-function comp {
-    BN=$(basename -s .teeny $1)
-    TTOUTPUT=$(${CARGO} ${COMPILER} $1 2>&1)
-    if [ $? -ne 0 ]; then
-        echo "${TTOUTPUT}"
-    else
-        mv out.c ${BN}.c
-        CCOUTPUT=$(${CC} -o ${BN} ${BN}.c)
-        if [ $? -ne 0 ]; then
-            echo "${CCOUTPUT}"
-        else
-            echo "${TTOUTPUT}"
-        fi
-    fi
-}
 
+# Builds each example, or the file given, with `teeny build`, which runs $CC (cc by default)
 if [ $# -eq 0 ]; then
     for i in $(ls examples/*.teeny); do
-        comp $i
+        ${CARGO} run -q -- build -q $i
     done
 else
-    comp $1
+    ${CARGO} run -q -- build -q $1
 fi
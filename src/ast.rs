@@ -0,0 +1,212 @@
+use super::span::Span;
+use std::fmt;
+
+/// program ::= {statement}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+/// A statement and the source it was parsed from, up to but not including the newline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+/// One of the statements of the grammar. Bodies of IF and WHILE hold their nested statements.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementKind {
+    /// "PRINT" (expression | string)
+    Print(Printable),
+    /// "IF" comparison "THEN" nl {statement} "ENDIF"
+    If {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    /// "WHILE" comparison "REPEAT" nl {statement} "ENDWHILE"
+    While {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    /// "LABEL" ident, along with the `##` doc comment written above it
+    Label { name: Ident, doc: Option<String> },
+    /// "GOTO" ident
+    Goto(Ident),
+    /// "LET" ident "=" expression
+    Let { name: Ident, value: Expression },
+    /// "INPUT" ident
+    Input(Ident),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Printable {
+    Expression(Expression),
+    String(String),
+}
+
+/// A name written in a statement, such as the variable of a LET or the label of a GOTO.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+/// Comparisons are binary expressions too, so a comparison chain like `a < b < c`
+/// nests to the left just like `a - b - c` does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpressionKind {
+    Number(f64),
+    Ident(String),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    EqEq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        match self {
+            UnaryOp::Plus => value,
+            UnaryOp::Minus => -value,
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::EqEq => "==",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+        }
+    }
+
+    /// Apply the operator to two values. Comparisons give 1 for true and 0 for false, like C.
+    pub fn apply(&self, left: f64, right: f64) -> f64 {
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+        match self {
+            BinaryOp::Add => left + right,
+            BinaryOp::Sub => left - right,
+            BinaryOp::Mul => left * right,
+            BinaryOp::Div => left / right,
+            BinaryOp::EqEq => truth(left == right),
+            BinaryOp::NotEq => truth(left != right),
+            BinaryOp::Lt => truth(left < right),
+            BinaryOp::LtEq => truth(left <= right),
+            BinaryOp::Gt => truth(left > right),
+            BinaryOp::GtEq => truth(left >= right),
+        }
+    }
+}
+
+impl Expression {
+    /// Return the value of an expression made only of numbers, or None if it reads a variable
+    pub fn constant(&self) -> Option<f64> {
+        match &self.kind {
+            ExpressionKind::Number(value) => Some(*value),
+            ExpressionKind::Ident(_) => None,
+            ExpressionKind::Unary(op, operand) => Some(op.apply(operand.constant()?)),
+            ExpressionKind::Binary(op, left, right) => Some(op.apply(left.constant()?, right.constant()?)),
+        }
+    }
+
+    /// Return true if C evaluates the expression as a float. Variables are floats and number
+    /// literals are doubles, so only arithmetic on nothing but variables stays in single precision.
+    /// Lowering and the backends for other languages use this to round where C would.
+    pub fn is_float(&self) -> bool {
+        match &self.kind {
+            ExpressionKind::Number(_) => false,
+            ExpressionKind::Ident(_) => true,
+            ExpressionKind::Unary(_, operand) => operand.is_float(),
+            ExpressionKind::Binary(_, left, right) => left.is_float() && right.is_float(),
+        }
+    }
+}
+
+impl Statement {
+    /// Write the statement back out as teeny source, with nested bodies indented
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        write!(f, "{:indent$}", "", indent = indent)?;
+        match &self.kind {
+            StatementKind::Print(Printable::String(text)) => write!(f, "PRINT \"{}\"", text),
+            StatementKind::Print(Printable::Expression(expression)) => write!(f, "PRINT {}", expression),
+            StatementKind::If { condition, body } => {
+                writeln!(f, "IF {} THEN", condition)?;
+                for statement in body {
+                    statement.write(f, indent + 4)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{:indent$}ENDIF", "", indent = indent)
+            }
+            StatementKind::While { condition, body } => {
+                writeln!(f, "WHILE {} REPEAT", condition)?;
+                for statement in body {
+                    statement.write(f, indent + 4)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{:indent$}ENDWHILE", "", indent = indent)
+            }
+            StatementKind::Label { name, .. } => write!(f, "LABEL {}", name.name),
+            StatementKind::Goto(target) => write!(f, "GOTO {}", target.name),
+            StatementKind::Let { name, value } => write!(f, "LET {} = {}", name.name, value),
+            StatementKind::Input(name) => write!(f, "INPUT {}", name.name),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// Teeny has no parentheses, so expressions print the way the parser would have read them
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ExpressionKind::Number(value) => write!(f, "{}", value),
+            ExpressionKind::Ident(name) => write!(f, "{}", name),
+            ExpressionKind::Unary(op, operand) => write!(f, "{}{}", op.as_str(), operand),
+            ExpressionKind::Binary(op, left, right) => write!(f, "{} {} {}", left, op.as_str(), right),
+        }
+    }
+}
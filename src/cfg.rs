@@ -0,0 +1,291 @@
+use super::ast::{Expression, Program, Statement, StatementKind};
+use super::span::Span;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub type BlockId = usize;
+
+/// Control flow graph of a program. Blocks are split at LABEL, GOTO, IF and WHILE boundaries,
+/// so every block is a run of straight-line statements ended by a single terminator.
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub entry: BlockId,
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    /// The label declared at the start of the block, if any
+    pub label: Option<String>,
+    /// PRINT, LET and INPUT statements, which never transfer control
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+    /// Spans of every statement starting in this block, including the LABEL at its start,
+    /// a GOTO ending it and an IF or WHILE whose condition ends it
+    pub spans: Vec<Span>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Terminator {
+    /// Fall through into the next block
+    Jump(BlockId),
+    /// An explicit GOTO
+    Goto { target: BlockId, span: Span },
+    /// The condition of an IF or WHILE
+    Branch {
+        condition: Expression,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// The end of the program
+    Return,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) | Terminator::Goto { target, .. } => vec![*target],
+            Terminator::Branch {
+                then_block, else_block, ..
+            } => vec![*then_block, *else_block],
+            Terminator::Return => vec![],
+        }
+    }
+}
+
+impl Cfg {
+    /// Build the graph for a program. The resolver must have checked that every GOTO target exists.
+    pub fn new(program: &Program) -> Self {
+        let mut builder = CfgBuilder {
+            blocks: Vec::new(),
+            current: 0,
+            labels: HashMap::new(),
+            gotos: Vec::new(),
+        };
+
+        builder.current = builder.new_block();
+        builder.statements(&program.statements);
+        builder.finish(Terminator::Return);
+
+        // GOTOs may jump forward, so they are pointed at their blocks once every label is known
+        for (block, target) in std::mem::take(&mut builder.gotos) {
+            if let Terminator::Goto { target: id, .. } = &mut builder.blocks[block].terminator {
+                *id = builder.labels[&target];
+            }
+        }
+
+        Self {
+            blocks: builder.blocks,
+            entry: 0,
+        }
+    }
+
+    /// Return the predecessors of every block
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in block.terminator.successors() {
+                predecessors[successor].push(id);
+            }
+        }
+        predecessors
+    }
+
+    /// Return which blocks can be reached from the entry
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![self.entry];
+
+        while let Some(block) = stack.pop() {
+            if !reachable[block] {
+                reachable[block] = true;
+                stack.extend(self.blocks[block].terminator.successors());
+            }
+        }
+
+        reachable
+    }
+
+    /// Render the graph in Graphviz DOT format, one box per block
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut lines = vec![match &block.label {
+                Some(label) => format!("b{}: LABEL {}", id, label),
+                None => format!("b{}", id),
+            }];
+            lines.extend(block.statements.iter().map(|statement| statement.to_string()));
+            match &block.terminator {
+                Terminator::Goto { target, .. } => lines.push(format!("GOTO b{}", target)),
+                Terminator::Branch { condition, .. } => lines.push(format!("{} ?", condition)),
+                Terminator::Jump(_) => {}
+                Terminator::Return => lines.push("END".to_owned()),
+            }
+
+            let label: String = lines.iter().map(|line| format!("{}\\l", dot_escape(line))).collect();
+            writeln!(dot, "    b{} [label=\"{}\"];", id, label).unwrap();
+
+            match &block.terminator {
+                Terminator::Jump(target) | Terminator::Goto { target, .. } => {
+                    writeln!(dot, "    b{} -> b{};", id, target).unwrap();
+                }
+                Terminator::Branch {
+                    then_block, else_block, ..
+                } => {
+                    writeln!(dot, "    b{} -> b{} [label=\"true\"];", id, then_block).unwrap();
+                    writeln!(dot, "    b{} -> b{} [label=\"false\"];", id, else_block).unwrap();
+                }
+                Terminator::Return => {}
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+/// Escape text for use inside a quoted DOT string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct CfgBuilder {
+    blocks: Vec<BasicBlock>,
+    current: BlockId,
+    labels: HashMap<String, BlockId>,
+    gotos: Vec<(BlockId, String)>,
+}
+
+impl CfgBuilder {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock {
+            label: None,
+            statements: Vec::new(),
+            terminator: Terminator::Return,
+            spans: Vec::new(),
+        });
+        self.blocks.len() - 1
+    }
+
+    /// End the current block with the terminator
+    fn finish(&mut self, terminator: Terminator) {
+        self.blocks[self.current].terminator = terminator;
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(_) | StatementKind::Let { .. } | StatementKind::Input(_) => {
+                self.blocks[self.current].statements.push(statement.clone());
+                self.blocks[self.current].spans.push(statement.span);
+            }
+            StatementKind::Label { name, .. } => {
+                // An empty block can take the label itself rather than falling through to a new one
+                let current = &self.blocks[self.current];
+                if !current.statements.is_empty() || current.label.is_some() {
+                    let block = self.new_block();
+                    self.finish(Terminator::Jump(block));
+                    self.current = block;
+                }
+
+                self.blocks[self.current].label = Some(name.name.clone());
+                self.blocks[self.current].spans.push(statement.span);
+                self.labels.insert(name.name.clone(), self.current);
+            }
+            StatementKind::Goto(target) => {
+                self.gotos.push((self.current, target.name.clone()));
+                self.blocks[self.current].spans.push(statement.span);
+                self.finish(Terminator::Goto {
+                    target: 0,
+                    span: statement.span,
+                });
+
+                // Anything up to the next label is unreachable, but still gets a block
+                self.current = self.new_block();
+            }
+            StatementKind::If { condition, body } => {
+                let then_block = self.new_block();
+                let after = self.new_block();
+                self.blocks[self.current].spans.push(statement.span);
+                self.finish(Terminator::Branch {
+                    condition: condition.clone(),
+                    then_block,
+                    else_block: after,
+                });
+
+                self.current = then_block;
+                self.statements(body);
+                self.finish(Terminator::Jump(after));
+                self.current = after;
+            }
+            StatementKind::While { condition, body } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let after = self.new_block();
+                self.finish(Terminator::Jump(header));
+
+                self.current = header;
+                self.blocks[self.current].spans.push(statement.span);
+                self.finish(Terminator::Branch {
+                    condition: condition.clone(),
+                    then_block: body_block,
+                    else_block: after,
+                });
+
+                self.current = body_block;
+                self.statements(body);
+                self.finish(Terminator::Jump(header));
+                self.current = after;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn dot_output() {
+        let source = "\
+LABEL top
+LET n = 0
+WHILE n < 3 REPEAT
+    PRINT \"hi\"
+    LET n = n + 1
+ENDWHILE
+GOTO top
+";
+        assert_eq!(
+            Cfg::new(&parse(source).unwrap()).to_dot(),
+            r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    b0 [label="b0: LABEL top\lLET n = 0\l"];
+    b0 -> b1;
+    b1 [label="b1\ln < 3 ?\l"];
+    b1 -> b2 [label="true"];
+    b1 -> b3 [label="false"];
+    b2 [label="b2\lPRINT \"hi\"\lLET n = n + 1\l"];
+    b2 -> b1;
+    b3 [label="b3\lGOTO b0\l"];
+    b3 -> b0;
+    b4 [label="b4\lEND\l"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn dot_escapes_quotes_and_backslashes() {
+        assert_eq!(dot_escape(r#"say "a\b""#), r#"say \"a\\b\""#);
+    }
+}
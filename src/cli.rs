@@ -0,0 +1,558 @@
+use super::driver;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use teeny::diagnostic::Diagnostic;
+use teeny::lint::Lint;
+use teeny::optimize::OptLevel;
+use teeny::passes::Pass;
+use teeny::{interp, jit, lsp, repl, Emit};
+
+pub const USAGE: &str = "\
+usage: teeny [options] <file.teeny | ->
+       teeny build [options] <file.teeny | ->
+       teeny run [--jit | --cc] [options] <file.teeny | ->
+       teeny repl
+       teeny lsp
+       teeny fmt [--check] <file.teeny... | ->
+
+With no command the program is compiled to the --emit kind of output. build compiles it to an
+executable named after the file with the system C compiler, and run runs it: interpreted, with
+--jit compiled to machine code in memory, or with --cc built with the C compiler, exiting with
+the program's exit status. repl starts an interactive session; :help there lists its commands.
+lsp serves the Language Server Protocol over standard input and output, for editors. fmt
+formats files in place, or standard input to standard output; with --check it changes nothing
+and fails if any file isn't formatted.
+
+options:
+  -o <path>               write the output to <path>, or to standard output with -
+  --emit <kind>           what to write out: tokens, ast, cst, c, js, py, rust, cfg-dot, ir,
+                          asm, llvm-ir or exe (default c). --target is the same option
+  --line-directives       write #line directives into C, so the C compiler, debuggers and
+                          sanitizers point at the teeny source
+  --source-map <path>     write a JSON map from lines of the C to teeny statements to <path>
+  --cc-flag <flag>        pass a flag to the C compiler, after those in $CFLAGS. The compiler
+                          is $CC, or cc if that isn't set
+  -O0, -O1                optimization level (default -O0)
+  --disable-pass <pass>   turn off an IR pass at -O1
+  --allow <warning>       turn off a warning, by code or name
+  --deny-warnings         treat warnings as errors
+  -q, --quiet             print nothing but diagnostics
+  --version               print the version and exit
+  -h, --help              print this message and exit
+
+The source is read from standard input when the file is -.
+
+-O1 folds constants for every kind of output. The IR passes, the loop optimizations among them,
+only reach what is generated from the IR: ir, asm, llvm-ir and exe, and run with or without
+--jit. c, js, py and rust are generated from the syntax tree, and so are build and run --cc, so
+they get the folding alone.
+
+Programs built with --emit exe or run with --jit read INPUT without the C library. A number with
+at most 15 significant digits whose power of ten is at most 10^22 either way, such as 0.1 or
+12345.678, reads exactly as scanf reads it. Others are scaled with rounding at each step, so they
+may differ from scanf in the last few bits of a double, and numbers near the largest double may
+read as infinity.
+
+exit status:
+  0  success
+  1  the program has errors, or with fmt --check a file isn't formatted
+  2  the command line is wrong
+  3  a file couldn't be read or written
+  4  the C compiler failed";
+
+/// The program has errors, or warnings with `--deny-warnings`
+pub const EXIT_ERRORS: i32 = 1;
+/// The command line couldn't be parsed
+pub const EXIT_USAGE: i32 = 2;
+/// A file couldn't be read or written
+pub const EXIT_IO: i32 = 3;
+/// The C compiler couldn't be run or failed
+pub const EXIT_CC: i32 = 4;
+
+/// What's done with the program
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Write out the `--emit` kind of output
+    Emit,
+    /// Build an executable with the system C compiler, with `build`
+    Build,
+    /// Run the program, with `run`
+    Run(Engine),
+}
+
+/// How `run` runs the program
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Engine {
+    /// Interpret the IR
+    Interpreter,
+    /// Compile to machine code in memory and call it, or interpret if that isn't possible here
+    Jit,
+    /// Build an executable with the system C compiler and run it
+    Cc,
+}
+
+/// Where each kind of output goes without `-o`
+pub fn default_output(emit: Emit) -> &'static str {
+    match emit {
+        Emit::Tokens | Emit::Ast | Emit::Cst => "-",
+        Emit::C => "out.c",
+        Emit::Js => "out.js",
+        Emit::Py => "out.py",
+        Emit::Rust => "out.rs",
+        Emit::CfgDot => "out.dot",
+        Emit::Ir => "out.ir",
+        Emit::Asm => "out.s",
+        Emit::LlvmIr => "out.ll",
+        Emit::Exe => "out",
+    }
+}
+
+/// What the command line asks for
+#[derive(Debug)]
+pub enum Command {
+    Compile(Box<Options>),
+    /// Start an interactive session
+    Repl,
+    /// Serve the Language Server Protocol over standard input and output
+    Lsp,
+    /// Format the files, or check they're formatted
+    Format {
+        check: bool,
+        inputs: Vec<String>,
+    },
+    Help,
+    Version,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// The teeny file, or `-` for standard input
+    pub input: String,
+    /// Where to write the output, or `-` for standard output. Each kind of output has a default.
+    pub output: Option<String>,
+    pub mode: Mode,
+    /// Flags for the C compiler given with `--cc-flag`
+    pub cc_flags: Vec<String>,
+    /// Print nothing but diagnostics
+    pub quiet: bool,
+    /// Where to write the JSON source map of the C, if anywhere
+    pub source_map: Option<String>,
+    /// How the program is compiled
+    pub compile: teeny::Options,
+}
+
+impl Options {
+    /// The name of the input for diagnostics
+    pub fn input_name(&self) -> &str {
+        if self.input == "-" {
+            "<stdin>"
+        } else {
+            &self.input
+        }
+    }
+
+    /// Where the output goes, `-` being standard output
+    pub fn output_path(&self) -> &str {
+        self.output.as_deref().unwrap_or(default_output(self.compile.emit))
+    }
+}
+
+/// Parse the command line arguments, not including the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut mode = Mode::Emit;
+    let mut engine = None;
+    let mut emitting = false;
+    let mut cc_flags = Vec::new();
+    let mut quiet = false;
+    let mut line_directives = false;
+    let mut source_map = None;
+    let mut compile = teeny::Options::default();
+
+    let mut args = args.into_iter().peekable();
+    if let Some(command) = args.next_if(|arg| arg == "repl" || arg == "lsp") {
+        return match args.next().as_deref() {
+            None if command == "repl" => Ok(Command::Repl),
+            None => Ok(Command::Lsp),
+            Some("-h" | "--help") => Ok(Command::Help),
+            Some(arg) => Err(format!("{} takes no arguments, got {}", command, arg)),
+        };
+    }
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        let mut check = false;
+        let mut inputs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--check" => check = true,
+                "-h" | "--help" => return Ok(Command::Help),
+                _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option: {}", arg)),
+                _ => inputs.push(arg),
+            }
+        }
+        if inputs.is_empty() {
+            return Err("expected the teeny files to format".to_owned());
+        }
+        return Ok(Command::Format { check, inputs });
+    }
+    match args.peek().map(String::as_str) {
+        Some("build") => mode = Mode::Build,
+        Some("run") => mode = Mode::Run(Engine::Interpreter),
+        _ => {}
+    }
+    if mode != Mode::Emit {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            "-o" => output = Some(value()?),
+            "--emit" | "--target" => {
+                let kind = value()?;
+                compile.emit = Emit::from_name(&kind).ok_or_else(|| format!("unknown --emit kind: {}", kind))?;
+                emitting = true;
+            }
+            "--allow" => {
+                let name = value()?;
+                compile
+                    .allowed
+                    .insert(Lint::from_name(&name).ok_or_else(|| format!("unknown warning: {}", name))?);
+            }
+            "--deny-warnings" => compile.deny_warnings = true,
+            "--jit" => engine = Some(Engine::Jit),
+            "--cc" => engine = Some(Engine::Cc),
+            "--cc-flag" => cc_flags.push(value()?),
+            "--line-directives" => line_directives = true,
+            "--source-map" => source_map = Some(value()?),
+            "--quiet" | "-q" => quiet = true,
+            "-O0" => compile.opt_level = OptLevel::O0,
+            "-O1" => compile.opt_level = OptLevel::O1,
+            "--disable-pass" => {
+                let name = value()?;
+                compile
+                    .disabled_passes
+                    .insert(Pass::from_name(&name).ok_or_else(|| format!("unknown pass: {}", name))?);
+            }
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option: {}", arg)),
+            _ if input.is_some() => return Err(format!("unexpected argument: {}", arg)),
+            _ => input = Some(arg),
+        }
+    }
+
+    if let Some(engine) = engine {
+        if !matches!(mode, Mode::Run(_)) {
+            return Err("--jit and --cc only apply to run".to_owned());
+        }
+        mode = Mode::Run(engine);
+    }
+    if emitting && mode != Mode::Emit {
+        return Err("--emit doesn't apply to build or run".to_owned());
+    }
+
+    let writes_c = match mode {
+        Mode::Emit => compile.emit == Emit::C,
+        Mode::Build | Mode::Run(Engine::Cc) => true,
+        Mode::Run(_) => false,
+    };
+    if line_directives && !writes_c {
+        return Err("--line-directives only applies to C".to_owned());
+    }
+    if source_map.is_some() && !(mode == Mode::Emit && compile.emit == Emit::C) {
+        return Err("--source-map only applies to --emit c".to_owned());
+    }
+
+    let input: String = input.ok_or("expected the teeny file")?;
+    let mut options = Options {
+        input,
+        output,
+        mode,
+        cc_flags,
+        quiet,
+        source_map,
+        compile,
+    };
+    if line_directives {
+        options.compile.line_directives = Some(options.input_name().to_owned());
+    }
+    options.compile.source_map = options.source_map.is_some();
+    Ok(Command::Compile(Box::new(options)))
+}
+
+/// Carry out the command line arguments, not including the program name, returning the exit
+/// status
+pub fn run(args: impl IntoIterator<Item = String>) -> i32 {
+    match parse(args) {
+        Ok(Command::Compile(options)) => compile(&options),
+        Ok(Command::Repl) => {
+            let interactive = io::stdin().is_terminal();
+            if interactive {
+                println!(
+                    "teeny {} interactive session, :help for help",
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+            match repl::run(&mut io::stdin().lock(), &mut io::stdout().lock(), interactive) {
+                Ok(()) => 0,
+                Err(why) => {
+                    eprintln!("error: {}", why);
+                    EXIT_IO
+                }
+            }
+        }
+        Ok(Command::Lsp) => lsp::Server::new()
+            .run(&mut io::stdin().lock(), &mut io::stdout().lock())
+            .unwrap_or_else(|why| {
+                eprintln!("error: {}", why);
+                EXIT_IO
+            }),
+        Ok(Command::Format { check, inputs }) => format(check, &inputs),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            0
+        }
+        Ok(Command::Version) => {
+            println!("teeny {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            EXIT_USAGE
+        }
+    }
+}
+
+/// Compile, build or run the program as the options say, returning the exit status
+fn compile(options: &Options) -> i32 {
+    // Status goes to stderr so standard output is left for the program or the output file
+    let verbose = !options.quiet && !matches!(options.mode, Mode::Run(_));
+    if verbose {
+        eprintln!("Teeny Tiny Compiler");
+    }
+
+    let source = match read_source(&options.input) {
+        Ok(source) => source,
+        Err(why) => {
+            eprintln!("error: couldn't read {}: {}", options.input_name(), why);
+            return EXIT_IO;
+        }
+    };
+
+    if options.mode == Mode::Emit {
+        return match emit(options, &source) {
+            Ok(()) => {
+                if verbose {
+                    eprintln!("Compiling completed");
+                }
+                0
+            }
+            Err(status) => status,
+        };
+    }
+
+    let program = match teeny::check(&source, &options.compile) {
+        Ok((program, warnings)) => {
+            report(&warnings, options, &source);
+            program
+        }
+        Err(errors) => {
+            report(&errors, options, &source);
+            return EXIT_ERRORS;
+        }
+    };
+
+    match options.mode {
+        Mode::Emit => unreachable!("written out above"),
+        Mode::Build => match driver::build(&program, &source, options) {
+            Ok(()) => {
+                if verbose {
+                    eprintln!("Compiling completed");
+                }
+                0
+            }
+            Err(status) => status,
+        },
+        Mode::Run(Engine::Cc) => driver::run(&program, &source, options),
+        Mode::Run(engine) => {
+            let function = teeny::ir(&program, &options.compile);
+            if engine == Engine::Jit {
+                if let Some(jit) = jit::Jit::new(&function) {
+                    jit.run();
+                    return 0;
+                }
+            }
+            match interp::run(&function, &mut io::stdin().lock(), &mut io::stdout().lock()) {
+                Ok(()) => 0,
+                Err(why) => {
+                    eprintln!("error: couldn't run {}: {}", options.input_name(), why);
+                    EXIT_IO
+                }
+            }
+        }
+    }
+}
+
+/// Write out the `--emit` kind of output, and the source map if asked for. Returns the exit
+/// status to fail with.
+fn emit(options: &Options, source: &str) -> Result<(), i32> {
+    let output = teeny::compile_str(source, &options.compile).map_err(|errors| {
+        report(&errors, options, source);
+        EXIT_ERRORS
+    })?;
+    report(&output.warnings, options, source);
+    write_output(options, &output.code)?;
+    if let (Some(path), Some(map)) = (&options.source_map, &output.source_map) {
+        let json = map.to_json(options.output_path(), options.input_name());
+        if let Err(why) = fs::write(path, json + "\n") {
+            eprintln!("error: couldn't write {}: {}", path, why);
+            return Err(EXIT_IO);
+        }
+    }
+    Ok(())
+}
+
+/// Read the teeny file, or standard input for `-`
+fn read_source(input: &str) -> io::Result<String> {
+    let mut source = String::new();
+    if input == "-" {
+        io::stdin().read_to_string(&mut source)?;
+    } else {
+        File::open(input)?.read_to_string(&mut source)?;
+    }
+    Ok(source)
+}
+
+/// Format each file in place, or standard input to standard output. With `check` nothing is
+/// written, and files that aren't formatted are listed. Returns the exit status.
+fn format(check: bool, inputs: &[String]) -> i32 {
+    let mut status = 0;
+    for input in inputs {
+        let name = if input == "-" { "<stdin>" } else { input };
+        let source = match read_source(input) {
+            Ok(source) => source,
+            Err(why) => {
+                eprintln!("error: couldn't read {}: {}", name, why);
+                status = EXIT_IO;
+                continue;
+            }
+        };
+        let formatted = match teeny::format::format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in errors {
+                    eprint!("{}", error.render(name, &source));
+                }
+                status = status.max(EXIT_ERRORS);
+                continue;
+            }
+        };
+
+        let written = if check {
+            if formatted != source {
+                eprintln!("{} isn't formatted", name);
+                status = status.max(EXIT_ERRORS);
+            }
+            Ok(())
+        } else if input == "-" {
+            io::stdout().write_all(formatted.as_bytes())
+        } else if formatted != source {
+            fs::write(input, formatted)
+        } else {
+            Ok(())
+        };
+        if let Err(why) = written {
+            eprintln!("error: couldn't write {}: {}", name, why);
+            status = EXIT_IO;
+        }
+    }
+    status
+}
+
+/// Write the output to the file the options name, or to standard output for `-`. Returns the
+/// exit status to fail with.
+fn write_output(options: &Options, output: &[u8]) -> Result<(), i32> {
+    let path = options.output_path();
+    let written = if path == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output).and_then(|_| stdout.flush())
+    } else {
+        fs::write(path, output)
+    };
+    // Executables are written executable
+    let written = written.and_then(|_| match options.compile.emit {
+        Emit::Exe if path != "-" => fs::set_permissions(path, fs::Permissions::from_mode(0o755)),
+        _ => Ok(()),
+    });
+    written.map_err(|why| {
+        eprintln!("error: couldn't write {}: {}", path, why);
+        EXIT_IO
+    })
+}
+
+/// Print the diagnostics
+fn report(diagnostics: &[Diagnostic], options: &Options, source: &str) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(options.input_name(), source));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    /// Write a teeny file into the temporary directory, returning its path
+    fn write_file(name: &str, source: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("teeny-cli-{}-{}", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn run_args(args: &[&str]) -> i32 {
+        run(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn fmt_check_fails_on_unformatted_input() {
+        let formatted = write_file("formatted.teeny", "LET n = 1\nPRINT n\n");
+        let unformatted = write_file("unformatted.teeny", "LET   n=1\n  PRINT n\n");
+        let (formatted, unformatted) = (formatted.to_str().unwrap(), unformatted.to_str().unwrap());
+
+        assert_eq!(run_args(&["fmt", "--check", formatted]), 0);
+        assert_eq!(run_args(&["fmt", "--check", unformatted]), EXIT_ERRORS);
+        assert_eq!(run_args(&["fmt", "--check", formatted, unformatted]), EXIT_ERRORS);
+        // --check leaves the file as it was
+        assert_eq!(fs::read_to_string(unformatted).unwrap(), "LET   n=1\n  PRINT n\n");
+
+        fs::remove_file(formatted).unwrap();
+        fs::remove_file(unformatted).unwrap();
+    }
+
+    #[test]
+    fn exit_statuses() {
+        let good = write_file("good.teeny", "PRINT 1\n");
+        let bad = write_file("bad.teeny", "PRINT n\n");
+        let missing = env::temp_dir().join(format!("teeny-cli-{}-missing.teeny", std::process::id()));
+        let output = env::temp_dir().join(format!("teeny-cli-{}-out", std::process::id()));
+        let (good, bad) = (good.to_str().unwrap(), bad.to_str().unwrap());
+        let (missing, output) = (missing.to_str().unwrap(), output.to_str().unwrap());
+
+        assert_eq!(run_args(&["-q", "-o", output, good]), 0);
+        assert_eq!(run_args(&["-q", "-o", output, bad]), EXIT_ERRORS);
+        assert_eq!(run_args(&["--no-such-option", good]), EXIT_USAGE);
+        assert_eq!(run_args(&["run", "--emit", "c", good]), EXIT_USAGE);
+        assert_eq!(run_args(&["-q", "-o", output, missing]), EXIT_IO);
+        // The C compiler rejects the flag, or isn't there to run at all
+        assert_eq!(
+            run_args(&["build", "-q", "--cc-flag", "--no-such-flag", "-o", output, good]),
+            EXIT_CC
+        );
+
+        fs::remove_file(good).unwrap();
+        fs::remove_file(bad).unwrap();
+        let _ = fs::remove_file(output);
+    }
+}
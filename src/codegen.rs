@@ -0,0 +1,281 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, Program, Statement, StatementKind};
+use super::emitter::{Emitter, Section};
+use super::span::{LineIndex, Span};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Walks the AST and writes the equivalent C program through the emitter.
+#[derive(Debug)]
+pub struct CodeGen<'a, W: Write> {
+    emitter: &'a mut Emitter<W>,
+    declared: HashSet<String>,
+    /// Runs of lines of the main section, each from the line given, counting from 0, to the next
+    /// run's, with the span of the statement they were generated from if there was one.
+    /// The C compiler's messages are mapped back to the teeny source with it.
+    pub line_map: Vec<(usize, Option<Span>)>,
+    /// The teeny file named by `#line` directives, quoted for C, if they're written
+    line_file: Option<String>,
+    /// Where the lines of the teeny source start, for the directives
+    lines: LineIndex,
+}
+
+impl<'a, W: Write> CodeGen<'a, W> {
+    pub fn new(emitter: &'a mut Emitter<W>) -> Self {
+        Self {
+            emitter,
+            declared: Default::default(), // Variables given a C declaration so far
+            line_map: Vec::new(),
+            line_file: None,
+            lines: LineIndex::default(),
+        }
+    }
+
+    /// Write a `#line` directive before each statement giving where it is in the teeny file, so
+    /// the C compiler, debuggers and sanitizers point at the teeny source
+    pub fn with_line_directives(mut self, file_name: &str, source: &str) -> Self {
+        let quoted = file_name.replace('\\', "\\\\").replace('"', "\\\"");
+        self.line_file = Some(format!("\"{}\"", quoted));
+        self.lines = LineIndex::new(source);
+        self
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        self.emitter.line_to(Section::Includes, "#include <stdio.h>");
+        self.emitter.begin_main("int main(void) {");
+
+        for statement in &program.statements {
+            self.statement(statement);
+        }
+
+        // Wrap things up
+        self.line_map.push((self.emitter.line(), None));
+        self.emitter.emit_line("return 0;");
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    pub fn statement(&mut self, statement: &Statement) {
+        self.line_map.push((self.emitter.line(), Some(statement.span)));
+        self.line_directive(statement.span.start);
+        match &statement.kind {
+            StatementKind::Print(Printable::String(text)) => {
+                self.emitter.emit_line(&format!("{}{}{}", "printf(\"", text, "\\n\");"));
+            }
+            StatementKind::Print(Printable::Expression(expression)) => {
+                self.emitter.emit("printf(\"%.2f\\n\", (float)(");
+                self.expression(expression);
+                self.emitter.emit_line("));");
+            }
+            StatementKind::If { condition, body } => {
+                self.emitter.emit("if(");
+                self.expression(condition);
+                self.emitter.emit_line("){");
+                self.body(body, statement.span);
+            }
+            StatementKind::While { condition, body } => {
+                self.emitter.emit("while(");
+                self.expression(condition);
+                self.emitter.emit_line("){");
+                self.body(body, statement.span);
+            }
+            StatementKind::Label { name, .. } => {
+                // Labels stand out a level to the left of the code around them
+                self.emitter.dedent();
+                self.emitter.emit_line(&format!("{}:", name.name));
+                self.emitter.indent();
+            }
+            StatementKind::Goto(target) => {
+                self.emitter.emit_line(&format!("goto {};", target.name));
+            }
+            StatementKind::Let { name, value } => {
+                let name = &name.name;
+                self.declare(name);
+                self.emitter.emit(&format!("{} = ", name));
+                self.expression(value);
+                self.emitter.emit_line(";");
+            }
+            StatementKind::Input(name) => {
+                // A word that isn't a number is skipped and reads as zero, as does the end of the
+                // input, like the other backends read
+                let name = &name.name;
+                self.declare(name);
+                self.emitter
+                    .emit_line(&format!("{}{}{}", "if (1 != scanf(\"%f\", &", name, ")) {"));
+                self.emitter.indent();
+                self.emitter.emit_line(&format!("{} = 0;", name));
+                self.emitter.emit_line("scanf(\"%*s\");");
+                self.emitter.dedent();
+                self.emitter.emit_line("}");
+            }
+        }
+    }
+
+    /// Emit the statements of an IF or WHILE body, indented, and the brace closing it, which maps
+    /// back to the ENDIF or ENDWHILE ending the statement's span
+    fn body(&mut self, body: &[Statement], span: Span) {
+        self.emitter.indent();
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emitter.dedent();
+        self.line_map.push((self.emitter.line(), Some(span)));
+        self.line_directive(span.end);
+        self.emitter.emit_line("}");
+    }
+
+    /// Emit a `#line` directive for the line of the teeny source a position is on, if asked to
+    fn line_directive(&mut self, pos: usize) {
+        if let Some(file) = &self.line_file {
+            let (line, _) = self.lines.line_col(pos);
+            let directive = format!("#line {} {}", line, file);
+            self.emitter.emit_line(&directive);
+        }
+    }
+
+    /// Emit an expression, adding parentheses only where C's precedence would otherwise differ
+    pub fn expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            // Written as double literals, so `7 / 2` divides like the folded constant would
+            ExpressionKind::Number(value) => self.emitter.emit(&format!("{:?}", value)),
+            ExpressionKind::Ident(name) => {
+                // Optimization can remove the only assignment the C code would have seen
+                self.declare(name);
+                self.emitter.emit(name)
+            }
+            ExpressionKind::Unary(op, operand) => {
+                self.emitter.emit(op.as_str());
+                self.operand(operand, u8::MAX);
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                let precedence = precedence(*op);
+                self.operand(left, precedence);
+                self.emitter.emit(&format!(" {} ", op.as_str()));
+                // Operators are left associative, so an equal precedence on the right needs parentheses
+                self.operand(right, precedence + 1);
+            }
+        }
+    }
+
+    fn operand(&mut self, expression: &Expression, min_precedence: u8) {
+        let needs_parens = match &expression.kind {
+            ExpressionKind::Binary(op, _, _) => precedence(*op) < min_precedence,
+            _ => false,
+        };
+
+        if needs_parens {
+            self.emitter.emit("(");
+            self.expression(expression);
+            self.emitter.emit(")");
+        } else {
+            self.expression(expression);
+        }
+    }
+
+    /// If variable doesn't already exist, declare it.
+    /// Variables start at zero so a read the analysis can't rule out never sees garbage.
+    fn declare(&mut self, name: &str) {
+        if self.declared.insert(name.to_owned()) {
+            self.emitter
+                .line_to(Section::Declarations, &format!("float {} = 0;", name));
+        }
+    }
+}
+
+/// C operator precedence, higher binds tighter
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Mul | BinaryOp::Div => 4,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => 2,
+        BinaryOp::EqEq | BinaryOp::NotEq => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{c, parse, Options};
+
+    fn generate(source: &str) -> String {
+        let (code, _) = c(&parse(source).unwrap(), source, &Options::default());
+        String::from_utf8(code).unwrap()
+    }
+
+    #[test]
+    fn one_declaration_per_source_variable() {
+        let source = "\
+PRINT \"hi\"
+INPUT n
+LET total = 0
+LET i = 0
+WHILE i < n REPEAT
+    LET total = total + i * 2 - 1
+    LET i = i + 1
+ENDWHILE
+IF total > 10 THEN
+    PRINT total
+ENDIF
+";
+        assert_eq!(
+            generate(source),
+            "\
+#include <stdio.h>
+int main(void) {
+    float n = 0;
+    float total = 0;
+    float i = 0;
+    printf(\"hi\\n\");
+    if (1 != scanf(\"%f\", &n)) {
+        n = 0;
+        scanf(\"%*s\");
+    }
+    total = 0.0;
+    i = 0.0;
+    while(i < n){
+        total = total + i * 2.0 - 1.0;
+        i = i + 1.0;
+    }
+    if(total > 10.0){
+        printf(\"%.2f\\n\", (float)(total));
+    }
+    return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn parenthesizes_only_where_precedence_differs() {
+        let code = generate("LET a = 1\nLET b = a - 2 - 3 * a\nLET c = a * 4 / 5\nPRINT b - c\n");
+        assert!(code.contains("b = a - 2.0 - 3.0 * a;"));
+        assert!(code.contains("c = a * 4.0 / 5.0;"));
+        assert!(code.contains("printf(\"%.2f\\n\", (float)(b - c));"));
+        assert_eq!(code.matches("float ").count(), 3);
+    }
+
+    #[test]
+    fn line_directives_name_the_quoted_file() {
+        let source = "LET n = 0\nWHILE n < 2 REPEAT\n    LET n = n + 1\nENDWHILE\n";
+        let options = Options {
+            line_directives: Some("say \"hi\"\\n.teeny".to_owned()),
+            ..Options::default()
+        };
+        let (code, _) = c(&parse(source).unwrap(), source, &options);
+        assert_eq!(
+            String::from_utf8(code).unwrap(),
+            r#"#include <stdio.h>
+int main(void) {
+    float n = 0;
+    #line 1 "say \"hi\"\\n.teeny"
+    n = 0.0;
+    #line 2 "say \"hi\"\\n.teeny"
+    while(n < 2.0){
+        #line 3 "say \"hi\"\\n.teeny"
+        n = n + 1.0;
+    #line 4 "say \"hi\"\\n.teeny"
+    }
+    return 0;
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use super::lex::Lexer;
+use super::span::Span;
+use super::token::TokenType;
+use super::Diagnostics;
+use std::fmt::{self, Write};
+
+/// Source between tokens that the grammar ignores
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Comment,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/// A token with its exact text, quotes and all, and the trivia written before it
+#[derive(Clone, Debug, PartialEq)]
+pub struct CstToken {
+    pub kind: TokenType,
+    pub text: String,
+    pub span: Span,
+    pub leading: Vec<Trivia>,
+}
+
+/// The kinds of nodes, one for each statement and expression of the grammar
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    Program,
+    Print,
+    If,
+    While,
+    Label,
+    Goto,
+    Let,
+    Input,
+    Unary,
+    Binary,
+    /// A number or a variable
+    Primary,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Element {
+    Node(Node),
+    Token(CstToken),
+}
+
+/// A node of the concrete syntax tree. Unlike the AST it keeps every token, newlines included,
+/// along with the whitespace and comments between them, so writing a tree out with `Display` gives
+/// back the source it was parsed from byte for byte. Statements own the newlines ending them,
+/// IF and WHILE own the statements of their bodies, and the program owns the blank lines before
+/// its first statement and the end of file token, which holds any trailing trivia.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    /// Every token of the node, in order
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a CstToken>) {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.collect_tokens(tokens),
+                Element::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// The span of the node's tokens, not counting the trivia before the first
+    pub fn span(&self) -> Span {
+        let tokens = self.tokens();
+        match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => Span::default(),
+        }
+    }
+
+    /// Write the tree out one node, token or piece of trivia per line, indented by depth
+    ///
+    /// ```text
+    /// Program
+    ///   Let
+    ///     Let "LET"
+    ///     Whitespace " "
+    ///     Ident "x"
+    /// ```
+    pub fn to_tree(&self) -> String {
+        let mut tree = String::new();
+        self.write_tree(&mut tree, 0);
+        tree
+    }
+
+    fn write_tree(&self, tree: &mut String, depth: usize) {
+        writeln!(tree, "{:indent$}{:?}", "", self.kind, indent = depth * 2).unwrap();
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.write_tree(tree, depth + 1),
+                Element::Token(token) => {
+                    for trivia in &token.leading {
+                        writeln!(
+                            tree,
+                            "{:indent$}{:?} {:?}",
+                            "",
+                            trivia.kind,
+                            trivia.text,
+                            indent = depth * 2 + 2
+                        )
+                        .unwrap();
+                    }
+                    writeln!(
+                        tree,
+                        "{:indent$}{:?} {:?}",
+                        "",
+                        token.kind,
+                        token.text,
+                        indent = depth * 2 + 2
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// The source the tree was parsed from, exactly
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens() {
+            for trivia in &token.leading {
+                write!(f, "{}", trivia.text)?;
+            }
+            write!(f, "{}", token.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the source into a concrete syntax tree. The source is parsed into an AST first, so
+/// the tree is only built for programs that parse, and their errors are returned otherwise.
+pub fn parse(source: &str) -> Result<Node, Diagnostics> {
+    super::parse(source)?;
+    let mut builder = Builder {
+        tokens: tokens(source)?.into_iter().peekable(),
+    };
+    Ok(builder.program())
+}
+
+/// Lex the source keeping its comments, and attach them and the whitespace between tokens to the
+/// token after them
+fn tokens(source: &str) -> Result<Vec<CstToken>, Diagnostics> {
+    let chars: Vec<char> = source.chars().collect();
+    // The lexer adds a newline to the end of the source, which has no text of its own here
+    let text = |span: Span| -> String {
+        chars[span.start.min(chars.len())..span.end.min(chars.len())]
+            .iter()
+            .collect()
+    };
+
+    let mut lexer = Lexer::new(source).with_comments();
+    let mut tokens = Vec::new();
+    let mut leading = Vec::new();
+    let mut end = 0;
+    loop {
+        let token = lexer.get_token().map_err(|error| vec![error])?;
+        if token.span.start > end {
+            let span = Span::new(end, token.span.start);
+            leading.push(Trivia {
+                kind: TriviaKind::Whitespace,
+                text: text(span),
+                span,
+            });
+        }
+        end = token.span.end;
+
+        if token.kind == TokenType::Comment {
+            leading.push(Trivia {
+                kind: TriviaKind::Comment,
+                text: text(token.span),
+                span: token.span,
+            });
+            continue;
+        }
+
+        let eof = token.kind == TokenType::Eof;
+        tokens.push(CstToken {
+            kind: token.kind,
+            text: text(token.span),
+            span: token.span,
+            leading: std::mem::take(&mut leading),
+        });
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+/// Builds the tree from the tokens of a program already known to parse, so it follows the grammar
+/// without checking it
+struct Builder {
+    tokens: std::iter::Peekable<std::vec::IntoIter<CstToken>>,
+}
+
+impl Builder {
+    fn check(&mut self, kind: TokenType) -> bool {
+        self.tokens.peek().is_some_and(|token| token.kind == kind)
+    }
+
+    fn token(&mut self, children: &mut Vec<Element>) {
+        let token = self
+            .tokens
+            .next()
+            .expect("the program parsed, so it has the tokens the grammar needs");
+        children.push(Element::Token(token));
+    }
+
+    /// nl ::= '\n'+
+    fn newlines(&mut self, children: &mut Vec<Element>) {
+        while self.check(TokenType::Newline) {
+            self.token(children);
+        }
+    }
+
+    /// program ::= {statement}
+    fn program(&mut self) -> Node {
+        let mut children = Vec::new();
+        self.newlines(&mut children);
+        while !self.check(TokenType::Eof) {
+            children.push(Element::Node(self.statement()));
+        }
+        self.token(&mut children);
+        Node {
+            kind: NodeKind::Program,
+            children,
+        }
+    }
+
+    fn statement(&mut self) -> Node {
+        let mut children = Vec::new();
+        let keyword = self.tokens.peek().expect("a statement starts with a token").kind;
+        self.token(&mut children);
+
+        let kind = match keyword {
+            TokenType::Print => {
+                if self.check(TokenType::String) {
+                    self.token(&mut children);
+                } else {
+                    children.push(self.expression());
+                }
+                NodeKind::Print
+            }
+            TokenType::If | TokenType::While => {
+                children.push(self.expression());
+                self.token(&mut children); // THEN or REPEAT
+                self.newlines(&mut children);
+
+                let end = if keyword == TokenType::If {
+                    TokenType::EndIf
+                } else {
+                    TokenType::EndWhile
+                };
+                while !self.check(end) {
+                    children.push(Element::Node(self.statement()));
+                }
+                self.token(&mut children);
+
+                if keyword == TokenType::If {
+                    NodeKind::If
+                } else {
+                    NodeKind::While
+                }
+            }
+            TokenType::Label => {
+                self.token(&mut children);
+                NodeKind::Label
+            }
+            TokenType::Goto => {
+                self.token(&mut children);
+                NodeKind::Goto
+            }
+            TokenType::Let => {
+                self.token(&mut children); // The variable
+                self.token(&mut children); // =
+                children.push(self.expression());
+                NodeKind::Let
+            }
+            TokenType::Input => {
+                self.token(&mut children);
+                NodeKind::Input
+            }
+            _ => unreachable!("the program parsed, so {:?} doesn't start a statement", keyword),
+        };
+
+        self.newlines(&mut children);
+        Node { kind, children }
+    }
+
+    /// Comparisons, sums and products all nest to the left, each binding tighter than the last
+    fn expression(&mut self) -> Element {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Element {
+        const LEVELS: [&[TokenType]; 3] = [
+            &[
+                TokenType::EqEq,
+                TokenType::NotEq,
+                TokenType::Gt,
+                TokenType::GtEq,
+                TokenType::Lt,
+                TokenType::LtEq,
+            ],
+            &[TokenType::Plus, TokenType::Minus],
+            &[TokenType::Asterisk, TokenType::Slash],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1);
+        while LEVELS[level].iter().any(|kind| self.check(*kind)) {
+            let mut children = vec![left];
+            self.token(&mut children);
+            children.push(self.binary(level + 1));
+            left = Element::Node(Node {
+                kind: NodeKind::Binary,
+                children,
+            });
+        }
+        left
+    }
+
+    /// unary ::= ["+" | "-"] primary
+    fn unary(&mut self) -> Element {
+        if !(self.check(TokenType::Plus) || self.check(TokenType::Minus)) {
+            return self.primary();
+        }
+        let mut children = Vec::new();
+        self.token(&mut children);
+        children.push(self.primary());
+        Element::Node(Node {
+            kind: NodeKind::Unary,
+            children,
+        })
+    }
+
+    /// primary ::= number | ident
+    fn primary(&mut self) -> Element {
+        let mut children = Vec::new();
+        self.token(&mut children);
+        Element::Node(Node {
+            kind: NodeKind::Primary,
+            children,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_reproduces_the_source() {
+        for source in [
+            "",
+            "PRINT 1",
+            "\n\n  PRINT   \"hi\"  # trailing comment\n",
+            "\
+## Doc comment
+LET  n=0 #[ block #[ nested ]# ]#
+WHILE n<3 REPEAT
+  IF n == 1 THEN
+        PRINT n
+  ENDIF
+    LET n = n + -1.5 * 2
+ENDWHILE
+
+LABEL  end   # no newline at the end",
+            "INPUT x\nGOTO done\nLABEL done\n",
+        ] {
+            assert_eq!(parse(source).unwrap().to_string(), source);
+        }
+    }
+}
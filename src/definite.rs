@@ -0,0 +1,154 @@
+use super::ast::{Expression, ExpressionKind, Printable, StatementKind};
+use super::cfg::{BlockId, Cfg, Terminator};
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+use std::collections::{HashSet, VecDeque};
+
+/// Definite-assignment analysis.
+/// Reports every read of a variable that some path from the start of the program reaches
+/// without passing an assignment to it, along with one such path.
+pub fn check(cfg: &Cfg) -> Vec<Diagnostic> {
+    let predecessors = cfg.predecessors();
+
+    // Variables assigned on every path into each block. `None` until a path reaching the block is
+    // found, which also means blocks that can never run are not checked at all.
+    let mut assigned_in: Vec<Option<HashSet<String>>> = vec![None; cfg.blocks.len()];
+    assigned_in[cfg.entry] = Some(HashSet::new());
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for block in 0..cfg.blocks.len() {
+            if block == cfg.entry {
+                continue;
+            }
+
+            let mut incoming: Option<HashSet<String>> = None;
+            for &predecessor in &predecessors[block] {
+                if let Some(assigned) = &assigned_in[predecessor] {
+                    let mut out = assigned.clone();
+                    out.extend(assigned_by(cfg, predecessor));
+                    incoming = Some(match incoming {
+                        Some(incoming) => incoming.intersection(&out).cloned().collect(),
+                        None => out,
+                    });
+                }
+            }
+
+            if incoming.is_some() && incoming != assigned_in[block] {
+                assigned_in[block] = incoming;
+                changed = true;
+            }
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    for (block, assigned) in assigned_in.iter().enumerate() {
+        let Some(assigned) = assigned else { continue };
+        let mut assigned = assigned.clone();
+        let mut reads = Vec::new();
+
+        for statement in &cfg.blocks[block].statements {
+            match &statement.kind {
+                StatementKind::Print(Printable::Expression(expression)) => {
+                    unassigned_reads(expression, &assigned, &mut reads);
+                }
+                StatementKind::Let { name, value } => {
+                    unassigned_reads(value, &assigned, &mut reads);
+                    assigned.insert(name.name.clone());
+                }
+                StatementKind::Input(name) => {
+                    assigned.insert(name.name.clone());
+                }
+                _ => {}
+            }
+        }
+
+        if let Terminator::Branch { condition, .. } = &cfg.blocks[block].terminator {
+            unassigned_reads(condition, &assigned, &mut reads);
+        }
+
+        for (name, span) in reads {
+            let mut diagnostic = Diagnostic::error(&format!("Variable {} is possibly uninitialized", name), span);
+            for (message, span) in unassigned_path(cfg, &name, block) {
+                diagnostic = diagnostic.with_label(span, message);
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// Return the variables the statements of a block assign
+fn assigned_by(cfg: &Cfg, block: BlockId) -> impl Iterator<Item = String> + '_ {
+    cfg.blocks[block]
+        .statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Let { name, .. } | StatementKind::Input(name) => Some(name.name.clone()),
+            _ => None,
+        })
+}
+
+/// Collect the variables read by the expression that aren't in the assigned set
+fn unassigned_reads(expression: &Expression, assigned: &HashSet<String>, reads: &mut Vec<(String, Span)>) {
+    match &expression.kind {
+        ExpressionKind::Number(_) => {}
+        ExpressionKind::Ident(name) => {
+            if !assigned.contains(name) {
+                reads.push((name.clone(), expression.span));
+            }
+        }
+        ExpressionKind::Unary(_, operand) => unassigned_reads(operand, assigned, reads),
+        ExpressionKind::Binary(_, left, right) => {
+            unassigned_reads(left, assigned, reads);
+            unassigned_reads(right, assigned, reads);
+        }
+    }
+}
+
+/// Find a shortest path from the entry to the block that never assigns the variable,
+/// described by the branches taken and GOTOs followed along the way.
+fn unassigned_path(cfg: &Cfg, name: &str, target: BlockId) -> Vec<(&'static str, Span)> {
+    let mut came_from: Vec<Option<BlockId>> = vec![None; cfg.blocks.len()];
+    let mut queue = VecDeque::from([cfg.entry]);
+    let mut visited = HashSet::from([cfg.entry]);
+
+    while let Some(block) = queue.pop_front() {
+        if block == target || assigned_by(cfg, block).any(|assigned| assigned == name) {
+            continue;
+        }
+
+        for successor in cfg.blocks[block].terminator.successors() {
+            if visited.insert(successor) {
+                came_from[successor] = Some(block);
+                queue.push_back(successor);
+            }
+        }
+    }
+
+    let mut path = Vec::new();
+    let mut block = target;
+    while let Some(previous) = came_from[block] {
+        match &cfg.blocks[previous].terminator {
+            Terminator::Goto { span, .. } => path.push(("jumping from here", *span)),
+            Terminator::Branch {
+                condition, then_block, ..
+            } => {
+                if *then_block == block {
+                    path.push(("when this condition is true", condition.span));
+                } else {
+                    path.push(("when this condition is false", condition.span));
+                }
+            }
+            _ => {}
+        }
+        block = previous;
+    }
+
+    path.reverse();
+    path
+}
@@ -0,0 +1,127 @@
+use super::span::{line_col, Span};
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A secondary location attached to a diagnostic, e.g. where a duplicate label was first declared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An error or warning about the program, pointing at the source it concerns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Warnings have a code, such as `W001`, which can be used to turn them off
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: &str, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: None,
+            message: message.to_owned(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: &str, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code: Some(code),
+            ..Self::error(message, span)
+        }
+    }
+
+    /// Attach a secondary label pointing at another part of the source
+    pub fn with_label(mut self, span: Span, message: &str) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.to_owned(),
+        });
+        self
+    }
+
+    /// Attach a note printed after the source lines
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_owned());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render the diagnostic with the source lines it points at, for printing to a terminal.
+    ///
+    /// ```text
+    /// error: Label already exists: top
+    ///  --> loop.teeny:7:7
+    ///   |
+    /// 7 | LABEL top
+    ///   |       ^^^
+    /// 1 | LABEL top
+    ///   |       --- first declared here
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let severity = match (self.severity, self.code) {
+            (Severity::Error, None) => "error".to_owned(),
+            (Severity::Error, Some(code)) => format!("error[{}]", code),
+            (Severity::Warning, None) => "warning".to_owned(),
+            (Severity::Warning, Some(code)) => format!("warning[{}]", code),
+        };
+
+        let (line, col) = line_col(source, self.span.start);
+        let last_line = self
+            .labels
+            .iter()
+            .map(|label| line_col(source, label.span.start).0)
+            .fold(line, usize::max);
+        let gutter = " ".repeat(last_line.to_string().len());
+
+        let mut out = String::new();
+        writeln!(out, "{}: {}", severity, self.message).unwrap();
+        writeln!(out, "{}--> {}:{}:{}", gutter, file_name, line, col).unwrap();
+        writeln!(out, "{} |", gutter).unwrap();
+        snippet(&mut out, source, &gutter, self.span, '^', "");
+        for label in &self.labels {
+            snippet(&mut out, source, &gutter, label.span, '-', &label.message);
+        }
+        for note in &self.notes {
+            writeln!(out, "{} = note: {}", gutter, note).unwrap();
+        }
+        out
+    }
+}
+
+/// Write the line containing the span, underlined with the marker character
+fn snippet(out: &mut String, source: &str, gutter: &str, span: Span, marker: char, message: &str) {
+    let (line, col) = line_col(source, span.start);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+
+    // Spans of multi-line statements are only underlined up to the end of their first line
+    let width = (span.end - span.start)
+        .min((text.chars().count() + 1).saturating_sub(col))
+        .max(1);
+
+    writeln!(out, "{:>width$} | {}", line, text, width = gutter.len()).unwrap();
+    let underline = format!("{}{}", " ".repeat(col - 1), marker.to_string().repeat(width));
+    if message.is_empty() {
+        writeln!(out, "{} | {}", gutter, underline).unwrap();
+    } else {
+        writeln!(out, "{} | {} {}", gutter, underline, message).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use super::cli::{self, Options};
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use teeny::ast::Program;
+use teeny::codegen::CodeGen;
+use teeny::emitter::{Emitter, Section};
+use teeny::sourcemap::SourceMap;
+
+/// A directory for intermediate files, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create the directory, or return the exit status to fail with
+    fn new() -> Result<Self, i32> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("teeny-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        if let Err(why) = fs::create_dir_all(&path) {
+            eprintln!("error: couldn't create {}: {}", path.display(), why);
+            return Err(cli::EXIT_IO);
+        }
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Compile the program to an executable with the system C compiler, named after the teeny file
+/// unless the options name it. Returns the exit status to fail with if the C compiler can't be
+/// run or fails.
+pub fn build(program: &Program, source: &str, options: &Options) -> Result<(), i32> {
+    let executable = match &options.output {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(stem(options)),
+    };
+    compile(program, source, options, &TempDir::new()?, &executable)
+}
+
+/// Build the program with the system C compiler, run it, and return its exit status.
+/// A program killed by a signal exits with 128 plus the signal's number, as shells report it.
+pub fn run(program: &Program, source: &str, options: &Options) -> i32 {
+    let dir = match TempDir::new() {
+        Ok(dir) => dir,
+        Err(code) => return code,
+    };
+    let executable = dir.0.join(stem(options));
+    let code =
+        compile(program, source, options, &dir, &executable).and_then(|_| match Command::new(&executable).status() {
+            Ok(status) => Ok(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))),
+            Err(why) => {
+                eprintln!("error: couldn't run {}: {}", executable.display(), why);
+                Err(cli::EXIT_IO)
+            }
+        });
+    code.unwrap_or_else(|code| code)
+}
+
+/// Generate C for the program in the directory and compile it to the executable with `$CC`, or
+/// `cc` if that isn't set, passing the flags in `$CFLAGS` and then the options'. The compiler's
+/// messages are printed pointing at the teeny source. Returns the exit status to fail with.
+fn compile(program: &Program, source: &str, options: &Options, dir: &TempDir, executable: &Path) -> Result<(), i32> {
+    let c_path = dir.0.join(format!("{}.c", stem(options)));
+    let file = match File::create(&c_path) {
+        Ok(file) => file,
+        Err(why) => {
+            eprintln!("error: couldn't write {}: {}", c_path.display(), why);
+            return Err(cli::EXIT_IO);
+        }
+    };
+
+    let mut emitter = Emitter::new(BufWriter::new(file));
+    let mut codegen = CodeGen::new(&mut emitter);
+    if let Some(file_name) = &options.compile.line_directives {
+        codegen = codegen.with_line_directives(file_name, source);
+    }
+    codegen.program(program);
+    let runs = codegen.line_map;
+    let line_map = LineMap {
+        map: SourceMap::new(emitter.start_of(Section::Main), &runs, source),
+        input_name: options.input_name(),
+    };
+    if let Err(why) = emitter.finish() {
+        eprintln!("error: couldn't write {}: {}", c_path.display(), why);
+        return Err(cli::EXIT_IO);
+    }
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let mut flags: Vec<String> = env::var("CFLAGS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+    flags.extend(options.cc_flags.iter().cloned());
+
+    let output = match Command::new(&cc)
+        .args(&flags)
+        .arg(&c_path)
+        .arg("-o")
+        .arg(executable)
+        .output()
+    {
+        Ok(output) => output,
+        Err(why) => {
+            eprintln!("error: couldn't run the C compiler {}: {}", cc, why);
+            return Err(cli::EXIT_CC);
+        }
+    };
+
+    let c_name = c_path.display().to_string();
+    for messages in [&output.stdout, &output.stderr] {
+        for message in String::from_utf8_lossy(messages).lines() {
+            eprintln!("{}", line_map.message(message, &c_name));
+        }
+    }
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        eprintln!("error: the C compiler {} failed", cc);
+        Err(cli::EXIT_CC)
+    }
+}
+
+/// The file name of the teeny file without its extension, or `out` for standard input
+fn stem(options: &Options) -> String {
+    match Path::new(&options.input).file_stem() {
+        Some(stem) if options.input != "-" => stem.to_string_lossy().into_owned(),
+        _ => "out".to_owned(),
+    }
+}
+
+/// Maps the C compiler's messages back to the teeny statements the C was generated from
+struct LineMap<'a> {
+    map: SourceMap,
+    input_name: &'a str,
+}
+
+impl LineMap<'_> {
+    /// Rewrite a C compiler message starting `file.c:line:column:` to start with the position of
+    /// the teeny statement instead. Lines of C no statement was generated from have no teeny
+    /// position, and anything not naming the C file, like the quoted C source or messages
+    /// already pointed at the teeny file by `#line` directives, is left alone.
+    fn message(&self, message: &str, c_name: &str) -> String {
+        let Some(mut rest) = message.strip_prefix(c_name) else {
+            return message.to_owned();
+        };
+
+        // The C line and column, which are both dropped
+        let mut c_line = None;
+        for part in 0..2 {
+            let Some(number) = rest.strip_prefix(':') else { break };
+            let digits = number.find(|c: char| !c.is_ascii_digit()).unwrap_or(number.len());
+            if digits == 0 {
+                break;
+            }
+            if part == 0 {
+                c_line = number[..digits].parse().ok();
+            }
+            rest = &number[digits..];
+        }
+
+        match c_line.and_then(|c_line| self.map.find(c_line)) {
+            Some(mapping) => format!(
+                "{}:{}:{}{}",
+                self.input_name, mapping.source_line, mapping.source_column, rest
+            ),
+            None => format!("{}{}", self.input_name, rest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teeny::span::Span;
+
+    #[test]
+    fn compiler_messages_point_at_the_teeny_source() {
+        let source = "LET a = 1\n  PRINT a / 0\n";
+        // The C lines 1 and 2 come from the statements, and line 3 from neither
+        let runs = [(0, Some(Span::new(0, 9))), (1, Some(Span::new(12, 23))), (2, None)];
+        let line_map = LineMap {
+            map: SourceMap::new(0, &runs, source),
+            input_name: "div.teeny",
+        };
+
+        assert_eq!(
+            line_map.message("/tmp/div.c:2:21: warning: division by zero", "/tmp/div.c"),
+            "div.teeny:2:3: warning: division by zero"
+        );
+        assert_eq!(
+            line_map.message("/tmp/div.c:3:1: error: expected ';'", "/tmp/div.c"),
+            "div.teeny: error: expected ';'"
+        );
+        assert_eq!(
+            line_map.message("/tmp/div.c: In function 'main':", "/tmp/div.c"),
+            "div.teeny: In function 'main':"
+        );
+        assert_eq!(
+            line_map.message("    2 |     printf(\"%.2f\\n\", (float)(a / 0.0));", "/tmp/div.c"),
+            "    2 |     printf(\"%.2f\\n\", (float)(a / 0.0));"
+        );
+    }
+
+    #[test]
+    fn temporary_directories_are_removed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.0.clone();
+        fs::write(path.join("out.c"), "int main(void) { return 0; }\n").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,67 @@
+/// Where the executable is loaded
+const BASE: u64 = 0x40_0000;
+pub const PAGE_SIZE: usize = 0x1000;
+/// The ELF header and the two program headers, which come before the image in the file
+pub const HEADERS_SIZE: usize = 64 + 2 * 56;
+
+/// Wrap an image of code and data into a statically linked x86-64 Linux executable.
+///
+/// The image starts with the entry point. Everything before `writable` is mapped readable and
+/// executable, and everything after it readable and writable, so `writable` plus the headers
+/// must be a multiple of the page size.
+pub fn executable(image: &[u8], writable: usize) -> Vec<u8> {
+    debug_assert_eq!((HEADERS_SIZE + writable) % PAGE_SIZE, 0);
+
+    let text_size = (HEADERS_SIZE + writable) as u64;
+    let data_size = (image.len() - writable) as u64;
+    let mut file = Vec::new();
+
+    // ELF header: 64 bit, little endian, current version, System V ABI
+    file.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    file.extend_from_slice(&[0; 8]);
+    u16(&mut file, 2); // An executable
+    u16(&mut file, 0x3e); // x86-64
+    u32(&mut file, 1);
+    u64(&mut file, BASE + HEADERS_SIZE as u64); // Entry point
+    u64(&mut file, 64); // Program headers, right after this header
+    u64(&mut file, 0); // No section headers
+    u32(&mut file, 0);
+    u16(&mut file, 64);
+    u16(&mut file, 56);
+    u16(&mut file, 2);
+    u16(&mut file, 64);
+    u16(&mut file, 0);
+    u16(&mut file, 0);
+
+    // The headers and the code, readable and executable
+    segment(&mut file, 0b101, 0, text_size);
+    // The data, readable and writable
+    segment(&mut file, 0b110, text_size, data_size);
+
+    file.extend_from_slice(image);
+    file
+}
+
+/// Write a program header loading part of the file at the same offset from the base address
+fn segment(file: &mut Vec<u8>, flags: u32, offset: u64, size: u64) {
+    u32(file, 1); // Loadable
+    u32(file, flags);
+    u64(file, offset);
+    u64(file, BASE + offset);
+    u64(file, BASE + offset);
+    u64(file, size);
+    u64(file, size);
+    u64(file, PAGE_SIZE as u64);
+}
+
+fn u16(file: &mut Vec<u8>, value: u16) {
+    file.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u32(file: &mut Vec<u8>, value: u32) {
+    file.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u64(file: &mut Vec<u8>, value: u64) {
+    file.extend_from_slice(&value.to_le_bytes());
+}
@@ -1,38 +1,186 @@
-use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 
+/// The parts of a generated program, in the order they're written out. Code can be added to any
+/// of them at any time, so a variable first seen deep inside a loop can still be declared first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    /// `#include`s and imports
+    Includes,
+    /// Global variables and constants
+    Globals,
+    /// Helper functions
+    Functions,
+    /// The start of the main function, where its variables are declared
+    Declarations,
+    /// The rest of the main function
+    Main,
+}
+
+impl Section {
+    const ALL: [Section; 5] = [
+        Section::Includes,
+        Section::Globals,
+        Section::Functions,
+        Section::Declarations,
+        Section::Main,
+    ];
+}
+
+/// The text of a section so far
+#[derive(Debug, Default)]
+struct Buffer {
+    text: String,
+    lines: usize,
+    indent: usize,
+}
+
+/// Collects generated code section by section, indenting each line to its section's current level,
+/// and writes the sections out in order to any `io::Write` sink.
 #[derive(Debug)]
-pub struct Emitter {
-    pub full_path: String,
-    pub header: String,
-    pub code: String,
+pub struct Emitter<W: Write> {
+    sink: W,
+    sections: [Buffer; 5],
+    /// Where `emit` and `emit_line` add code
+    current: Section,
 }
 
-impl Emitter {
-    pub fn new(full_path: &str) -> Self {
+impl<W: Write> Emitter<W> {
+    pub fn new(sink: W) -> Self {
         Self {
-            full_path: full_path.to_owned(),
-            header: "".to_owned(),
-            code: "".to_owned(),
+            sink,
+            sections: Default::default(),
+            current: Section::Main,
         }
     }
 
+    /// Add code from now on to another section
+    pub fn set_section(&mut self, section: Section) {
+        self.current = section;
+    }
+
     pub fn emit(&mut self, code: &str) {
-        self.code = format!("{}{}", self.code, code)
+        self.append(self.current, code);
     }
 
     pub fn emit_line(&mut self, code: &str) {
-        self.code = format!("{}{}\n", self.code, code)
+        self.line_to(self.current, code);
+    }
+
+    /// Add a line to a section other than the current one
+    pub fn line_to(&mut self, section: Section, code: &str) {
+        self.append(section, code);
+        self.append(section, "\n");
+    }
+
+    /// Start the main function with its opening line, and add code from now on to its body.
+    /// Its declarations and its body are indented a level.
+    pub fn begin_main(&mut self, opening: &str) {
+        self.line_to(Section::Declarations, opening);
+        self.buffer(Section::Declarations).indent += 1;
+        self.set_section(Section::Main);
+        self.indent();
+    }
+
+    /// Indent the lines added to the current section after this one level more
+    pub fn indent(&mut self) {
+        self.buffer(self.current).indent += 1;
+    }
+
+    pub fn dedent(&mut self) {
+        let buffer = self.buffer(self.current);
+        buffer.indent = buffer.indent.checked_sub(1).expect("dedented past the margin");
+    }
+
+    /// The number of lines in the current section so far, which is the line the next one starts
+    /// on counting from the start of the section
+    pub fn line(&self) -> usize {
+        self.sections[self.current as usize].lines
+    }
+
+    /// The line a section starts on in the output, counting from 0
+    pub fn start_of(&self, section: Section) -> usize {
+        self.sections[..section as usize]
+            .iter()
+            .map(|buffer| buffer.lines)
+            .sum()
+    }
+
+    /// Write the sections out in order, returning the sink
+    pub fn finish(mut self) -> io::Result<W> {
+        for section in Section::ALL {
+            self.sink.write_all(self.sections[section as usize].text.as_bytes())?;
+        }
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+
+    fn buffer(&mut self, section: Section) -> &mut Buffer {
+        &mut self.sections[section as usize]
     }
 
-    pub fn header_line(&mut self, code: &str) {
-        self.header = format!("{}{}\n", self.header, code)
+    /// Append code to a section, indenting it if it starts a line
+    fn append(&mut self, section: Section, code: &str) {
+        let buffer = self.buffer(section);
+        let at_line_start = buffer.text.is_empty() || buffer.text.ends_with('\n');
+        if at_line_start && !code.is_empty() && code != "\n" {
+            buffer.text.push_str(&"    ".repeat(buffer.indent));
+        }
+        buffer.text.push_str(code);
+        buffer.lines += code.matches('\n').count();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_are_written_in_order_and_indented() {
+        let mut emitter = Emitter::new(Vec::new());
+        emitter.line_to(Section::Includes, "#include <stdio.h>");
+        emitter.begin_main("int main(void) {");
+        emitter.emit_line("while(x){");
+        emitter.indent();
+        // A variable first seen inside the loop is still declared at the top of main
+        emitter.line_to(Section::Declarations, "float x = 0;");
+        emitter.emit("x = ");
+        emitter.emit("x - 1;\n");
+        emitter.dedent();
+        emitter.emit_line("}");
+
+        assert_eq!(emitter.line(), 3);
+        assert_eq!(emitter.start_of(Section::Main), 3);
+        assert_eq!(
+            String::from_utf8(emitter.finish().unwrap()).unwrap(),
+            "\
+#include <stdio.h>
+int main(void) {
+    float x = 0;
+    while(x){
+        x = x - 1;
+    }
+"
+        );
+    }
+
+    /// A sink that fails every write
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
-    pub fn write_file(&self) {
-        let mut output_file = File::create(&self.full_path).unwrap();
-        output_file.write_all(self.header.as_bytes()).unwrap();
-        output_file.write_all(self.code.as_bytes()).unwrap();
-        output_file.sync_all().unwrap();
+    #[test]
+    fn write_errors_are_returned() {
+        let mut emitter = Emitter::new(Broken);
+        emitter.emit_line("print(1)");
+        assert_eq!(emitter.finish().unwrap_err().to_string(), "broken");
     }
 }
@@ -0,0 +1,466 @@
+use super::elf;
+use super::runtime::Runtime;
+use super::x86::{Cond, Gpr, MInst, Program, SseOp, Xmm};
+use std::collections::HashMap;
+
+/// A position in the code, bound once its address is known
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// A memory operand
+#[derive(Copy, Clone, Debug)]
+pub enum Mem {
+    /// A register plus a displacement
+    Base(Gpr, i32),
+    /// A label, addressed relative to the instruction pointer
+    Rip(Label),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Alu {
+    Add,
+    Or,
+    And,
+    Sub,
+    Cmp,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Shift {
+    Left,
+    Right,
+}
+
+/// Encodes x86-64 instructions into a single image of code and data, addressing everything
+/// relative to the instruction pointer so the image runs wherever it's loaded
+#[derive(Debug, Default)]
+pub struct Assembler {
+    pub code: Vec<u8>,
+    /// The offset each label is bound to
+    labels: Vec<Option<usize>>,
+    /// 32 bit displacements to fill in once their label is bound: where the displacement is,
+    /// the label and how many bytes of the instruction follow the displacement
+    fixups: Vec<(usize, Label, usize)>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Bind a label to the current position
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Pad with zeroes up to the offset
+    pub fn pad_to(&mut self, offset: usize) {
+        self.code.resize(offset, 0);
+    }
+
+    /// Resolve every reference to a label and return the image
+    pub fn finish(mut self) -> Vec<u8> {
+        for (position, label, trailing) in self.fixups {
+            let target = self.labels[label.0].expect("label never bound");
+            let displacement = target as i64 - (position + 4 + trailing) as i64;
+            self.code[position..position + 4].copy_from_slice(&(displacement as i32).to_le_bytes());
+        }
+        self.code
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | (rm >> 3);
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    /// Emit an instruction with a register operand in ModRM.reg and another in ModRM.rm
+    fn op_rr(&mut self, prefix: Option<u8>, wide: bool, opcode: &[u8], reg: u8, rm: u8) {
+        self.code.extend(prefix);
+        self.rex(wide, reg, rm);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0xc0 | (reg & 7) << 3 | (rm & 7));
+    }
+
+    /// Emit an instruction with a register operand, or an opcode extension, in ModRM.reg and a
+    /// memory operand in ModRM.rm. `trailing` is the size of any immediate after the displacement.
+    fn op_rm(&mut self, prefix: Option<u8>, wide: bool, opcode: &[u8], reg: u8, mem: Mem, trailing: usize) {
+        self.code.extend(prefix);
+        match mem {
+            Mem::Base(base, displacement) => {
+                let base = base.number();
+                self.rex(wide, reg, base);
+                self.code.extend_from_slice(opcode);
+                // Always a 32 bit displacement, which also covers rbp and r13 needing one
+                self.code.push(0x80 | (reg & 7) << 3 | (base & 7));
+                if base & 7 == 4 {
+                    // rsp and r12 as a base need a SIB byte
+                    self.code.push(0x24);
+                }
+                self.code.extend_from_slice(&displacement.to_le_bytes());
+            }
+            Mem::Rip(label) => {
+                self.rex(wide, reg, 0);
+                self.code.extend_from_slice(opcode);
+                self.code.push((reg & 7) << 3 | 0b101);
+                self.fixups.push((self.code.len(), label, trailing));
+                self.code.extend_from_slice(&[0; 4]);
+            }
+        }
+    }
+
+    fn rel32(&mut self, opcode: &[u8], target: Label) {
+        self.code.extend_from_slice(opcode);
+        self.fixups.push((self.code.len(), target, 0));
+        self.code.extend_from_slice(&[0; 4]);
+    }
+
+    pub fn push(&mut self, reg: Gpr) {
+        self.rex(false, 0, reg.number());
+        self.code.push(0x50 | (reg.number() & 7));
+    }
+
+    pub fn pop(&mut self, reg: Gpr) {
+        self.rex(false, 0, reg.number());
+        self.code.push(0x58 | (reg.number() & 7));
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn leave(&mut self) {
+        self.code.push(0xc9);
+    }
+
+    pub fn syscall(&mut self) {
+        self.code.extend_from_slice(&[0x0f, 0x05]);
+    }
+
+    pub fn call(&mut self, target: Label) {
+        self.rel32(&[0xe8], target);
+    }
+
+    pub fn jmp(&mut self, target: Label) {
+        self.rel32(&[0xe9], target);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, target: Label) {
+        self.rel32(&[0x0f, 0x80 | cond.number()], target);
+    }
+
+    pub fn mov(&mut self, dst: Gpr, src: Gpr) {
+        self.op_rr(None, true, &[0x89], src.number(), dst.number());
+    }
+
+    /// Load an immediate, with the shortest encoding that fits it
+    pub fn mov_imm(&mut self, dst: Gpr, imm: u64) {
+        self.rex(imm > u32::MAX as u64, 0, dst.number());
+        self.code.push(0xb8 | (dst.number() & 7));
+        if imm > u32::MAX as u64 {
+            self.code.extend_from_slice(&imm.to_le_bytes());
+        } else {
+            self.code.extend_from_slice(&(imm as u32).to_le_bytes());
+        }
+    }
+
+    pub fn load(&mut self, dst: Gpr, src: Mem) {
+        self.op_rm(None, true, &[0x8b], dst.number(), src, 0);
+    }
+
+    pub fn store(&mut self, dst: Mem, src: Gpr) {
+        self.op_rm(None, true, &[0x89], src.number(), dst, 0);
+    }
+
+    /// Load a byte, zero extended
+    pub fn load_byte(&mut self, dst: Gpr, src: Mem) {
+        self.op_rm(None, false, &[0x0f, 0xb6], dst.number(), src, 0);
+    }
+
+    /// Store the low byte of al, cl, dl or bl. The other registers' low bytes need a REX prefix.
+    pub fn store_byte(&mut self, dst: Mem, src: Gpr) {
+        debug_assert!(src.number() < 4);
+        self.op_rm(None, false, &[0x88], src.number(), dst, 0);
+    }
+
+    pub fn store_byte_imm(&mut self, dst: Mem, imm: u8) {
+        self.op_rm(None, false, &[0xc6], 0, dst, 1);
+        self.code.push(imm);
+    }
+
+    pub fn lea(&mut self, dst: Gpr, src: Mem) {
+        self.op_rm(None, true, &[0x8d], dst.number(), src, 0);
+    }
+
+    pub fn alu(&mut self, op: Alu, dst: Gpr, src: Gpr) {
+        let opcode = match op {
+            Alu::Add => 0x01,
+            Alu::Or => 0x09,
+            Alu::And => 0x21,
+            Alu::Sub => 0x29,
+            Alu::Cmp => 0x39,
+        };
+        self.op_rr(None, true, &[opcode], src.number(), dst.number());
+    }
+
+    /// An operation with a 32 bit immediate, sign extended to 64 bits
+    pub fn alu_imm(&mut self, op: Alu, dst: Gpr, imm: i32) {
+        let extension = match op {
+            Alu::Add => 0,
+            Alu::Or => 1,
+            Alu::And => 4,
+            Alu::Sub => 5,
+            Alu::Cmp => 7,
+        };
+        self.op_rr(None, true, &[0x81], extension, dst.number());
+        self.code.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    /// `xor r32, r32`, which clears the whole register
+    pub fn zero(&mut self, reg: Gpr) {
+        self.op_rr(None, false, &[0x31], reg.number(), reg.number());
+    }
+
+    pub fn imul(&mut self, dst: Gpr, src: Gpr) {
+        self.op_rr(None, true, &[0x0f, 0xaf], dst.number(), src.number());
+    }
+
+    /// Divide rdx:rax by the register as unsigned numbers, leaving the quotient in rax and the remainder in rdx
+    pub fn div(&mut self, src: Gpr) {
+        self.op_rr(None, true, &[0xf7], 6, src.number());
+    }
+
+    /// Multiply rax by the register as unsigned numbers, leaving the 128 bit product in rdx:rax
+    pub fn mul(&mut self, src: Gpr) {
+        self.op_rr(None, true, &[0xf7], 4, src.number());
+    }
+
+    /// Shift by the count in cl
+    pub fn shift_cl(&mut self, shift: Shift, dst: Gpr) {
+        let extension = match shift {
+            Shift::Left => 4,
+            Shift::Right => 5,
+        };
+        self.op_rr(None, true, &[0xd3], extension, dst.number());
+    }
+
+    pub fn shift(&mut self, shift: Shift, dst: Gpr, count: u8) {
+        let extension = match shift {
+            Shift::Left => 4,
+            Shift::Right => 5,
+        };
+        self.op_rr(None, true, &[0xc1], extension, dst.number());
+        self.code.push(count);
+    }
+
+    pub fn sse(&mut self, op: SseOp, dst: Xmm, src: Xmm) {
+        let (prefix, opcode) = match op {
+            SseOp::Add => (0xf2, 0x58),
+            SseOp::Mul => (0xf2, 0x59),
+            SseOp::Sub => (0xf2, 0x5c),
+            SseOp::Div => (0xf2, 0x5e),
+            SseOp::And => (0x66, 0x54),
+            SseOp::Xor => (0x66, 0x57),
+        };
+        self.op_rr(Some(prefix), false, &[0x0f, opcode], dst.0, src.0);
+    }
+
+    pub fn movsd(&mut self, dst: Xmm, src: Xmm) {
+        self.op_rr(Some(0xf2), false, &[0x0f, 0x10], dst.0, src.0);
+    }
+
+    pub fn movsd_load(&mut self, dst: Xmm, src: Mem) {
+        self.op_rm(Some(0xf2), false, &[0x0f, 0x10], dst.0, src, 0);
+    }
+
+    pub fn movsd_store(&mut self, dst: Mem, src: Xmm) {
+        self.op_rm(Some(0xf2), false, &[0x0f, 0x11], src.0, dst, 0);
+    }
+
+    pub fn cmpsd(&mut self, dst: Xmm, src: Xmm, predicate: u8) {
+        self.op_rr(Some(0xf2), false, &[0x0f, 0xc2], dst.0, src.0);
+        self.code.push(predicate);
+    }
+
+    pub fn ucomisd(&mut self, left: Xmm, right: Xmm) {
+        self.op_rr(Some(0x66), false, &[0x0f, 0x2e], left.0, right.0);
+    }
+
+    /// Move the bits of a general purpose register into an SSE register
+    pub fn movq_to_xmm(&mut self, dst: Xmm, src: Gpr) {
+        self.op_rr(Some(0x66), true, &[0x0f, 0x6e], dst.0, src.number());
+    }
+
+    /// Move the bits of an SSE register into a general purpose register
+    pub fn movq_from_xmm(&mut self, dst: Gpr, src: Xmm) {
+        self.op_rr(Some(0x66), true, &[0x0f, 0x7e], src.0, dst.number());
+    }
+
+    /// Convert a signed integer to a double
+    pub fn cvtsi2sd(&mut self, dst: Xmm, src: Gpr) {
+        self.op_rr(Some(0xf2), true, &[0x0f, 0x2a], dst.0, src.number());
+    }
+
+    /// Round a double to single precision
+    pub fn cvtsd2ss(&mut self, dst: Xmm, src: Xmm) {
+        self.op_rr(Some(0xf2), false, &[0x0f, 0x5a], dst.0, src.0);
+    }
+
+    /// Widen a single precision float to a double
+    pub fn cvtss2sd(&mut self, dst: Xmm, src: Xmm) {
+        self.op_rr(Some(0xf3), false, &[0x0f, 0x5a], dst.0, src.0);
+    }
+}
+
+/// Encode a compiled program as a statically linked x86-64 Linux executable,
+/// with the runtime doing input and output through system calls
+pub fn executable(program: &Program) -> Vec<u8> {
+    let mut asm = Assembler::new();
+    let runtime = Runtime::new(&mut asm);
+
+    let main = asm.new_label();
+    runtime.start(&mut asm, main);
+    asm.bind(main);
+    encode_main(&mut asm, &runtime, program);
+    let writable = runtime.data(&mut asm, elf::HEADERS_SIZE);
+
+    elf::executable(&asm.finish(), writable)
+}
+
+/// Encode a compiled program to be loaded at the start of a page and called as a C function,
+/// with the same runtime as executables. Returns the image, which starts with `main`, and where
+/// its writable data starts.
+pub fn loadable(program: &Program) -> (Vec<u8>, usize) {
+    let mut asm = Assembler::new();
+    let runtime = Runtime::new(&mut asm);
+
+    encode_main(&mut asm, &runtime, program);
+    let writable = runtime.data(&mut asm, 0);
+
+    (asm.finish(), writable)
+}
+
+/// Encode `main`, followed by the runtime's routines and the strings the program prints
+fn encode_main(asm: &mut Assembler, runtime: &Runtime, program: &Program) {
+    let strings: Vec<Label> = program.strings.iter().map(|_| asm.new_label()).collect();
+    let mut blocks = HashMap::new();
+    let mut block = |asm: &mut Assembler, block: usize| *blocks.entry(block).or_insert_with(|| asm.new_label());
+
+    for inst in &program.code {
+        match *inst {
+            MInst::Label(label) => {
+                let label = block(asm, label);
+                asm.bind(label);
+            }
+            MInst::Push(reg) => asm.push(reg),
+            MInst::MovGpr { dst, src } => asm.mov(dst, src),
+            MInst::SubImm { dst, imm } => asm.alu_imm(Alu::Sub, dst, imm as i32),
+            MInst::Zero(reg) => asm.zero(reg),
+            MInst::MovImm { dst, imm } => asm.mov_imm(dst, imm),
+            MInst::MovqToXmm { dst, src } => asm.movq_to_xmm(dst, src),
+            MInst::MovsdReg { dst, src } => asm.movsd(dst, src),
+            MInst::MovsdLoad { dst, offset } => asm.movsd_load(dst, Mem::Base(Gpr::Rbp, offset)),
+            MInst::MovsdStore { offset, src } => asm.movsd_store(Mem::Base(Gpr::Rbp, offset), src),
+            MInst::Sse { op, dst, src } => asm.sse(op, dst, src),
+            MInst::Cmpsd { dst, src, predicate } => asm.cmpsd(dst, src, predicate),
+            MInst::Ucomisd { left, right } => asm.ucomisd(left, right),
+            MInst::Cvtsd2ss { dst, src } => asm.cvtsd2ss(dst, src),
+            MInst::Cvtss2sd { dst, src } => asm.cvtss2sd(dst, src),
+            MInst::Jcc { cond, target } => {
+                let target = block(asm, target);
+                asm.jcc(cond, target);
+            }
+            MInst::Jmp(target) => {
+                let target = block(asm, target);
+                asm.jmp(target);
+            }
+            MInst::Leave => asm.leave(),
+            MInst::Ret => asm.ret(),
+            MInst::PrintNumber => asm.call(runtime.print_number),
+            MInst::PrintString(index) => {
+                // Each string is stored with its newline, so it's a single write
+                runtime.write(asm, Mem::Rip(strings[index]), program.strings[index].len() + 1)
+            }
+            MInst::Input => asm.call(runtime.input),
+        }
+    }
+
+    runtime.routines(asm);
+    for (label, string) in strings.iter().zip(&program.strings) {
+        asm.bind(*label);
+        asm.bytes(string.as_bytes());
+        asm.bytes(b"\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_resolve_relative_to_the_next_instruction() {
+        let mut asm = Assembler::new();
+        let (start, end) = (asm.new_label(), asm.new_label());
+        asm.bind(start);
+        asm.jmp(end);
+        asm.ret();
+        asm.bind(end);
+        asm.jmp(start);
+        assert_eq!(
+            asm.finish(),
+            [0xe9, 0x01, 0x00, 0x00, 0x00, 0xc3, 0xe9, 0xf5, 0xff, 0xff, 0xff]
+        );
+    }
+
+    /// Executables print what the interpreter prints, without the C library
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    #[test]
+    fn executables_run_like_the_interpreter() {
+        use crate::{interp, ir, native, parse, Options};
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::{Command, Stdio};
+        use std::{env, fs};
+
+        let source = "\
+PRINT \"sum\"
+INPUT a
+INPUT b
+PRINT a + b
+PRINT a / 3
+INPUT c
+PRINT c
+";
+        let input = "1.5\n-2.25\nten\n";
+        let function = ir(&parse(source).unwrap(), &Options::default());
+        let mut expected = Vec::new();
+        interp::run(&function, &mut input.as_bytes(), &mut expected).unwrap();
+
+        let path = env::temp_dir().join(format!("teeny-encode-{}", std::process::id()));
+        fs::write(&path, executable(&native::compile(&function))).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, expected);
+        assert_eq!(String::from_utf8(expected).unwrap(), "sum\n-0.75\n0.50\n0.00\n");
+    }
+}
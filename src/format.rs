@@ -0,0 +1,104 @@
+use super::lex::Lexer;
+use super::parse::Parser;
+use super::token::{Token, TokenType};
+use super::Diagnostics;
+
+/// How far each level of IF and WHILE bodies is indented
+const INDENT: &str = "    ";
+
+/// Format a program the canonical way: keywords in uppercase, one space between tokens, none
+/// after a sign, IF and WHILE bodies indented four spaces, runs of blank lines collapsed to one, and
+/// no blank lines at the start or end. Comments are kept where they were written. Programs that
+/// don't parse are left alone, and their errors returned.
+pub fn format(source: &str) -> Result<String, Diagnostics> {
+    // Only whole statements are laid out, so the program has to parse. One that only parses with
+    // its keywords in any case has them rewritten; otherwise a word like `print` is a variable.
+    // If it parses neither way, the error found further into the source is the likelier mistake.
+    let any_case = match super::parse(source) {
+        Ok(_) => false,
+        Err(errors) => {
+            match Parser::new(Lexer::new(source).with_any_case_keywords()).and_then(|mut parser| parser.program()) {
+                Ok(_) => true,
+                Err(error) if error.span.start > errors[0].span.start => return Err(vec![error]),
+                Err(_) => return Err(errors),
+            }
+        }
+    };
+
+    let mut lines: Vec<Vec<Token>> = vec![Vec::new()];
+    let mut lexer = Lexer::new(source).with_comments();
+    if any_case {
+        lexer = lexer.with_any_case_keywords();
+    }
+    loop {
+        let token = lexer.get_token().map_err(|error| vec![error])?;
+        match token.kind {
+            TokenType::Newline => lines.push(Vec::new()),
+            TokenType::Eof => break,
+            _ => lines.last_mut().expect("there's always a line").push(token),
+        }
+    }
+
+    let mut formatted = String::new();
+    let mut depth = 0;
+    let mut blank = false;
+    for line in &lines {
+        // Comments written before a statement on its line don't decide its indentation
+        let first = line
+            .iter()
+            .find(|token| token.kind != TokenType::Comment)
+            .map(|token| token.kind);
+        if line.is_empty() {
+            blank = !formatted.is_empty();
+            continue;
+        }
+
+        if matches!(first, Some(TokenType::EndIf | TokenType::EndWhile)) {
+            depth -= 1;
+        }
+        if blank {
+            formatted.push('\n');
+            blank = false;
+        }
+        formatted.push_str(&INDENT.repeat(depth));
+        formatted.push_str(&format_line(line));
+        formatted.push('\n');
+        if matches!(first, Some(TokenType::If | TokenType::While)) {
+            depth += 1;
+        }
+    }
+    Ok(formatted)
+}
+
+/// Write the tokens of a line with a space between each, except after a sign
+fn format_line(tokens: &[Token]) -> String {
+    let mut line = String::new();
+    // A + or - where an operand is expected is the sign of the operand that follows
+    let mut operand_expected = true;
+    let mut after_sign = false;
+
+    for token in tokens {
+        if !line.is_empty() && !after_sign {
+            line.push(' ');
+        }
+        after_sign = false;
+
+        match token.kind {
+            TokenType::String => line.push_str(&format!("\"{}\"", token.text)),
+            TokenType::Comment => line.push_str(token.text.trim_end()),
+            TokenType::Plus | TokenType::Minus if operand_expected => {
+                line.push_str(&token.text);
+                after_sign = true;
+            }
+            // Keywords are recognized in any case only when they're to be rewritten
+            _ if Token::check_if_keyword(&token.text.to_uppercase()) == token.kind => {
+                line.push_str(&token.text.to_uppercase())
+            }
+            _ => line.push_str(&token.text),
+        }
+        if token.kind != TokenType::Comment {
+            operand_expected = !matches!(token.kind, TokenType::Number | TokenType::Ident);
+        }
+    }
+    line
+}
@@ -0,0 +1,191 @@
+use super::ir::{Function, Inst, Terminator};
+use std::io::{self, BufRead, Write};
+
+/// Run a function in SSA form, reading INPUT from `input` and printing to `output`
+pub fn run(function: &Function, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let mut values = vec![0.0; function.value_count];
+    let mut block = 0;
+    let mut previous = None;
+
+    loop {
+        let insts = &function.blocks[block].insts;
+
+        // The phis at the start of a block all read their arguments before any of them is written
+        let phis: Vec<_> = insts
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::Phi { dst, args } => {
+                    let (_, value) = args
+                        .iter()
+                        .find(|(predecessor, _)| Some(*predecessor) == previous)
+                        .expect("phi has no argument for the predecessor");
+                    Some((*dst, values[*value]))
+                }
+                _ => None,
+            })
+            .collect();
+        for (dst, value) in phis {
+            values[dst] = value;
+        }
+
+        for inst in insts {
+            match inst {
+                Inst::Const { dst, value } => values[*dst] = *value,
+                Inst::Copy { dst, src } => values[*dst] = values[*src],
+                Inst::Neg { dst, src } => values[*dst] = -values[*src],
+                Inst::Binary { dst, op, left, right } => values[*dst] = op.apply(values[*left], values[*right]),
+                Inst::Round { dst, src } => values[*dst] = values[*src] as f32 as f64,
+                Inst::Phi { .. } => {}
+                Inst::Print { src } => writeln!(output, "{}", format_number(values[*src]))?,
+                Inst::PrintStr { text } => writeln!(output, "{}", text)?,
+                Inst::Input { dst } => {
+                    // Whatever was printed should be seen before waiting for input
+                    output.flush()?;
+                    values[*dst] = read_number(input)?;
+                }
+                Inst::Load { .. } | Inst::Store { .. } => unreachable!("the function is in SSA form"),
+            }
+        }
+
+        previous = Some(block);
+        block = match function.blocks[block].terminator {
+            Terminator::Jump(target) => target,
+            // Anything but zero is true, including NaN
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                if values[cond] != 0.0 {
+                    then_block
+                } else {
+                    else_block
+                }
+            }
+            Terminator::Return => return output.flush(),
+        };
+    }
+}
+
+/// Format a number the way `printf("%.2f")` does
+pub fn format_number(value: f64) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        format!("{}nan", sign)
+    } else if value.is_infinite() {
+        format!("{}inf", sign)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+/// Read a number the way `scanf("%lf")` does. If the input isn't a number the word is skipped
+/// and the number read is zero, as it is at the end of the input.
+pub fn read_number(input: &mut impl BufRead) -> io::Result<f64> {
+    while peek(input)?.is_some_and(|byte| byte.is_ascii_whitespace()) {
+        input.consume(1);
+    }
+
+    let negative = match peek(input)? {
+        Some(b'-') => {
+            input.consume(1);
+            true
+        }
+        Some(b'+') => {
+            input.consume(1);
+            false
+        }
+        _ => false,
+    };
+    let whole = digits(input)?;
+    let mut fraction = String::new();
+    if peek(input)? == Some(b'.') {
+        input.consume(1);
+        fraction = digits(input)?;
+    }
+
+    if whole.is_empty() && fraction.is_empty() {
+        while peek(input)?.is_some_and(|byte| !byte.is_ascii_whitespace()) {
+            input.consume(1);
+        }
+        return Ok(0.0);
+    }
+
+    let mut exponent = String::new();
+    if matches!(peek(input)?, Some(b'e' | b'E')) {
+        input.consume(1);
+        if let Some(sign @ (b'-' | b'+')) = peek(input)? {
+            input.consume(1);
+            exponent.push(sign as char);
+        }
+        exponent.push_str(&digits(input)?);
+    }
+    // An exponent with no digits counts as zero
+    if !exponent.ends_with(|c: char| c.is_ascii_digit()) {
+        exponent = String::from("0");
+    }
+
+    let sign = if negative { "-" } else { "" };
+    let text = format!("{}0{}.{}0e{}", sign, whole, fraction, exponent);
+    Ok(text.parse().expect("the number was checked while reading it"))
+}
+
+fn peek(input: &mut impl BufRead) -> io::Result<Option<u8>> {
+    Ok(input.fill_buf()?.first().copied())
+}
+
+fn digits(input: &mut impl BufRead) -> io::Result<String> {
+    let mut digits = String::new();
+    while let Some(byte) = peek(input)?.filter(u8::is_ascii_digit) {
+        digits.push(byte as char);
+        input.consume(1);
+    }
+    Ok(digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parse, Options};
+
+    fn run_source(source: &str, input: &str) -> String {
+        let function = ir(&parse(source).unwrap(), &Options::default());
+        let mut output = Vec::new();
+        run(&function, &mut input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn loops_through_phis() {
+        let source = "\
+INPUT n
+LET a = 0
+LET b = 1
+WHILE n > 0 REPEAT
+    LET t = a + b
+    LET a = b
+    LET b = t
+    LET n = n - 1
+ENDWHILE
+PRINT a
+";
+        assert_eq!(run_source(source, "10\n"), "55.00\n");
+    }
+
+    #[test]
+    fn numbers_format_like_printf() {
+        assert_eq!(format_number(2.5), "2.50");
+        assert_eq!(format_number(-0.0), "-0.00");
+        assert_eq!(format_number(f64::NAN), "nan");
+        assert_eq!(format_number(-f64::NAN), "-nan");
+        assert_eq!(format_number(f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn numbers_read_like_scanf() {
+        let mut input = "  12.5\n-3e2 +.25 word 7 1.".as_bytes();
+        let numbers: Vec<f64> = (0..7).map(|_| read_number(&mut input).unwrap()).collect();
+        // The word reads as zero, as does the end of the input
+        assert_eq!(numbers, [12.5, -300.0, 0.25, 0.0, 7.0, 1.0, 0.0]);
+    }
+}
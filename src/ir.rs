@@ -0,0 +1,394 @@
+use super::ast::BinaryOp;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A virtual register. Once the function is in SSA form each one is assigned exactly once.
+pub type Value = usize;
+pub type BlockId = usize;
+
+/// A three-address instruction
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: Value,
+        value: f64,
+    },
+    Copy {
+        dst: Value,
+        src: Value,
+    },
+    Neg {
+        dst: Value,
+        src: Value,
+    },
+    Binary {
+        dst: Value,
+        op: BinaryOp,
+        left: Value,
+        right: Value,
+    },
+    /// Round to the nearest single precision float. Teeny variables are C floats, while number
+    /// literals are doubles, so values are rounded wherever C would convert them to a float.
+    Round {
+        dst: Value,
+        src: Value,
+    },
+    /// Pick the value coming from whichever predecessor control arrived from
+    Phi {
+        dst: Value,
+        args: Vec<(BlockId, Value)>,
+    },
+    /// Read a variable. Only present before SSA construction.
+    Load {
+        dst: Value,
+        var: String,
+    },
+    /// Assign a variable. Only present before SSA construction.
+    Store {
+        var: String,
+        src: Value,
+    },
+    Print {
+        src: Value,
+    },
+    PrintStr {
+        text: String,
+    },
+    Input {
+        dst: Value,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Go to the first block if the value is non-zero and to the second otherwise
+    Branch {
+        cond: Value,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+/// A whole teeny program as IR. Block 0 is the entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub blocks: Vec<Block>,
+    /// Number of values allocated so far
+    pub value_count: usize,
+    /// The variable a value holds, if any, so dumps stay readable
+    pub names: HashMap<Value, String>,
+}
+
+impl Inst {
+    /// Return the value the instruction defines, if any
+    pub fn dst(&self) -> Option<Value> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Copy { dst, .. }
+            | Inst::Neg { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Round { dst, .. }
+            | Inst::Phi { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Input { dst } => Some(*dst),
+            Inst::Store { .. } | Inst::Print { .. } | Inst::PrintStr { .. } => None,
+        }
+    }
+
+    /// Return the values the instruction reads
+    pub fn uses(&self) -> Vec<Value> {
+        match self {
+            Inst::Copy { src, .. }
+            | Inst::Neg { src, .. }
+            | Inst::Round { src, .. }
+            | Inst::Store { src, .. }
+            | Inst::Print { src } => vec![*src],
+            Inst::Binary { left, right, .. } => vec![*left, *right],
+            Inst::Phi { args, .. } => args.iter().map(|(_, value)| *value).collect(),
+            Inst::Const { .. } | Inst::Load { .. } | Inst::PrintStr { .. } | Inst::Input { .. } => vec![],
+        }
+    }
+
+    /// Return mutable references to the values the instruction reads
+    pub fn uses_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Inst::Copy { src, .. }
+            | Inst::Neg { src, .. }
+            | Inst::Round { src, .. }
+            | Inst::Store { src, .. }
+            | Inst::Print { src } => vec![src],
+            Inst::Binary { left, right, .. } => vec![left, right],
+            Inst::Phi { args, .. } => args.iter_mut().map(|(_, value)| value).collect(),
+            Inst::Const { .. } | Inst::Load { .. } | Inst::PrintStr { .. } | Inst::Input { .. } => vec![],
+        }
+    }
+
+    /// Return true if removing the instruction would change what the program does
+    /// even when nothing uses its result
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Inst::Store { .. } | Inst::Print { .. } | Inst::PrintStr { .. } | Inst::Input { .. }
+        )
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then_block, else_block, ..
+            } => vec![*then_block, *else_block],
+            Terminator::Return => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_block, else_block, ..
+            } => vec![then_block, else_block],
+            Terminator::Return => vec![],
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Value> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            _ => vec![],
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![cond],
+            _ => vec![],
+        }
+    }
+}
+
+impl Function {
+    /// Allocate a new value
+    pub fn new_value(&mut self) -> Value {
+        self.value_count += 1;
+        self.value_count - 1
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in block.terminator.successors() {
+                predecessors[successor].push(id);
+            }
+        }
+        predecessors
+    }
+
+    /// Return the blocks reachable from the entry in reverse postorder,
+    /// so every block comes before its successors except along back edges
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // Each entry is a block and how many of its successors have been visited
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+
+        while let Some((block, next)) = stack.pop() {
+            let successors = self.blocks[block].terminator.successors();
+            if next < successors.len() {
+                stack.push((block, next + 1));
+                if !visited[successors[next]] {
+                    visited[successors[next]] = true;
+                    stack.push((successors[next], 0));
+                }
+            } else {
+                postorder.push(block);
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+
+    /// Remove blocks that can't be reached from the entry, renumbering the rest.
+    /// Return true if any block was removed.
+    pub fn remove_unreachable_blocks(&mut self) -> bool {
+        let mut reachable = self.reverse_postorder();
+        if reachable.len() == self.blocks.len() {
+            return false;
+        }
+
+        // Keep the surviving blocks in their original order
+        reachable.sort_unstable();
+        let mut new_ids = vec![None; self.blocks.len()];
+        for (new_id, &block) in reachable.iter().enumerate() {
+            new_ids[block] = Some(new_id);
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for (id, mut block) in blocks.into_iter().enumerate() {
+            if new_ids[id].is_none() {
+                continue;
+            }
+
+            for successor in block.terminator.successors_mut() {
+                *successor = new_ids[*successor].unwrap();
+            }
+            for inst in &mut block.insts {
+                if let Inst::Phi { args, .. } = inst {
+                    args.retain(|(predecessor, _)| new_ids[*predecessor].is_some());
+                    for (predecessor, _) in args.iter_mut() {
+                        *predecessor = new_ids[*predecessor].unwrap();
+                    }
+                }
+            }
+            self.blocks.push(block);
+        }
+
+        true
+    }
+
+    /// Return the immediate dominator of every reachable block, with the entry dominating itself.
+    /// Uses the iterative algorithm from Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm".
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let predecessors = self.predecessors();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (index, &block) in order.iter().enumerate() {
+            position[block] = index;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &predecessor in &predecessors[block] {
+                    if idom[predecessor].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(other) => {
+                            // Walk both up the dominator tree until they meet
+                            let (mut a, mut b) = (predecessor, other);
+                            while a != b {
+                                while position[a] > position[b] {
+                                    a = idom[a].unwrap();
+                                }
+                                while position[b] > position[a] {
+                                    b = idom[b].unwrap();
+                                }
+                            }
+                            a
+                        }
+                    });
+                }
+
+                if new_idom != idom[block] {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idom
+    }
+
+    /// Return true if block `a` dominates block `b`, given the immediate dominators
+    pub fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match idom[b] {
+                Some(parent) if parent != b => b = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    fn value_name(&self, value: Value) -> String {
+        match self.names.get(&value) {
+            Some(name) => format!("%{}.{}", name, value),
+            None => format!("%{}", value),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = |value: Value| self.value_name(value);
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", id)?;
+
+            for inst in &block.insts {
+                write!(f, "    ")?;
+                match inst {
+                    Inst::Const { dst, value } => writeln!(f, "{} = const {}", v(*dst), value)?,
+                    Inst::Copy { dst, src } => writeln!(f, "{} = copy {}", v(*dst), v(*src))?,
+                    Inst::Neg { dst, src } => writeln!(f, "{} = neg {}", v(*dst), v(*src))?,
+                    Inst::Binary { dst, op, left, right } => {
+                        writeln!(f, "{} = {} {}, {}", v(*dst), op_name(*op), v(*left), v(*right))?
+                    }
+                    Inst::Round { dst, src } => writeln!(f, "{} = round {}", v(*dst), v(*src))?,
+                    Inst::Phi { dst, args } => {
+                        let args: Vec<_> = args
+                            .iter()
+                            .map(|(block, value)| format!("[b{}: {}]", block, v(*value)))
+                            .collect();
+                        writeln!(f, "{} = phi {}", v(*dst), args.join(", "))?
+                    }
+                    Inst::Load { dst, var } => writeln!(f, "{} = load {}", v(*dst), var)?,
+                    Inst::Store { var, src } => writeln!(f, "store {}, {}", var, v(*src))?,
+                    Inst::Print { src } => writeln!(f, "print {}", v(*src))?,
+                    Inst::PrintStr { text } => writeln!(f, "print \"{}\"", text)?,
+                    Inst::Input { dst } => writeln!(f, "{} = input", v(*dst))?,
+                }
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump b{}", target)?,
+                Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block,
+                } => writeln!(f, "    branch {}, b{}, b{}", v(*cond), then_block, else_block)?,
+                Terminator::Return => writeln!(f, "    return")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The mnemonic of a binary operator in IR dumps
+pub fn op_name(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div",
+        BinaryOp::EqEq => "eq",
+        BinaryOp::NotEq => "ne",
+        BinaryOp::Lt => "lt",
+        BinaryOp::LtEq => "le",
+        BinaryOp::Gt => "gt",
+        BinaryOp::GtEq => "ge",
+    }
+}
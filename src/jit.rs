@@ -0,0 +1,102 @@
+use super::encode;
+use super::ir::Function;
+use super::native;
+
+/// A program compiled to machine code and mapped into this process, ready to run. The code only
+/// ever comes from compiling an IR function, which is why running it is safe.
+pub struct Jit {
+    memory: *mut u8,
+    size: usize,
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const PROT_EXEC: c_int = 4;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+impl Jit {
+    /// Compile a function and encode it into memory of its own, with the code executable and the
+    /// runtime's data writable but never both. Returns None if machine code can't be run here.
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    pub fn new(function: &Function) -> Option<Self> {
+        use std::ptr;
+
+        let (image, writable) = encode::loadable(&native::compile(function));
+        let size = image.len();
+        // SAFETY: a fresh private mapping aliases nothing, and it's only made executable once
+        // the image is copied in
+        unsafe {
+            let memory = sys::mmap(
+                ptr::null_mut(),
+                size,
+                sys::PROT_READ | sys::PROT_WRITE,
+                sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if memory as isize == -1 {
+                return None;
+            }
+            let jit = Self {
+                memory: memory.cast(),
+                size,
+            };
+            ptr::copy_nonoverlapping(image.as_ptr(), jit.memory, size);
+            if sys::mprotect(memory, writable, sys::PROT_READ | sys::PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(jit)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    pub fn new(_function: &Function) -> Option<Self> {
+        None
+    }
+
+    /// Run the program, which reads and writes standard input and output directly rather than
+    /// through the buffers of `std::io`. Returns what main returns.
+    pub fn run(&self) -> i64 {
+        // SAFETY: the image starts with main, which follows the C calling convention, and was
+        // compiled from an IR function, so it only touches the runtime's own data and the
+        // standard input and output
+        let main: extern "C" fn() -> i64 = unsafe { std::mem::transmute(self.memory) };
+        main()
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        // SAFETY: the mapping came from mmap and nothing points into it once it's dropped
+        unsafe {
+            sys::munmap(self.memory.cast(), self.size);
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::{ir, parse, Options};
+
+    #[test]
+    fn runs_in_memory() {
+        // Printing would go straight to the test's standard output, so the program only computes
+        let source = "LET n = 0\nWHILE n < 100 REPEAT\n    LET n = n + 1\nENDWHILE\n";
+        let jit = Jit::new(&ir(&parse(source).unwrap(), &Options::default())).unwrap();
+        assert_eq!(jit.run(), 0);
+    }
+}
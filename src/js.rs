@@ -0,0 +1,397 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, Program, Statement, StatementKind};
+use super::cfg::{Cfg, Terminator};
+use super::emitter::{Emitter, Section};
+use super::optimize::has_label;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Words JavaScript won't take as a variable name. Variables named after them get a `$` in front,
+/// which no teeny identifier can start with.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "Math",
+    "Number",
+    "Object",
+    "BigInt",
+    "print",
+    "input",
+];
+
+/// Helpers for printing and reading numbers like the C program does
+const PRELUDE: &str = r#"// Format a number the way printf("%.2f") does
+function $format(x) {
+    if (Number.isNaN(x)) return "nan";
+    if (!Number.isFinite(x)) return x < 0 ? "-inf" : "inf";
+    if (Object.is(x, -0)) return "-0.00";
+    // toFixed switches to an exponent from 1e21, where every number is whole
+    if (Math.abs(x) >= 1e21) return `${BigInt(x)}.00`;
+    // toFixed rounds ties away from zero but printf rounds them to even, and the only numbers
+    // exactly halfway between hundredths are odd eighths
+    const text = x.toFixed(2);
+    const last = text.slice(-1);
+    const tie = Number.isInteger(x * 8) && !Number.isInteger(x * 4);
+    return tie && "13579".includes(last) ? text.slice(0, -1) + (last - 1) : text;
+}
+
+// Read a number from the start of the text the way scanf("%f") does, or zero if there isn't one
+function $number(text) {
+    const match = /^\s*[+-]?(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?/.exec(text);
+    return match ? Math.fround(Number(match[0])) : 0;
+}
+"#;
+
+/// Walks the AST and writes the equivalent JavaScript module through the emitter.
+///
+/// The module's default export is an async function taking two callbacks: `print`, called with
+/// each line of output, and `input`, which resolves to the text of the next number to read.
+/// Variables are rounded to single precision as the C program's floats are.
+#[derive(Debug)]
+pub struct JsGen<'a, W: Write> {
+    emitter: &'a mut Emitter<W>,
+    declared: HashSet<String>,
+}
+
+impl<'a, W: Write> JsGen<'a, W> {
+    pub fn new(emitter: &'a mut Emitter<W>) -> Self {
+        Self {
+            emitter,
+            declared: HashSet::new(),
+        }
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        self.emitter.line_to(Section::Functions, PRELUDE);
+        self.emitter
+            .begin_main("export default async function main(print = console.log, input = async () => \"\") {");
+
+        // JavaScript has no goto, so a program with labels runs its blocks from a dispatch loop
+        if has_label(&program.statements) {
+            self.dispatch(&Cfg::new(program));
+        } else {
+            for statement in &program.statements {
+                self.statement(statement);
+            }
+        }
+
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Emit a statement other than a LABEL or GOTO
+    pub fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(Printable::String(text)) => self.emitter.emit_line(&format!("print(\"{}\");", text)),
+            StatementKind::Print(Printable::Expression(expression)) => {
+                let value = self.expression(expression).0;
+                self.emitter
+                    .emit_line(&format!("print($format(Math.fround({})));", value));
+            }
+            StatementKind::If { condition, body } => {
+                let condition = self.expression(condition).0;
+                self.block(&format!("if ({})", condition), body);
+            }
+            StatementKind::While { condition, body } => {
+                let condition = self.expression(condition).0;
+                self.block(&format!("while ({})", condition), body);
+            }
+            StatementKind::Let { name, value } => {
+                let name = self.variable(&name.name);
+                let value = self.expression(value).0;
+                self.emitter.emit_line(&format!("{} = Math.fround({});", name, value));
+            }
+            StatementKind::Input(name) => {
+                let name = self.variable(&name.name);
+                self.emitter.emit_line(&format!("{} = $number(await input());", name));
+            }
+            StatementKind::Label { .. } | StatementKind::Goto(_) => {
+                unreachable!("programs with labels are emitted from their control flow graph")
+            }
+        }
+    }
+
+    fn block(&mut self, head: &str, body: &[Statement]) {
+        self.emitter.emit_line(&format!("{} {{", head));
+        self.emitter.indent();
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Emit a loop around a switch with a case per reachable block, falling through where the
+    /// next case is the block that comes next
+    fn dispatch(&mut self, cfg: &Cfg) {
+        let reachable = cfg.reachable();
+        let order: Vec<usize> = (0..cfg.blocks.len()).filter(|&block| reachable[block]).collect();
+
+        self.emitter.emit_line(&format!("let $block = {};", cfg.entry));
+        self.emitter.emit_line("dispatch: for (;;) {");
+        self.emitter.indent();
+        self.emitter.emit_line("switch ($block) {");
+
+        for (index, &id) in order.iter().enumerate() {
+            let block = &cfg.blocks[id];
+            let next = order.get(index + 1).copied();
+            match &block.label {
+                Some(label) => self.emitter.emit_line(&format!("case {}: // {}", id, label)),
+                None => self.emitter.emit_line(&format!("case {}:", id)),
+            }
+            self.emitter.indent();
+
+            for statement in &block.statements {
+                self.statement(statement);
+            }
+            match &block.terminator {
+                Terminator::Jump(target) | Terminator::Goto { target, .. } => {
+                    if Some(*target) != next {
+                        self.emitter.emit_line(&format!("$block = {};", target));
+                        self.emitter.emit_line("continue dispatch;");
+                    }
+                }
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    let condition = self.expression(condition).0;
+                    self.emitter
+                        .emit_line(&format!("$block = {} ? {} : {};", condition, then_block, else_block));
+                    self.emitter.emit_line("continue dispatch;");
+                }
+                Terminator::Return => self.emitter.emit_line("return;"),
+            }
+
+            self.emitter.dedent();
+        }
+
+        self.emitter.emit_line("}");
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Return an expression as JavaScript, along with the precedence of its outermost operator
+    fn expression(&mut self, expression: &Expression) -> (String, u8) {
+        match &expression.kind {
+            ExpressionKind::Number(value) => (format!("{:?}", value), u8::MAX),
+            ExpressionKind::Ident(name) => (self.variable(name), u8::MAX),
+            ExpressionKind::Unary(op, operand) => {
+                let operand = self.operand(operand, u8::MAX);
+                (format!("{}{}", op.as_str(), operand), u8::MAX)
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                let precedence = precedence(*op);
+                let left = self.operand(left, precedence);
+                let right = self.operand(right, precedence + 1);
+                let code = format!("{} {} {}", left, op.as_str(), right);
+
+                // Arithmetic between floats is rounded to a float at every step in C
+                let arithmetic = matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div);
+                if arithmetic && expression.is_float() {
+                    (format!("Math.fround({})", code), u8::MAX)
+                } else {
+                    (code, precedence)
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self, expression: &Expression, min_precedence: u8) -> String {
+        let (code, precedence) = self.expression(expression);
+        if precedence < min_precedence {
+            format!("({})", code)
+        } else {
+            code
+        }
+    }
+
+    /// Return the JavaScript name of a variable, declaring it the first time it's seen
+    fn variable(&mut self, name: &str) -> String {
+        let js_name = if RESERVED.contains(&name) {
+            format!("${}", name)
+        } else {
+            name.to_owned()
+        };
+        if self.declared.insert(name.to_owned()) {
+            self.emitter
+                .line_to(Section::Declarations, &format!("let {} = 0;", js_name));
+        }
+        js_name
+    }
+}
+
+/// JavaScript operator precedence, higher binds tighter. It orders the operators the same way C does.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Mul | BinaryOp::Div => 4,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => 2,
+        BinaryOp::EqEq | BinaryOp::NotEq => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, Emit, Options};
+
+    /// Return the code generated for the program from the start of its main function
+    fn main_of(source: &str) -> String {
+        let options = Options {
+            emit: Emit::Js,
+            ..Options::default()
+        };
+        let code = String::from_utf8(compile_str(source, &options).unwrap().code).unwrap();
+        code[code.find("export default").unwrap()..].to_owned()
+    }
+
+    #[test]
+    fn structured_program() {
+        let source = "\
+PRINT \"hi\"
+INPUT n
+LET i = 0
+WHILE i < n REPEAT
+    IF i == 2 THEN
+        PRINT i * -1
+    ENDIF
+    LET i = i + 1
+ENDWHILE
+";
+        assert_eq!(
+            main_of(source),
+            "\
+export default async function main(print = console.log, input = async () => \"\") {
+    let n = 0;
+    let i = 0;
+    print(\"hi\");
+    n = $number(await input());
+    i = Math.fround(0.0);
+    while (i < n) {
+        if (i == 2.0) {
+            print($format(Math.fround(i * -1.0)));
+        }
+        i = Math.fround(i + 1.0);
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn goto_program() {
+        let source = "\
+LET i = 0
+LABEL top
+LET i = i + 1
+IF i < 3 THEN
+    GOTO top
+ENDIF
+PRINT i
+";
+        assert_eq!(
+            main_of(source),
+            "\
+export default async function main(print = console.log, input = async () => \"\") {
+    let i = 0;
+    let $block = 0;
+    dispatch: for (;;) {
+        switch ($block) {
+        case 0:
+            i = Math.fround(0.0);
+        case 1: // top
+            i = Math.fround(i + 1.0);
+            $block = i < 3.0 ? 2 : 3;
+            continue dispatch;
+        case 2:
+            $block = 1;
+            continue dispatch;
+        case 3:
+            print($format(Math.fround(i)));
+            return;
+        }
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn reserved_names() {
+        let source = "\
+LET new = 1
+LET lambda = 2
+LET fn = 3
+LET self = 4
+PRINT new + lambda / fn - self
+IF new < lambda == fn > self THEN
+    PRINT \"chained\"
+ENDIF
+";
+        assert_eq!(
+            main_of(source),
+            "\
+export default async function main(print = console.log, input = async () => \"\") {
+    let $new = 0;
+    let lambda = 0;
+    let fn = 0;
+    let self = 0;
+    $new = Math.fround(1.0);
+    lambda = Math.fround(2.0);
+    fn = Math.fround(3.0);
+    self = Math.fround(4.0);
+    print($format(Math.fround(Math.fround(Math.fround($new + Math.fround(lambda / fn)) - self))));
+    if (($new < lambda == fn) > self) {
+        print(\"chained\");
+    }
+}
+"
+        );
+    }
+}
@@ -1,10 +1,17 @@
+use super::diagnostic::Diagnostic;
+use super::span::Span;
 use super::token::{Token, TokenType};
 
 #[derive(Debug)]
 pub struct Lexer {
-    pub source: String,
+    pub source: Vec<char>,
     pub cur_pos: i32,
     pub cur_char: char,
+    pub pending_doc: Vec<String>,
+    pub keep_comments: bool,
+    pub any_case_keywords: bool,
+    pub line_has_token: bool,
+    pub line_is_blank: bool,
 }
 
 /// The Lexer struct implements lexical analysis for the compiler.
@@ -14,38 +21,50 @@ pub struct Lexer {
 impl Lexer {
     pub fn new(source: &str) -> Self {
         let mut new_self = Self {
-            source: format!("{}\n", source),
-            // Source code to lex as a string.
+            source: format!("{}\n", source).chars().collect(),
+            // Source code to lex as characters, so each one can be indexed directly.
             // Append a newline to simplify lexing/parsing
-            cur_pos: -1,   // Current character in the string.
-            cur_char: ' ', // Current position in the string.
+            cur_pos: -1,              // Current character in the string.
+            cur_char: ' ',            // Current position in the string.
+            pending_doc: Vec::new(),  // Doc comment lines not yet attached to a token.
+            keep_comments: false,     // Return comments as tokens instead of skipping them.
+            any_case_keywords: false, // Recognize keywords however they're capitalized.
+            line_has_token: false,    // A token other than a comment came before on this line.
+            line_is_blank: true,      // Nothing but spaces came before on this line.
         };
         new_self.next_char();
         new_self
     }
 
-    /// Process the next character.
+    /// Return each comment as a `Comment` token, for tools that rewrite the source
+    pub fn with_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Recognize keywords written in any case, such as `let` or `Print`, for tools that rewrite
+    /// them in uppercase. Their tokens keep the text as written.
+    pub fn with_any_case_keywords(mut self) -> Self {
+        self.any_case_keywords = true;
+        self
+    }
+
+    /// Process the next character. Positions count characters, not bytes, so the source can
+    /// hold any text.
     pub fn next_char(&mut self) {
         self.cur_pos += 1;
-
-        if self.cur_pos >= self.source.len() as i32 {
-            self.cur_char = "\0".parse().unwrap();
-        } else {
-            self.cur_char = self.source.chars().nth(self.cur_pos as usize).unwrap();
-        }
+        self.cur_char = self.source.get(self.cur_pos as usize).copied().unwrap_or('\0');
     }
 
     /// Return the lookahead character.
     pub fn peek(&self) -> char {
-        if self.cur_pos + 1 >= self.source.len() as i32 {
-            return '\0';
-        }
-        self.source.chars().nth((self.cur_pos + 1) as usize).unwrap()
+        self.source.get((self.cur_pos + 1) as usize).copied().unwrap_or('\0')
     }
 
-    /// Invalid token found, print error message and exit.
-    pub fn abort(&self, message: &str) {
-        panic!("{}", message)
+    /// Invalid token found, return an error pointing at the current character.
+    pub fn error(&self, message: &str) -> Diagnostic {
+        let pos = self.cur_pos as usize;
+        Diagnostic::error(message, Span::new(pos, pos + 1))
     }
 
     /// Skip whitespace except newlines, which we will use to indicate the end of a statement.
@@ -56,57 +75,105 @@ impl Lexer {
     }
 
     /// Skip comments in the code.
-    pub fn skip_comment(&mut self) {
-        if self.cur_char == '#' {
+    ///
+    /// `#` comments run to the end of the line, `#[` ... `]#` block comments may nest,
+    /// and the text of `##` doc comments is kept so it can be attached to the next token.
+    /// Only `##` lines of their own count: one after a token on the same line is a plain comment.
+    pub fn skip_comment(&mut self) -> Result<(), Diagnostic> {
+        if self.cur_char != '#' {
+            return Ok(());
+        }
+        self.line_is_blank = false;
+
+        if self.peek() == '[' {
+            self.skip_block_comment()?;
+        } else if self.peek() == '#' {
+            self.next_char();
+            self.next_char();
+            let start_pos = self.cur_pos;
+
             while self.cur_char != '\n' {
                 self.next_char()
             }
+
+            if !self.line_has_token {
+                let text = self.get_token_text(start_pos, self.cur_pos);
+                self.pending_doc
+                    .push(text.strip_prefix(' ').unwrap_or(&text).trim_end().to_owned());
+            }
+        } else {
+            while self.cur_char != '\n' {
+                self.next_char()
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip a `#[` ... `]#` block comment, including any block comments nested inside it.
+    fn skip_block_comment(&mut self) -> Result<(), Diagnostic> {
+        let start = self.cur_pos as usize;
+        let mut depth = 0;
+
+        loop {
+            if self.cur_char == '#' && self.peek() == '[' {
+                depth += 1;
+                self.next_char();
+            } else if self.cur_char == ']' && self.peek() == '#' {
+                depth -= 1;
+                self.next_char();
+
+                if depth == 0 {
+                    self.next_char();
+                    return Ok(());
+                }
+            } else if self.cur_char == '\0' {
+                return Err(Diagnostic::error(
+                    "Unterminated block comment",
+                    Span::new(start, start + 2),
+                ));
+            }
+
+            self.next_char();
         }
     }
 
     /// Retrieves the next token from the source code.
-    /// This function skips whitespace and comments, and returns the next token found.
+    /// This function skips whitespace and comments, unless comments are kept, and returns the next
+    /// token found.
     /// If no more tokens are available, it returns an end-of-file token.
     ///
     /// # Returns
     ///
-    /// The next token found in the source code.
-    pub fn get_token(&mut self) -> Token {
+    /// The next token found in the source code, or an error if the source has an invalid token.
+    pub fn get_token(&mut self) -> Result<Token, Diagnostic> {
+        // Comments may follow each other on the same line, e.g. `#[ ... ]# # ...`
         self.skip_whitespace();
-        self.skip_comment();
+        while self.cur_char == '#' {
+            let comment_start = self.cur_pos;
+            self.skip_comment()?;
+            if self.keep_comments {
+                let mut token = Token::new(self.get_token_text(comment_start, self.cur_pos), TokenType::Comment);
+                token.span = Span::new(comment_start as usize, self.cur_pos as usize);
+                return Ok(token);
+            }
+            self.skip_whitespace();
+        }
 
-        let mut token = Token::default();
+        let token_start = self.cur_pos as usize;
+        let mut token;
 
         if self.cur_char == '+' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Plus,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Plus);
         } else if self.cur_char == '-' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Minus,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Minus);
         } else if self.cur_char == '*' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Asterisk,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Asterisk);
         } else if self.cur_char == '/' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Slash,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Slash);
         } else if self.cur_char == '\n' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Newline,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Newline);
         } else if self.cur_char == '\0' {
-            token = Token {
-                text: String::from(self.cur_char),
-                kind: TokenType::Eof,
-            };
+            token = Token::new(String::from(self.cur_char), TokenType::Eof);
         } else if self.cur_char == '=' {
             token = self.handle_next_char_for_composite_chars(TokenType::Eq, TokenType::EqEq);
         } else if self.cur_char == '>' {
@@ -117,12 +184,9 @@ impl Lexer {
             if self.peek() == '=' {
                 let last_char = self.cur_char;
                 self.next_char();
-                token = Token {
-                    text: format!("{}{}", last_char, self.cur_char),
-                    kind: TokenType::NotEq,
-                };
+                token = Token::new(format!("{}{}", last_char, self.cur_char), TokenType::NotEq);
             } else {
-                self.abort(&format!("Expected !=, got !{}", self.cur_char));
+                return Err(self.error(&format!("Expected !=, got !{}", self.peek())));
             }
         } else if self.cur_char == '"' {
             self.next_char();
@@ -133,7 +197,7 @@ impl Lexer {
                 // We will be using C's printf on this string.
                 match self.cur_char {
                     '\r' | '\n' | '\t' | '\\' | '%' => {
-                        self.abort("Illegal character in string");
+                        return Err(self.error("Illegal character in string"));
                     }
                     _ => {
                         self.next_char();
@@ -143,10 +207,7 @@ impl Lexer {
 
             let token_text = self.get_token_text(start_pos, self.cur_pos); // Get the substring.
 
-            token = Token {
-                text: token_text,
-                kind: TokenType::String,
-            };
+            token = Token::new(token_text, TokenType::String);
         } else if self.cur_char.is_ascii_digit() {
             // Leading character is a digit, so this must be a number.
             // Get all consecutive digits and decimal if there is one.
@@ -160,7 +221,7 @@ impl Lexer {
                 self.next_char();
 
                 if !self.peek().is_ascii_digit() {
-                    self.abort("Illegal character in number");
+                    return Err(self.error("Illegal character in number"));
                 }
 
                 while self.peek().is_ascii_digit() {
@@ -170,10 +231,7 @@ impl Lexer {
 
             let token_text = self.get_token_text(start_pos, self.cur_pos + 1);
 
-            token = Token {
-                text: token_text,
-                kind: TokenType::Number,
-            };
+            token = Token::new(token_text, TokenType::Number);
         } else if self.cur_char.is_alphabetic() {
             // Leading character is a letter, so this must be an identifier or a keyword.
             // Get all consecutive alphanumeric characters.
@@ -186,24 +244,41 @@ impl Lexer {
 
             // Check if the token is in the list of keywords.
             let token_text: String = self.get_token_text(start_pos, self.cur_pos + 1);
-            let keyword = Token::check_if_keyword(&token_text);
+            let keyword = if self.any_case_keywords {
+                Token::check_if_keyword(&token_text.to_uppercase())
+            } else {
+                Token::check_if_keyword(&token_text)
+            };
 
             if keyword == TokenType::Unknown {
-                token = Token {
-                    text: token_text,
-                    kind: TokenType::Ident,
-                };
+                token = Token::new(token_text, TokenType::Ident);
             } else {
-                token = Token {
-                    text: token_text,
-                    kind: keyword,
-                };
+                token = Token::new(token_text, keyword);
             }
         } else {
-            self.abort(&format!("Unknown token: {}", self.cur_char));
+            return Err(self.error(&format!("Unknown token: {}", self.cur_char)));
         }
+        token.span = Span::new(token_start, self.cur_pos as usize + 1);
         self.next_char();
-        token
+
+        // Doc comments belong to the next thing on the line right after them, so they skip past
+        // their own newlines, but a blank line in between leaves them attached to nothing.
+        if token.kind == TokenType::Newline {
+            if self.line_is_blank {
+                self.pending_doc.clear();
+            }
+            self.line_has_token = false;
+            self.line_is_blank = true;
+        } else {
+            if !self.pending_doc.is_empty() {
+                token.doc = Some(self.pending_doc.join("\n"));
+                self.pending_doc.clear();
+            }
+            self.line_has_token = true;
+            self.line_is_blank = false;
+        }
+
+        Ok(token)
     }
 
     /// Handles the next character for composite characters(=) in the source code.
@@ -220,15 +295,9 @@ impl Lexer {
         if self.peek() == '=' {
             let last_char = self.cur_char;
             self.next_char();
-            Token {
-                text: format!("{}{}", last_char, self.cur_char),
-                kind: other_token_type,
-            }
+            Token::new(format!("{}{}", last_char, self.cur_char), other_token_type)
         } else {
-            Token {
-                text: String::from(self.cur_char),
-                kind: token_type,
-            }
+            Token::new(String::from(self.cur_char), token_type)
         }
     }
 
@@ -243,10 +312,57 @@ impl Lexer {
     ///
     /// The text of the token as a `String`.
     fn get_token_text(&self, start_pos: i32, end_pos: i32) -> String {
-        self.source
-            .chars()
-            .skip(start_pos as usize)
-            .take((end_pos - start_pos) as usize)
-            .collect()
+        self.source[start_pos as usize..end_pos as usize].iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize;
+
+    /// The kinds of the tokens of the source, up to the end of file
+    fn kinds(source: &str) -> Vec<TokenType> {
+        tokenize(source).unwrap().into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let source = "PRINT #[ outer #[ inner ]# still outer ]# 1\n";
+        assert_eq!(
+            kinds(source),
+            [
+                TokenType::Print,
+                TokenType::Number,
+                TokenType::Newline,
+                TokenType::Newline,
+                TokenType::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let errors = tokenize("PRINT 1\n#[ open #[ nested ]#\nPRINT 2\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment");
+        assert_eq!(errors[0].span, Span::new(8, 10));
+    }
+
+    #[test]
+    fn doc_comments_attach_to_the_next_token() {
+        let source = "## Counts up\n## to ten\nLET n = 0 ## not a doc comment\nPRINT n\n";
+        let documented: Vec<_> = tokenize(source)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| Some((token.kind, token.doc?)))
+            .collect();
+        assert_eq!(documented, [(TokenType::Let, "Counts up\nto ten".to_owned())]);
+    }
+
+    #[test]
+    fn doc_comments_are_dropped_after_a_blank_line() {
+        let tokens = tokenize("## Nothing follows\n\nLET n = 0\n").unwrap();
+        assert!(tokens.iter().all(|token| token.doc.is_none()));
     }
 }
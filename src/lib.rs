@@ -0,0 +1,351 @@
+//! The teeny compiler as a library. `compile_str` takes a program's source through to any of the
+//! outputs the `teeny` command writes, and the modules give access to each stage on the way.
+
+pub mod ast;
+pub mod cfg;
+pub mod codegen;
+pub mod cst;
+mod definite;
+pub mod diagnostic;
+mod elf;
+pub mod emitter;
+pub mod encode;
+pub mod format;
+pub mod interp;
+pub mod ir;
+pub mod jit;
+pub mod js;
+pub mod json;
+pub mod lex;
+pub mod lint;
+pub mod llvm;
+mod loops;
+mod lower;
+pub mod lsp;
+pub mod native;
+pub mod optimize;
+pub mod parse;
+pub mod passes;
+pub mod py;
+mod regalloc;
+pub mod repl;
+pub mod resolve;
+mod runtime;
+pub mod rust;
+pub mod sourcemap;
+pub mod span;
+mod ssa;
+pub mod symbols;
+pub mod token;
+pub mod x86;
+
+use ast::Program;
+use cfg::Cfg;
+use codegen::CodeGen;
+use diagnostic::{Diagnostic, Severity};
+use emitter::{Emitter, Section};
+use js::JsGen;
+use lex::Lexer;
+use lint::Lint;
+use optimize::OptLevel;
+use parse::Parser;
+use passes::Pass;
+use py::PyGen;
+use resolve::Resolver;
+use rust::RustGen;
+use sourcemap::SourceMap;
+use span::LineIndex;
+use std::collections::HashSet;
+use token::{Token, TokenType};
+
+/// The errors, and any warnings, that stopped a program from compiling
+pub type Diagnostics = Vec<Diagnostic>;
+
+/// What a program is compiled to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Emit {
+    /// The tokens, one per line with their position
+    Tokens,
+    /// The syntax tree
+    Ast,
+    /// The concrete syntax tree, with every token, space and comment
+    Cst,
+    /// C source
+    C,
+    /// A JavaScript module
+    Js,
+    /// A Python 3 script
+    Py,
+    /// A Rust program
+    Rust,
+    /// The control flow graph in Graphviz DOT format
+    CfgDot,
+    /// The SSA intermediate representation
+    Ir,
+    /// x86-64 assembly calling the C library
+    Asm,
+    /// An LLVM IR module calling the C library
+    LlvmIr,
+    /// A statically linked x86-64 Linux executable that needs no C library
+    Exe,
+}
+
+impl Emit {
+    /// Find a kind of output by the name `--emit` takes
+    pub fn from_name(kind: &str) -> Option<Emit> {
+        match kind {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "cst" => Some(Emit::Cst),
+            "c" => Some(Emit::C),
+            "js" => Some(Emit::Js),
+            "py" => Some(Emit::Py),
+            "rust" => Some(Emit::Rust),
+            "cfg-dot" => Some(Emit::CfgDot),
+            "ir" => Some(Emit::Ir),
+            "asm" => Some(Emit::Asm),
+            "llvm-ir" => Some(Emit::LlvmIr),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+}
+
+/// How a program is compiled
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub emit: Emit,
+    pub opt_level: OptLevel,
+    /// Warnings that aren't reported
+    pub allowed: HashSet<Lint>,
+    /// Treat warnings as errors
+    pub deny_warnings: bool,
+    /// IR passes that don't run
+    pub disabled_passes: HashSet<Pass>,
+    /// Write `#line` directives naming this file into C, so the C compiler and debuggers point at
+    /// the teeny source
+    pub line_directives: Option<String>,
+    /// Map the lines of C back to the teeny source, in `Output::source_map`
+    pub source_map: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            emit: Emit::C,
+            opt_level: OptLevel::O0,
+            allowed: HashSet::new(),
+            deny_warnings: false,
+            disabled_passes: HashSet::new(),
+            line_directives: None,
+            source_map: false,
+        }
+    }
+}
+
+/// A compiled program
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// The program as the options asked for it: text, or the bytes of an executable
+    pub code: Vec<u8>,
+    /// Warnings about the program
+    pub warnings: Vec<Diagnostic>,
+    /// Where the lines of C came from, if the options asked for it
+    pub source_map: Option<SourceMap>,
+}
+
+/// Compile a program's source as the options say
+pub fn compile_str(source: &str, options: &Options) -> Result<Output, Diagnostics> {
+    // The tokens and the syntax trees are written out whether or not the program checks
+    let mut source_map = None;
+    let (code, warnings) = match options.emit {
+        Emit::Tokens => (tokens(source)?, Vec::new()),
+        Emit::Ast => (generate(&parse(source)?, source, options)?, Vec::new()),
+        Emit::Cst => (concrete_tree(source)?, Vec::new()),
+        Emit::C if options.source_map => {
+            let (program, warnings) = check(source, options)?;
+            let (code, map) = c(&program, source, options);
+            source_map = Some(map);
+            (code, warnings)
+        }
+        _ => {
+            let (program, warnings) = check(source, options)?;
+            (generate(&program, source, options)?, warnings)
+        }
+    };
+    Ok(Output {
+        code,
+        warnings,
+        source_map,
+    })
+}
+
+/// Return the tokens of the source, ending with the end of file token
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostics> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.get_token().map_err(|error| vec![error])?;
+        let eof = token.kind == TokenType::Eof;
+        tokens.push(token);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+/// Parse the source into a syntax tree, without checking it
+pub fn parse(source: &str) -> Result<Program, Diagnostics> {
+    Parser::new(Lexer::new(source))
+        .and_then(|mut parser| parser.program())
+        .map_err(|error| vec![error])
+}
+
+/// Parse and check the source, returning the program optimized as the options say along with any
+/// warnings about it
+pub fn check(source: &str, options: &Options) -> Result<(Program, Vec<Diagnostic>), Diagnostics> {
+    let program = parse(source)?;
+    let mut diagnostics = Vec::new();
+
+    // Each check relies on the ones before it finding no errors
+    let mut resolver = Resolver::new();
+    resolver.program(&program);
+    add(&mut diagnostics, resolver.diagnostics, options)?;
+
+    let cfg = Cfg::new(&program);
+    add(&mut diagnostics, definite::check(&cfg), options)?;
+    add(
+        &mut diagnostics,
+        lint::check(&program, &resolver.symbols, &cfg, &options.allowed),
+        options,
+    )?;
+
+    Ok((optimize::optimize(&program, options.opt_level), diagnostics))
+}
+
+/// Add a check's diagnostics to those so far, and fail with all of them if any is an error
+fn add(diagnostics: &mut Vec<Diagnostic>, new: Vec<Diagnostic>, options: &Options) -> Result<(), Diagnostics> {
+    for mut diagnostic in new {
+        if options.deny_warnings {
+            diagnostic.severity = Severity::Error;
+        }
+        diagnostics.push(diagnostic);
+    }
+
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        Err(std::mem::take(diagnostics))
+    } else {
+        Ok(())
+    }
+}
+
+/// Generate code for a checked program parsed from the source. Tokens and the concrete syntax
+/// tree are written from the source, which fails only if it doesn't lex or parse.
+pub fn generate(program: &Program, source: &str, options: &Options) -> Result<Vec<u8>, Diagnostics> {
+    Ok(match options.emit {
+        Emit::Tokens => tokens(source)?,
+        Emit::Cst => concrete_tree(source)?,
+        Emit::Ast => format!("{:#?}\n", program).into_bytes(),
+        Emit::C => c(program, source, options).0,
+        Emit::Js => emit(|emitter| JsGen::new(emitter).program(program)),
+        Emit::Py => emit(|emitter| PyGen::new(emitter).program(program)),
+        Emit::Rust => emit(|emitter| RustGen::new(emitter).program(program)),
+        Emit::CfgDot => Cfg::new(program).to_dot().into_bytes(),
+        Emit::Ir => ir(program, options).to_string().into_bytes(),
+        Emit::Asm => native::compile(&ir(program, options)).to_string().into_bytes(),
+        Emit::LlvmIr => llvm::module(&ir(program, options)).into_bytes(),
+        Emit::Exe => encode::executable(&native::compile(&ir(program, options))),
+    })
+}
+
+/// Write the tokens of the source one per line, with their line and column
+fn tokens(source: &str) -> Result<Vec<u8>, Diagnostics> {
+    let mut code = String::new();
+    let lines = LineIndex::new(source);
+    for token in tokenize(source)? {
+        let (line, col) = lines.line_col(token.span.start);
+        code.push_str(&format!("{}:{} {:?} {:?}\n", line, col, token.kind, token.text));
+    }
+    Ok(code.into_bytes())
+}
+
+/// Write the concrete syntax tree of the source
+fn concrete_tree(source: &str) -> Result<Vec<u8>, Diagnostics> {
+    Ok(cst::parse(source)?.to_tree().into_bytes())
+}
+
+/// Return the source a code generator writes through an emitter
+fn emit(gen: impl FnOnce(&mut Emitter<Vec<u8>>)) -> Vec<u8> {
+    let mut emitter = Emitter::new(Vec::new());
+    gen(&mut emitter);
+    emitter.finish().expect("writing to memory can't fail")
+}
+
+/// Generate C for a checked program parsed from the source, along with where its lines came from
+pub fn c(program: &Program, source: &str, options: &Options) -> (Vec<u8>, SourceMap) {
+    let mut emitter = Emitter::new(Vec::new());
+    let mut codegen = CodeGen::new(&mut emitter);
+    if let Some(file_name) = &options.line_directives {
+        codegen = codegen.with_line_directives(file_name, source);
+    }
+    codegen.program(program);
+    let line_map = codegen.line_map;
+    let map = SourceMap::new(emitter.start_of(Section::Main), &line_map, source);
+    (emitter.finish().expect("writing to memory can't fail"), map)
+}
+
+/// Lower the program to IR in SSA form, optimized as the options ask
+pub fn ir(program: &Program, options: &Options) -> ir::Function {
+    let mut function = lower::lower(&Cfg::new(program));
+    ssa::construct(&mut function);
+    if options.opt_level >= OptLevel::O1 {
+        passes::run(&mut function, &options.disabled_passes);
+    }
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_writes_every_kind_of_output() {
+        let source = "LET a = 1\nPRINT a\n";
+        let program = parse(source).unwrap();
+        for kind in [
+            "tokens", "ast", "cst", "c", "js", "py", "rust", "cfg-dot", "ir", "asm", "llvm-ir", "exe",
+        ] {
+            let options = Options {
+                emit: Emit::from_name(kind).unwrap(),
+                ..Options::default()
+            };
+            let code = generate(&program, source, &options).unwrap();
+            assert_eq!(code, compile_str(source, &options).unwrap().code, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn compile_str_returns_errors_warnings_and_source_maps() {
+        let errors = compile_str("PRINT n\n", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].message, "Referencing variable before assignment: n");
+
+        let output = compile_str("LET n = 1\n", &Options::default()).unwrap();
+        assert_eq!(output.warnings[0].code, Some("W002"));
+        assert!(output.source_map.is_none());
+
+        let options = Options {
+            source_map: true,
+            ..Options::default()
+        };
+        let output = compile_str("LET n = 1\nPRINT n\n", &options).unwrap();
+        assert!(output.warnings.is_empty());
+        let lines: Vec<_> = output
+            .source_map
+            .unwrap()
+            .mappings
+            .iter()
+            .map(|mapping| mapping.source_line)
+            .collect();
+        assert_eq!(lines, [1, 2]);
+    }
+}
@@ -0,0 +1,211 @@
+use super::ast::{Program, Statement, StatementKind};
+use super::cfg::Cfg;
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+use super::symbols::{SymbolKind, SymbolTable, UseKind};
+use std::collections::HashSet;
+
+/// The kinds of warning the compiler can give. Each can be turned off on its own with `--allow`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedLabel,
+    UnusedVariable,
+    UnreachableCode,
+    WhileFalse,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [
+        Lint::UnusedLabel,
+        Lint::UnusedVariable,
+        Lint::UnreachableCode,
+        Lint::WhileFalse,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "W001",
+            Lint::UnusedVariable => "W002",
+            Lint::UnreachableCode => "W003",
+            Lint::WhileFalse => "W004",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "unused-label",
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::WhileFalse => "while-false",
+        }
+    }
+
+    /// Find a lint by its code or its name
+    pub fn from_name(lint: &str) -> Option<Lint> {
+        Lint::ALL
+            .into_iter()
+            .find(|known| known.code().eq_ignore_ascii_case(lint) || known.name() == lint)
+    }
+}
+
+/// Check a program that compiled without errors for code that is probably a mistake
+pub fn check(program: &Program, symbols: &SymbolTable, cfg: &Cfg, allowed: &HashSet<Lint>) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+
+    for symbol in symbols.symbols() {
+        let read = symbol.uses.iter().any(|use_| use_.kind == UseKind::Read);
+        if read {
+            continue;
+        }
+
+        match symbol.kind {
+            SymbolKind::Label => warnings.push((
+                Lint::UnusedLabel,
+                Diagnostic::warning(
+                    Lint::UnusedLabel.code(),
+                    &format!("Label {} is never jumped to", symbol.name),
+                    symbol.declared,
+                ),
+            )),
+            SymbolKind::Variable => warnings.push((
+                Lint::UnusedVariable,
+                Diagnostic::warning(
+                    Lint::UnusedVariable.code(),
+                    &format!("Variable {} is assigned but never read", symbol.name),
+                    symbol.declared,
+                ),
+            )),
+        }
+    }
+
+    let reachable = cfg.reachable();
+    let unreachable: HashSet<_> = cfg
+        .blocks
+        .iter()
+        .zip(reachable)
+        .filter(|(_, reachable)| !reachable)
+        .flat_map(|(block, _)| block.spans.iter().copied())
+        .collect();
+    let mut lints = Lints { unreachable, warnings };
+    lints.statements(&program.statements);
+
+    let mut diagnostics: Vec<_> = lints
+        .warnings
+        .into_iter()
+        .filter(|(lint, _)| !allowed.contains(lint))
+        .map(|(lint, diagnostic)| {
+            diagnostic.with_note(&format!("pass `--allow {}` to turn this warning off", lint.name()))
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+struct Lints {
+    /// Spans of the statements in blocks that can never run
+    unreachable: HashSet<Span>,
+    warnings: Vec<(Lint, Diagnostic)>,
+}
+
+impl Lints {
+    fn statements(&mut self, statements: &[Statement]) {
+        // Only the first statement of a run of unreachable statements is reported
+        let mut in_unreachable_run = false;
+
+        for statement in statements {
+            let unreachable = self.unreachable.contains(&statement.span);
+            if unreachable && !in_unreachable_run {
+                self.warnings.push((
+                    Lint::UnreachableCode,
+                    Diagnostic::warning(Lint::UnreachableCode.code(), "Unreachable statement", statement.span),
+                ));
+            }
+            in_unreachable_run = unreachable;
+
+            match &statement.kind {
+                StatementKind::If { body, .. } if !unreachable => self.statements(body),
+                StatementKind::While { condition, body } if !unreachable => {
+                    if condition.constant() == Some(0.0) {
+                        self.warnings.push((
+                            Lint::WhileFalse,
+                            Diagnostic::warning(
+                                Lint::WhileFalse.code(),
+                                "Condition of WHILE is always false, so the loop never runs",
+                                condition.span,
+                            ),
+                        ));
+                    } else {
+                        self.statements(body);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+    use crate::Options;
+
+    /// A program with one of each warning
+    const SOURCE: &str = "\
+LABEL unused
+LET x = 1
+WHILE 1 > 2 REPEAT
+    PRINT \"never\"
+ENDWHILE
+GOTO end
+PRINT \"dead\"
+LABEL end
+";
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&'static str> {
+        diagnostics.iter().map(|diagnostic| diagnostic.code.unwrap()).collect()
+    }
+
+    #[test]
+    fn each_warning_can_be_allowed() {
+        let (_, warnings) = crate::check(SOURCE, &Options::default()).unwrap();
+        assert_eq!(codes(&warnings), ["W001", "W002", "W004", "W003"]);
+
+        for lint in Lint::ALL {
+            for name in [lint.code(), lint.name()] {
+                let options = Options {
+                    allowed: HashSet::from([Lint::from_name(name).unwrap()]),
+                    ..Options::default()
+                };
+                let (_, warnings) = crate::check(SOURCE, &options).unwrap();
+                let expected: Vec<_> = ["W001", "W002", "W004", "W003"]
+                    .into_iter()
+                    .filter(|&code| code != lint.code())
+                    .collect();
+                assert_eq!(codes(&warnings), expected, "--allow {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn deny_warnings_makes_each_warning_an_error() {
+        for lint in Lint::ALL {
+            // Allow every warning but this one
+            let options = Options {
+                allowed: Lint::ALL.into_iter().filter(|&other| other != lint).collect(),
+                deny_warnings: true,
+                ..Options::default()
+            };
+            let errors = crate::check(SOURCE, &options).unwrap_err();
+            assert_eq!(codes(&errors), [lint.code()]);
+            assert_eq!(errors[0].severity, Severity::Error);
+        }
+
+        let options = Options {
+            allowed: HashSet::from(Lint::ALL),
+            deny_warnings: true,
+            ..Options::default()
+        };
+        assert!(crate::check(SOURCE, &options).unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+use super::ast::BinaryOp;
+use super::ir::{Function, Inst, Terminator, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Declarations and helpers every module gets. Numbers print and read through the C library like
+/// the assembly backend's, with a word that isn't a number reading as zero.
+const PRELUDE: &str = r#"@.number_format = private unnamed_addr constant [6 x i8] c"%.2f\0A\00"
+@.input_format = private unnamed_addr constant [4 x i8] c"%lf\00"
+@.skip_format = private unnamed_addr constant [4 x i8] c"%*s\00"
+
+declare i32 @printf(ptr, ...)
+declare i32 @puts(ptr)
+declare i32 @scanf(ptr, ...)
+
+define internal void @print_number(double %value) {
+entry:
+  call i32 (ptr, ...) @printf(ptr @.number_format, double %value)
+  ret void
+}
+
+define internal double @read_number() {
+entry:
+  %slot = alloca double
+  store double 0.0, ptr %slot
+  %read = call i32 (ptr, ...) @scanf(ptr @.input_format, ptr %slot)
+  %ok = icmp eq i32 %read, 1
+  br i1 %ok, label %done, label %skip
+skip:
+  store double 0.0, ptr %slot
+  call i32 (ptr, ...) @scanf(ptr @.skip_format)
+  br label %done
+done:
+  %value = load double, ptr %slot
+  ret double %value
+}
+"#;
+
+/// Translate a function in SSA form to a textual LLVM IR module defining `main`, for `llc` or
+/// `clang` to compile. Every IR block becomes a basic block, and phis stay phis.
+pub fn module(function: &Function) -> String {
+    let module = Module::new(function);
+    let mut strings = Vec::new();
+    let mut out = String::new();
+
+    writeln!(out, "define i32 @main() {{").unwrap();
+    // LLVM's entry block can't be jumped to, but block 0 can be
+    writeln!(out, "entry:").unwrap();
+    writeln!(out, "  br label %b0").unwrap();
+
+    for block in function.reverse_postorder() {
+        writeln!(out, "b{}:", block).unwrap();
+
+        for inst in &function.blocks[block].insts {
+            match inst {
+                // Constants and copies are folded into the instructions using them
+                Inst::Const { .. } | Inst::Copy { .. } => {}
+                Inst::Neg { dst, src } => {
+                    writeln!(out, "  {} = fneg double {}", module.name(*dst), module.operand(*src)).unwrap()
+                }
+                Inst::Binary { dst, op, left, right } => {
+                    let left = module.operand(*left);
+                    let right = module.operand(*right);
+                    let dst = module.name(*dst);
+                    let arithmetic = match op {
+                        BinaryOp::Add => Some("fadd"),
+                        BinaryOp::Sub => Some("fsub"),
+                        BinaryOp::Mul => Some("fmul"),
+                        BinaryOp::Div => Some("fdiv"),
+                        _ => None,
+                    };
+                    match arithmetic {
+                        Some(instruction) => {
+                            writeln!(out, "  {} = {} double {}, {}", dst, instruction, left, right).unwrap()
+                        }
+                        None => {
+                            // Comparisons give 1 or 0. Not equal is unordered, so it holds for NaN.
+                            let predicate = match op {
+                                BinaryOp::EqEq => "oeq",
+                                BinaryOp::NotEq => "une",
+                                BinaryOp::Lt => "olt",
+                                BinaryOp::LtEq => "ole",
+                                BinaryOp::Gt => "ogt",
+                                BinaryOp::GtEq => "oge",
+                                _ => unreachable!(),
+                            };
+                            let truth = format!("{}.truth", dst);
+                            writeln!(out, "  {} = fcmp {} double {}, {}", truth, predicate, left, right).unwrap();
+                            writeln!(out, "  {} = uitofp i1 {} to double", dst, truth).unwrap();
+                        }
+                    }
+                }
+                Inst::Round { dst, src } => {
+                    let dst = module.name(*dst);
+                    writeln!(
+                        out,
+                        "  {}.single = fptrunc double {} to float",
+                        dst,
+                        module.operand(*src)
+                    )
+                    .unwrap();
+                    writeln!(out, "  {} = fpext float {}.single to double", dst, dst).unwrap();
+                }
+                Inst::Phi { dst, args } => {
+                    let mut incoming: Vec<String> = args
+                        .iter()
+                        .filter(|(predecessor, _)| module.reachable[*predecessor])
+                        .map(|(predecessor, value)| format!("[ {}, %b{} ]", module.operand(*value), predecessor))
+                        .collect();
+                    // Variables start at zero
+                    if block == 0 {
+                        incoming.push("[ 0.0, %entry ]".to_owned());
+                    }
+                    writeln!(out, "  {} = phi double {}", module.name(*dst), incoming.join(", ")).unwrap();
+                }
+                Inst::Print { src } => {
+                    writeln!(out, "  call void @print_number(double {})", module.operand(*src)).unwrap()
+                }
+                Inst::PrintStr { text } => {
+                    writeln!(out, "  call i32 @puts(ptr @.string.{})", strings.len()).unwrap();
+                    strings.push(text);
+                }
+                Inst::Input { dst } => writeln!(out, "  {} = call double @read_number()", module.name(*dst)).unwrap(),
+                Inst::Load { .. } | Inst::Store { .. } => unreachable!("the function is in SSA form"),
+            }
+        }
+
+        match function.blocks[block].terminator {
+            Terminator::Jump(target) => writeln!(out, "  br label %b{}", target).unwrap(),
+            // Anything but zero is true, including NaN
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let truth = format!("%b{}.cond", block);
+                writeln!(out, "  {} = fcmp une double {}, 0.0", truth, module.operand(cond)).unwrap();
+                writeln!(out, "  br i1 {}, label %b{}, label %b{}", truth, then_block, else_block).unwrap();
+            }
+            Terminator::Return => writeln!(out, "  ret i32 0").unwrap(),
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    let mut header = String::new();
+    for (index, text) in strings.iter().enumerate() {
+        writeln!(
+            header,
+            "@.string.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+            index,
+            text.len() + 1,
+            escape(text)
+        )
+        .unwrap();
+    }
+    writeln!(header, "{}", PRELUDE).unwrap();
+    header + &out
+}
+
+/// What's known about a function's values while translating it
+struct Module<'a> {
+    function: &'a Function,
+    constants: HashMap<Value, f64>,
+    copies: HashMap<Value, Value>,
+    reachable: Vec<bool>,
+}
+
+impl<'a> Module<'a> {
+    fn new(function: &'a Function) -> Self {
+        let mut constants = HashMap::new();
+        let mut copies = HashMap::new();
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            match inst {
+                Inst::Const { dst, value } => {
+                    constants.insert(*dst, *value);
+                }
+                Inst::Copy { dst, src } => {
+                    copies.insert(*dst, *src);
+                }
+                _ => {}
+            }
+        }
+
+        let mut reachable = vec![false; function.blocks.len()];
+        for block in function.reverse_postorder() {
+            reachable[block] = true;
+        }
+
+        Self {
+            function,
+            constants,
+            copies,
+            reachable,
+        }
+    }
+
+    /// Return a value as an operand: a constant, or the name of the value a copy chain ends at
+    fn operand(&self, mut value: Value) -> String {
+        while let Some(&src) = self.copies.get(&value) {
+            value = src;
+        }
+        match self.constants.get(&value) {
+            // The hexadecimal form is exact
+            Some(constant) => format!("0x{:016X}", constant.to_bits()),
+            None => self.name(value),
+        }
+    }
+
+    /// Return the LLVM name of a value, with its variable's name if it has one that needs no quoting
+    fn name(&self, value: Value) -> String {
+        match self.function.names.get(&value) {
+            Some(name) if name.chars().all(|c| c.is_ascii_alphanumeric()) => format!("%{}.{}", name, value),
+            _ => format!("%v{}", value),
+        }
+    }
+}
+
+/// Escape a string for a `c"..."` constant, writing anything but printable ASCII as hex bytes
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parse, Options};
+
+    /// Return the module for the program from the start of main, after the runtime it calls
+    fn main_of(source: &str) -> String {
+        let module = module(&ir(&parse(source).unwrap(), &Options::default()));
+        module[module.find("define i32 @main").unwrap()..].to_owned()
+    }
+
+    #[test]
+    fn branches_print_and_input() {
+        // Stored values are rounded through float, and comparisons widen to doubles
+        let source = "\
+INPUT n
+IF n > 1 THEN
+    PRINT n / 2
+ENDIF
+PRINT \"done\"
+";
+        assert_eq!(
+            main_of(source),
+            "\
+define i32 @main() {
+entry:
+  br label %b0
+b0:
+  %v0 = call double @read_number()
+  %v1.single = fptrunc double %v0 to float
+  %v1 = fpext float %v1.single to double
+  %v4.truth = fcmp ogt double %v1, 0x3FF0000000000000
+  %v4 = uitofp i1 %v4.truth to double
+  %b0.cond = fcmp une double %v4, 0.0
+  br i1 %b0.cond, label %b1, label %b2
+b1:
+  %v7 = fdiv double %v1, 0x4000000000000000
+  %v8.single = fptrunc double %v7 to float
+  %v8 = fpext float %v8.single to double
+  call void @print_number(double %v8)
+  br label %b2
+b2:
+  call i32 @puts(ptr @.string.0)
+  ret i32 0
+}
+"
+        );
+    }
+
+    #[test]
+    fn loops_use_phis() {
+        let source = "\
+LET i = 0
+WHILE i < 3 REPEAT
+    LET i = i + 1
+ENDWHILE
+PRINT i
+";
+        assert_eq!(
+            main_of(source),
+            "\
+define i32 @main() {
+entry:
+  br label %b0
+b0:
+  br label %b1
+b1:
+  %i.9 = phi double [ 0x0000000000000000, %b0 ], [ %v7, %b2 ]
+  %v3.truth = fcmp olt double %i.9, 0x4008000000000000
+  %v3 = uitofp i1 %v3.truth to double
+  %b1.cond = fcmp une double %v3, 0.0
+  br i1 %b1.cond, label %b2, label %b3
+b3:
+  call void @print_number(double %i.9)
+  ret i32 0
+b2:
+  %v6 = fadd double %i.9, 0x3FF0000000000000
+  %v7.single = fptrunc double %v6 to float
+  %v7 = fpext float %v7.single to double
+  br label %b1
+}
+"
+        );
+    }
+}
@@ -0,0 +1,589 @@
+use super::ast::BinaryOp;
+use super::ir::{Block, BlockId, Function, Inst, Terminator, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Whole numbers up to 2^24 are exact in single precision, and up to 2^53 in double precision
+const EXACT: f64 = 16777216.0;
+const MAX_EXACT_DOUBLE: f64 = 9007199254740992.0;
+
+/// A natural loop: a header that dominates every block of the loop, and the blocks jumping back to it
+#[derive(Debug)]
+pub struct Loop {
+    pub header: BlockId,
+    /// Every block of the loop, including the header
+    pub blocks: BTreeSet<BlockId>,
+    /// The blocks with an edge back to the header
+    pub latches: Vec<BlockId>,
+}
+
+impl Loop {
+    /// Return the predecessors of the header from outside the loop
+    fn entries(&self, predecessors: &[Vec<BlockId>]) -> Vec<BlockId> {
+        predecessors[self.header]
+            .iter()
+            .copied()
+            .filter(|predecessor| !self.blocks.contains(predecessor))
+            .collect()
+    }
+}
+
+/// Find the natural loops of a function, innermost first.
+/// Loops sharing a header are merged. Loops entered other than through their header,
+/// which GOTO can make, have no header dominating them and aren't found.
+pub fn find_loops(function: &Function) -> Vec<Loop> {
+    let idom = function.dominators();
+    let predecessors = function.predecessors();
+    let mut loops: Vec<Loop> = Vec::new();
+
+    for block in function.reverse_postorder() {
+        for header in function.blocks[block].terminator.successors() {
+            if !Function::dominates(&idom, header, block) {
+                continue;
+            }
+
+            // Everything reaching the latch without going through the header is in the loop
+            let mut blocks = BTreeSet::from([header]);
+            let mut worklist = vec![block];
+            while let Some(current) = worklist.pop() {
+                if blocks.insert(current) {
+                    worklist.extend(&predecessors[current]);
+                }
+            }
+
+            match loops.iter_mut().find(|other| other.header == header) {
+                Some(other) => {
+                    other.blocks.extend(blocks);
+                    if !other.latches.contains(&block) {
+                        other.latches.push(block);
+                    }
+                }
+                None => loops.push(Loop {
+                    header,
+                    blocks,
+                    latches: vec![block],
+                }),
+            }
+        }
+    }
+
+    loops.sort_by_key(|found| found.blocks.len());
+    loops
+}
+
+/// Move instructions computing the same value on every iteration of a loop into its preheader,
+/// a block running once before the loop. Return true if anything moved or a preheader was added.
+pub fn hoist_invariants(function: &mut Function) -> bool {
+    for found in find_loops(function) {
+        let (preheader, added) = match preheader(function, &found) {
+            Some(preheader) => (preheader, false),
+            None => match add_preheader(function, &found) {
+                Some(preheader) => (preheader, true),
+                None => continue,
+            },
+        };
+
+        let mut defined_in_loop: HashSet<Value> = found
+            .blocks
+            .iter()
+            .flat_map(|&block| &function.blocks[block].insts)
+            .filter_map(Inst::dst)
+            .collect();
+
+        let mut hoisted = Vec::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &block in &found.blocks {
+                let insts = std::mem::take(&mut function.blocks[block].insts);
+                for inst in insts {
+                    let invariant = !matches!(inst, Inst::Phi { .. })
+                        && !inst.has_side_effects()
+                        && inst.uses().iter().all(|value| !defined_in_loop.contains(value));
+
+                    if invariant {
+                        defined_in_loop.remove(&inst.dst().unwrap());
+                        hoisted.push(inst);
+                        changed = true;
+                    } else {
+                        function.blocks[block].insts.push(inst);
+                    }
+                }
+            }
+        }
+
+        // Later loops were found before this one changed the blocks, so start again
+        if added || !hoisted.is_empty() {
+            function.blocks[preheader].insts.extend(hoisted);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Replace multiplications of an induction variable by a constant with a second induction variable
+/// stepping by the product. Only whole numbers are handled, so the sums are exactly the products.
+/// Return true if a multiplication was replaced.
+pub fn reduce_strength(function: &mut Function) -> bool {
+    let constants: HashMap<Value, f64> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst {
+            Inst::Const { dst, value } => Some((*dst, *value)),
+            _ => None,
+        })
+        .collect();
+    let integral = |value: Value| constants.get(&value).copied().filter(|value| value.fract() == 0.0);
+
+    for found in find_loops(function) {
+        let [latch] = found.latches[..] else {
+            continue;
+        };
+        let Some(preheader) = preheader(function, &found) else {
+            continue;
+        };
+
+        // Basic induction variables: phis starting from a constant and stepping by one every iteration
+        let definitions: HashMap<Value, &Inst> = found
+            .blocks
+            .iter()
+            .flat_map(|&block| &function.blocks[block].insts)
+            .filter_map(|inst| Some((inst.dst()?, inst)))
+            .collect();
+        let mut inductions = HashMap::new();
+        for inst in &function.blocks[found.header].insts {
+            let Inst::Phi { dst, args } = inst else {
+                continue;
+            };
+            let [(from_a, a), (from_b, b)] = args[..] else {
+                continue;
+            };
+            let (init, next) = match (from_a, from_b) {
+                _ if from_a == preheader && from_b == latch => (a, b),
+                _ if from_a == latch && from_b == preheader => (b, a),
+                _ => continue,
+            };
+            // A counter assigned back to its variable is rounded to single precision on the way,
+            // which leaves it alone while it stays exact. That's checked against the loop's exit below.
+            let mut next_definition = definitions.get(&next);
+            if let Some(Inst::Round { src, .. }) = next_definition {
+                next_definition = definitions.get(src);
+            }
+            let step = match next_definition {
+                Some(Inst::Binary {
+                    op: BinaryOp::Add,
+                    left,
+                    right,
+                    ..
+                }) if left == dst => integral(*right),
+                Some(Inst::Binary {
+                    op: BinaryOp::Add,
+                    left,
+                    right,
+                    ..
+                }) if right == dst => integral(*left),
+                Some(Inst::Binary {
+                    op: BinaryOp::Sub,
+                    left,
+                    right,
+                    ..
+                }) if left == dst => integral(*right).map(|step| -step),
+                _ => None,
+            };
+            if let (Some(init), Some(step)) = (integral(init), step) {
+                inductions.insert(*dst, (init, step));
+            }
+        }
+
+        // Past 2^24 a counter rounded to single precision stops counting, as it does in C, while
+        // the sums replacing its products would carry on. Only the counter the loop exits on, with
+        // a bound keeping it exact, is known to stay short of that.
+        let Some((counter, op, bound)) = exit_test(function, &found, &constants) else {
+            continue;
+        };
+        inductions.retain(|&value, &mut (init, step)| value == counter && stays_exact(op, bound, init, step));
+
+        // Find a multiplication of one by a whole constant, small enough that the products of
+        // exact counters are exact doubles
+        let factor = |value: Value| integral(value).filter(|factor| factor.abs() * EXACT <= MAX_EXACT_DOUBLE);
+        let mut reduced = None;
+        'search: for &block in &found.blocks {
+            for (index, inst) in function.blocks[block].insts.iter().enumerate() {
+                let Inst::Binary {
+                    dst,
+                    op: BinaryOp::Mul,
+                    left,
+                    right,
+                } = *inst
+                else {
+                    continue;
+                };
+                let (induction, factor) = match (inductions.get(&left), inductions.get(&right)) {
+                    (Some(induction), _) if factor(right).is_some() => (*induction, factor(right).unwrap()),
+                    (_, Some(induction)) if factor(left).is_some() => (*induction, factor(left).unwrap()),
+                    _ => continue,
+                };
+                reduced = Some((block, index, dst, induction, factor));
+                break 'search;
+            }
+        }
+        let Some((block, index, dst, (init, step), factor)) = reduced else {
+            continue;
+        };
+
+        let start = function.new_value();
+        let increment = function.new_value();
+        let current = function.new_value();
+        let next = function.new_value();
+
+        function.blocks[preheader].insts.push(Inst::Const {
+            dst: start,
+            value: init * factor,
+        });
+        function.blocks[preheader].insts.push(Inst::Const {
+            dst: increment,
+            value: step * factor,
+        });
+        function.blocks[found.header].insts.insert(
+            0,
+            Inst::Phi {
+                dst: current,
+                args: vec![(preheader, start), (latch, next)],
+            },
+        );
+        function.blocks[latch].insts.push(Inst::Binary {
+            dst: next,
+            op: BinaryOp::Add,
+            left: current,
+            right: increment,
+        });
+
+        // The phi went in at the start of the header, which moved the multiplication if it was there
+        let index = if block == found.header { index + 1 } else { index };
+        function.blocks[block].insts[index] = Inst::Copy { dst, src: current };
+        return true;
+    }
+
+    false
+}
+
+/// Return what the branch in a loop's header that leaves the loop tests: a value compared to a
+/// constant, with the comparison that keeps the loop going turned around to put the value first
+fn exit_test(function: &Function, found: &Loop, constants: &HashMap<Value, f64>) -> Option<(Value, BinaryOp, f64)> {
+    let header = &function.blocks[found.header];
+    let Terminator::Branch {
+        cond,
+        then_block,
+        else_block,
+    } = header.terminator
+    else {
+        return None;
+    };
+    if !found.blocks.contains(&then_block) || found.blocks.contains(&else_block) {
+        return None;
+    }
+
+    header.insts.iter().find_map(|inst| match *inst {
+        Inst::Binary { dst, op, left, right } if dst == cond => match (constants.get(&left), constants.get(&right)) {
+            (None, Some(&bound)) => Some((left, op, bound)),
+            (Some(&bound), None) => {
+                let op = match op {
+                    BinaryOp::Lt => BinaryOp::Gt,
+                    BinaryOp::LtEq => BinaryOp::GtEq,
+                    BinaryOp::Gt => BinaryOp::Lt,
+                    BinaryOp::GtEq => BinaryOp::LtEq,
+                    _ => return None,
+                };
+                Some((right, op, bound))
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Return true if a counter starting at `init` and stepping by `step` for as long as it compares to
+/// `bound` as `op` says only takes whole numbers single precision holds exactly
+fn stays_exact(op: BinaryOp, bound: f64, init: f64, step: f64) -> bool {
+    // The counter gets at most a step past the bound before the loop exits
+    let last = match op {
+        BinaryOp::Lt | BinaryOp::LtEq if step > 0.0 => bound + step,
+        BinaryOp::Gt | BinaryOp::GtEq if step < 0.0 => bound + step,
+        _ => return false,
+    };
+    init.abs() <= EXACT && last.abs() <= EXACT
+}
+
+/// Return the loop's preheader if it already has one: the only block entering it from outside,
+/// which goes nowhere else
+fn preheader(function: &Function, found: &Loop) -> Option<BlockId> {
+    match found.entries(&function.predecessors())[..] {
+        [entry] if function.blocks[entry].terminator == Terminator::Jump(found.header) => Some(entry),
+        _ => None,
+    }
+}
+
+/// Add a block between the blocks entering the loop from outside and its header,
+/// merging the values they pass to the header's phis. Return None for a loop nothing enters,
+/// which is one headed by the entry block.
+fn add_preheader(function: &mut Function, found: &Loop) -> Option<BlockId> {
+    let entries = found.entries(&function.predecessors());
+    if entries.is_empty() {
+        return None;
+    }
+
+    let preheader = function.blocks.len();
+    let mut insts = Vec::new();
+
+    for index in 0..function.blocks[found.header].insts.len() {
+        let Inst::Phi { dst, args } = &mut function.blocks[found.header].insts[index] else {
+            continue;
+        };
+        let dst = *dst;
+        let (outside, inside): (Vec<_>, Vec<_>) = args.iter().partition(|(block, _)| entries.contains(block));
+        *args = inside;
+
+        let value = if outside.iter().all(|(_, value)| *value == outside[0].1) {
+            outside[0].1
+        } else {
+            let merged = function.new_value();
+            if let Some(name) = function.names.get(&dst).cloned() {
+                function.names.insert(merged, name);
+            }
+            insts.push(Inst::Phi {
+                dst: merged,
+                args: outside,
+            });
+            merged
+        };
+
+        if let Inst::Phi { args, .. } = &mut function.blocks[found.header].insts[index] {
+            args.push((preheader, value));
+        }
+    }
+
+    for &entry in &entries {
+        for successor in function.blocks[entry].terminator.successors_mut() {
+            if *successor == found.header {
+                *successor = preheader;
+            }
+        }
+    }
+    function.blocks.push(Block {
+        insts,
+        terminator: Terminator::Jump(found.header),
+    });
+
+    Some(preheader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::OptLevel;
+    use crate::{interp, ir, parse, passes, Options};
+
+    /// Lower a program to IR in SSA form, with its copies propagated to keep it short
+    fn lower(source: &str) -> Function {
+        let mut function = ir(&parse(source).unwrap(), &Options::default());
+        passes::propagate_copies(&mut function);
+        passes::eliminate_dead_code(&mut function);
+        function
+    }
+
+    /// Check the IR of a program before and after running a loop pass on it, which has nothing
+    /// left to do the second time
+    fn check(source: &str, pass: fn(&mut Function) -> bool, before: &str, after: &str) {
+        let mut function = lower(source);
+        assert_eq!(function.to_string(), before);
+        assert!(pass(&mut function));
+        assert_eq!(function.to_string(), after);
+        assert!(!pass(&mut function));
+    }
+
+    #[test]
+    fn nested_loops_innermost_first() {
+        let source = "\
+LET i = 0
+WHILE i < 3 REPEAT
+    LET j = 0
+    WHILE j < 2 REPEAT
+        LET j = j + 1
+    ENDWHILE
+    LET i = i + 1
+ENDWHILE
+";
+        let loops = find_loops(&lower(source));
+        let found: Vec<_> = loops
+            .iter()
+            .map(|found| {
+                (
+                    found.header,
+                    found.blocks.iter().copied().collect(),
+                    found.latches.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(found, vec![(4, vec![4, 5], vec![5]), (1, vec![1, 2, 4, 5, 6], vec![6])]);
+    }
+
+    #[test]
+    fn hoisting() {
+        check(
+            "INPUT n\nLET i = 0\nWHILE i < 3 REPEAT\n    PRINT n * 2\n    LET i = i + 1\nENDWHILE\n",
+            hoist_invariants,
+            "\
+b0:
+    %0 = input
+    %n.1 = round %0
+    %i.2 = const 0
+    jump b1
+b1:
+    %i.14 = phi [b0: %i.2], [b2: %i.13]
+    %4 = const 3
+    %5 = lt %i.14, %4
+    branch %5, b2, b3
+b2:
+    %7 = const 2
+    %8 = mul %n.1, %7
+    %9 = round %8
+    print %9
+    %11 = const 1
+    %12 = add %i.14, %11
+    %i.13 = round %12
+    jump b1
+b3:
+    return
+",
+            "\
+b0:
+    %0 = input
+    %n.1 = round %0
+    %i.2 = const 0
+    %4 = const 3
+    %7 = const 2
+    %8 = mul %n.1, %7
+    %9 = round %8
+    %11 = const 1
+    jump b1
+b1:
+    %i.14 = phi [b0: %i.2], [b2: %i.13]
+    %5 = lt %i.14, %4
+    branch %5, b2, b3
+b2:
+    print %9
+    %12 = add %i.14, %11
+    %i.13 = round %12
+    jump b1
+b3:
+    return
+",
+        );
+    }
+
+    #[test]
+    fn strength_reduction() {
+        // j steps by 3 alongside i, which still steps through its rounding
+        check(
+            "LET i = 0\nWHILE i < 5 REPEAT\n    LET j = i * 3\n    PRINT j\n    LET i = i + 1\nENDWHILE\n",
+            reduce_strength,
+            "\
+b0:
+    %i.0 = const 0
+    jump b1
+b1:
+    %i.13 = phi [b0: %i.0], [b2: %i.12]
+    %2 = const 5
+    %3 = lt %i.13, %2
+    branch %3, b2, b3
+b2:
+    %5 = const 3
+    %6 = mul %i.13, %5
+    %j.7 = round %6
+    print %j.7
+    %10 = const 1
+    %11 = add %i.13, %10
+    %i.12 = round %11
+    jump b1
+b3:
+    return
+",
+            "\
+b0:
+    %i.0 = const 0
+    %19 = const 0
+    %20 = const 3
+    jump b1
+b1:
+    %21 = phi [b0: %19], [b2: %22]
+    %i.13 = phi [b0: %i.0], [b2: %i.12]
+    %2 = const 5
+    %3 = lt %i.13, %2
+    branch %3, b2, b3
+b2:
+    %5 = const 3
+    %6 = copy %21
+    %j.7 = round %6
+    print %j.7
+    %10 = const 1
+    %11 = add %i.13, %10
+    %i.12 = round %11
+    %22 = add %21, %20
+    jump b1
+b3:
+    return
+",
+        );
+    }
+
+    /// Run a program through the interpreter at -O0 and -O1, which have to print the same
+    fn run_both(source: &str) -> String {
+        let program = parse(source).unwrap();
+        let outputs: Vec<String> = [OptLevel::O0, OptLevel::O1]
+            .into_iter()
+            .map(|opt_level| {
+                let function = ir(
+                    &program,
+                    &Options {
+                        opt_level,
+                        ..Options::default()
+                    },
+                );
+                let mut output = Vec::new();
+                interp::run(&function, &mut &b""[..], &mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            })
+            .collect();
+        assert_eq!(outputs[0], outputs[1]);
+        outputs[0].clone()
+    }
+
+    #[test]
+    fn counters_past_single_precision() {
+        // i stops counting at 2^24, so the products stop growing too
+        let untested = "\
+LET i = 16777214
+LET n = 0
+WHILE n < 6 REPEAT
+    PRINT i * 2
+    LET i = i + 1
+    LET n = n + 1
+ENDWHILE
+";
+        assert!(!reduce_strength(&mut lower(untested)));
+        assert_eq!(
+            run_both(untested),
+            "33554428.00\n33554430.00\n33554432.00\n33554432.00\n33554432.00\n33554432.00\n"
+        );
+
+        // Tested by the loop's exit, but against a bound it never reaches
+        let unbounded = "LET i = 16777214\nWHILE i < 16777220 REPEAT\n    PRINT i * 2\n    LET i = i + 1\nENDWHILE\n";
+        assert!(!reduce_strength(&mut lower(unbounded)));
+
+        let bounded = "LET i = 16777200\nWHILE i < 16777215 REPEAT\n    PRINT i * 2\n    LET i = i + 1\nENDWHILE\n";
+        assert!(reduce_strength(&mut lower(bounded)));
+        assert!(run_both(bounded).ends_with("33554428.00\n"));
+    }
+}
@@ -0,0 +1,204 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, StatementKind, UnaryOp};
+use super::cfg::{self, Cfg};
+use super::ir::{Block, Function, Inst, Terminator, Value};
+use std::collections::HashMap;
+
+/// Lower the control flow graph of a program into IR, one IR block per CFG block.
+/// Variables are read and written with loads and stores; SSA construction removes them.
+///
+/// Values are rounded to single precision wherever the C program would have a float: when a
+/// variable is assigned or read from the input, when a number is printed, and after arithmetic
+/// between floats.
+pub fn lower(cfg: &Cfg) -> Function {
+    let mut lowering = Lowering {
+        function: Function {
+            blocks: Vec::new(),
+            value_count: 0,
+            names: HashMap::new(),
+        },
+        insts: Vec::new(),
+    };
+
+    // Blocks keep their CFG numbering, so the CFG entry is already block 0
+    debug_assert_eq!(cfg.entry, 0);
+
+    for block in &cfg.blocks {
+        for statement in &block.statements {
+            match &statement.kind {
+                StatementKind::Print(Printable::String(text)) => {
+                    lowering.insts.push(Inst::PrintStr { text: text.clone() });
+                }
+                StatementKind::Print(Printable::Expression(expression)) => {
+                    let src = lowering.float(expression);
+                    lowering.insts.push(Inst::Print { src });
+                }
+                StatementKind::Let { name, value } => {
+                    let src = lowering.float(value);
+                    lowering.insts.push(Inst::Store {
+                        var: name.name.clone(),
+                        src,
+                    });
+                }
+                StatementKind::Input(name) => {
+                    let number = lowering.function.new_value();
+                    lowering.insts.push(Inst::Input { dst: number });
+                    let src = lowering.round(number);
+                    lowering.insts.push(Inst::Store {
+                        var: name.name.clone(),
+                        src,
+                    });
+                }
+                _ => unreachable!("control flow statements end blocks"),
+            }
+        }
+
+        let terminator = match &block.terminator {
+            cfg::Terminator::Jump(target) | cfg::Terminator::Goto { target, .. } => Terminator::Jump(*target),
+            cfg::Terminator::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => Terminator::Branch {
+                cond: lowering.expression(condition),
+                then_block: *then_block,
+                else_block: *else_block,
+            },
+            cfg::Terminator::Return => Terminator::Return,
+        };
+
+        let insts = std::mem::take(&mut lowering.insts);
+        lowering.function.blocks.push(Block { insts, terminator });
+    }
+
+    lowering.function
+}
+
+struct Lowering {
+    function: Function,
+    /// Instructions of the block being lowered
+    insts: Vec<Inst>,
+}
+
+impl Lowering {
+    /// Lower an expression whose result goes into a float, rounding it unless it's a float already
+    fn float(&mut self, expression: &Expression) -> Value {
+        match expression.kind {
+            // As the C compiler would, round numbers right away
+            ExpressionKind::Number(value) => {
+                let dst = self.function.new_value();
+                self.insts.push(Inst::Const {
+                    dst,
+                    value: value as f32 as f64,
+                });
+                dst
+            }
+            _ if expression.is_float() => self.expression(expression),
+            _ => {
+                let value = self.expression(expression);
+                self.round(value)
+            }
+        }
+    }
+
+    fn round(&mut self, src: Value) -> Value {
+        let dst = self.function.new_value();
+        self.insts.push(Inst::Round { dst, src });
+        dst
+    }
+
+    /// Lower an expression into instructions, returning the value holding its result
+    fn expression(&mut self, expression: &Expression) -> Value {
+        match &expression.kind {
+            ExpressionKind::Number(value) => {
+                let dst = self.function.new_value();
+                self.insts.push(Inst::Const { dst, value: *value });
+                dst
+            }
+            ExpressionKind::Ident(name) => {
+                let dst = self.function.new_value();
+                self.insts.push(Inst::Load { dst, var: name.clone() });
+                dst
+            }
+            ExpressionKind::Unary(UnaryOp::Plus, operand) => self.expression(operand),
+            ExpressionKind::Unary(UnaryOp::Minus, operand) => {
+                let src = self.expression(operand);
+                let dst = self.function.new_value();
+                self.insts.push(Inst::Neg { dst, src });
+                dst
+            }
+            ExpressionKind::Binary(op, left_operand, right_operand) => {
+                let left = self.expression(left_operand);
+                let right = self.expression(right_operand);
+                let dst = self.function.new_value();
+                self.insts.push(Inst::Binary {
+                    dst,
+                    op: *op,
+                    left,
+                    right,
+                });
+
+                // Arithmetic between floats is done in single precision
+                let arithmetic = matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div);
+                if arithmetic && expression.is_float() {
+                    self.round(dst)
+                } else {
+                    dst
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn rounds_where_c_has_a_float() {
+        let program = parse("LET x = 123456789\nLET y = x * 3\nPRINT y + 0.5\nINPUT x\n").unwrap();
+        // The number is rounded as it's lowered, and x * 3 and y + 0.5 are doubles until they're
+        // stored or printed
+        assert_eq!(
+            lower(&Cfg::new(&program)).to_string(),
+            "\
+b0:
+    %0 = const 123456792
+    store x, %0
+    %1 = load x
+    %2 = const 3
+    %3 = mul %1, %2
+    %4 = round %3
+    store y, %4
+    %5 = load y
+    %6 = const 0.5
+    %7 = add %5, %6
+    %8 = round %7
+    print %8
+    %9 = input
+    %10 = round %9
+    store x, %10
+    return
+"
+        );
+    }
+
+    #[test]
+    fn rounds_arithmetic_between_floats() {
+        let program = parse("LET x = 1\nPRINT x / x\n").unwrap();
+        assert_eq!(
+            lower(&Cfg::new(&program)).to_string(),
+            "\
+b0:
+    %0 = const 1
+    store x, %0
+    %1 = load x
+    %2 = load x
+    %3 = div %1, %2
+    %4 = round %3
+    print %4
+    return
+"
+        );
+    }
+}
@@ -0,0 +1,626 @@
+use super::ast::{Statement, StatementKind};
+use super::diagnostic::{Diagnostic, Severity};
+use super::json::Value;
+use super::lex::Lexer;
+use super::parse::Parser;
+use super::resolve::Resolver;
+use super::span::Span;
+use super::symbols::{Symbol, SymbolKind, SymbolTable, Type};
+use super::token::{Token, TokenType};
+use super::Options;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: f64 = -32700.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_PARAMS: f64 = -32602.0;
+
+/// LSP completion item kinds
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+const COMPLETION_REFERENCE: usize = 18;
+
+/// A language server speaking the Language Server Protocol, keeping the text of the open documents
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve messages from `input` until the client says to exit or closes it, writing responses
+    /// and notifications to `output`. Returns the exit status: 0 if the client asked the server to
+    /// shut down first, as it should, and 1 otherwise.
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<i32> {
+        while let Some(message) = read_message(input)? {
+            let message = match Value::parse(&message) {
+                Ok(message) => message,
+                Err(why) => {
+                    send(
+                        output,
+                        response(Value::Null, Err((PARSE_ERROR, format!("parse error: {}", why)))),
+                    )?;
+                    continue;
+                }
+            };
+            let method = message.get(&["method"]).and_then(Value::as_str).unwrap_or("");
+            if method == "exit" {
+                return Ok(if self.shut_down { 0 } else { 1 });
+            }
+
+            let params = message.get(&["params"]).unwrap_or(&Value::Null);
+            let (result, notifications) = self.handle(method, params);
+            for notification in notifications {
+                send(output, notification)?;
+            }
+            // Notifications have no id and get no response
+            if let Some(id) = message.get(&["id"]) {
+                send(output, response(id.clone(), result))?;
+            }
+        }
+        Ok(1)
+    }
+
+    /// Handle a request or notification, returning the result of a request along with any
+    /// notifications to send first
+    fn handle(&mut self, method: &str, params: &Value) -> (Result<Value, (f64, String)>, Vec<Value>) {
+        let uri = params
+            .get(&["textDocument", "uri"])
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_owned();
+        let mut notifications = Vec::new();
+
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .get(&["textDocument", "text"])
+                    .and_then(Value::as_str)
+                    .unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_owned());
+                notifications.push(self.diagnostics(&uri));
+                Ok(Value::Null)
+            }
+            "textDocument/didChange" => {
+                // The server asks for the whole text on every change
+                let changes = params.get(&["contentChanges"]).and_then(Value::as_array).unwrap_or(&[]);
+                if let Some(text) = changes
+                    .last()
+                    .and_then(|change| change.get(&["text"]))
+                    .and_then(Value::as_str)
+                {
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                notifications.push(self.diagnostics(&uri));
+                Ok(Value::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                notifications.push(publish(&uri, Vec::new()));
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => Ok(self
+                .symbol_at(&uri, params)
+                .map_or(Value::Null, |(source, symbol)| location(&uri, source, symbol.declared))),
+            "textDocument/hover" => Ok(self.hover(&uri, params).unwrap_or(Value::Null)),
+            "textDocument/completion" => Ok(self.completion(&uri)),
+            "textDocument/rename" => self.rename(&uri, params),
+            _ if method.starts_with("$/") || method == "initialized" => Ok(Value::Null),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        };
+        (result, notifications)
+    }
+
+    /// A notification publishing the errors and warnings in a document
+    fn diagnostics(&self, uri: &str) -> Value {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        let diagnostics = match super::check(source, &Options::default()) {
+            Ok((_, warnings)) => warnings,
+            Err(diagnostics) => diagnostics,
+        };
+        let diagnostics = diagnostics
+            .iter()
+            .map(|diagnostic| lsp_diagnostic(uri, source, diagnostic))
+            .collect();
+        publish(uri, diagnostics)
+    }
+
+    /// Return the document and the variable or label named at the position the request gives,
+    /// if the document parses
+    fn symbol_at(&self, uri: &str, params: &Value) -> Option<(&str, Symbol)> {
+        let source = self.documents.get(uri)?;
+        let offset = offset(source, params.get(&["position"])?);
+        let (_, symbols) = resolve(source)?;
+        let symbol = symbols.symbols().find(|symbol| {
+            std::iter::once(symbol.declared)
+                .chain(symbol.uses.iter().map(|using| using.span))
+                .any(|span| span.start <= offset && offset <= span.end)
+        })?;
+        Some((source, symbol.clone()))
+    }
+
+    /// What a variable or label is, along with the doc comment above a label
+    fn hover(&self, uri: &str, params: &Value) -> Option<Value> {
+        let (source, symbol) = self.symbol_at(uri, params)?;
+        let mut text = match symbol.kind {
+            SymbolKind::Variable => format!("variable `{}`: {}", symbol.name, type_name(symbol.ty)),
+            SymbolKind::Label => format!("label `{}`", symbol.name),
+        };
+        if symbol.kind == SymbolKind::Label {
+            let (statements, _) = resolve(source)?;
+            if let Some(doc) = label_doc(&statements, &symbol.name) {
+                text.push_str("\n\n");
+                text.push_str(&doc);
+            }
+        }
+
+        Some(Value::object(vec![
+            (
+                "contents",
+                Value::object(vec![("kind", "markdown".into()), ("value", text.into())]),
+            ),
+            (
+                "range",
+                range(source, span_at(&symbol, offset(source, params.get(&["position"])?))),
+            ),
+        ]))
+    }
+
+    /// The keywords, and the variables and labels of the document if it parses
+    fn completion(&self, uri: &str) -> Value {
+        let mut items: Vec<Value> = Token::KEYWORDS
+            .iter()
+            .map(|keyword| completion_item(keyword, COMPLETION_KEYWORD, "keyword"))
+            .collect();
+
+        if let Some((_, symbols)) = self.documents.get(uri).and_then(|source| resolve(source)) {
+            for symbol in symbols.symbols() {
+                items.push(match symbol.kind {
+                    SymbolKind::Variable => completion_item(&symbol.name, COMPLETION_VARIABLE, "variable"),
+                    SymbolKind::Label => completion_item(&symbol.name, COMPLETION_REFERENCE, "label"),
+                });
+            }
+        }
+        Value::Array(items)
+    }
+
+    /// Rename a variable or label everywhere it's declared and used. A name already taken by
+    /// another variable or label of the same kind is refused, since the two would merge.
+    fn rename(&self, uri: &str, params: &Value) -> Result<Value, (f64, String)> {
+        let new_name = params.get(&["newName"]).and_then(Value::as_str).unwrap_or("");
+        if !is_identifier(new_name) {
+            return Err((INVALID_PARAMS, format!("{} isn't a valid name", new_name)));
+        }
+        let Some((source, symbol)) = self.symbol_at(uri, params) else {
+            return Ok(Value::Null);
+        };
+        let taken =
+            resolve(source).is_some_and(|(_, symbols)| symbols.lookup(symbol.scope, symbol.kind, new_name).is_some());
+        if taken && new_name != symbol.name {
+            let kind = match symbol.kind {
+                SymbolKind::Variable => "variable",
+                SymbolKind::Label => "label",
+            };
+            return Err((INVALID_PARAMS, format!("there's already a {} named {}", kind, new_name)));
+        }
+
+        let edits = std::iter::once(symbol.declared)
+            .chain(symbol.uses.iter().map(|using| using.span))
+            .map(|span| Value::object(vec![("range", range(source, span)), ("newText", new_name.into())]))
+            .collect();
+        Ok(Value::object(vec![(
+            "changes",
+            Value::Object(vec![(uri.to_owned(), Value::Array(edits))]),
+        )]))
+    }
+}
+
+/// What the server can do, in answer to `initialize`
+fn capabilities() -> Value {
+    Value::object(vec![
+        (
+            "capabilities",
+            Value::object(vec![
+                // The whole text is sent on every change
+                ("textDocumentSync", 1.into()),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("completionProvider", Value::object(vec![])),
+                ("renameProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Value::object(vec![
+                ("name", "teeny".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+/// Parse the source and resolve its names, returning its statements and symbols, or None if it
+/// doesn't parse. Names that are used wrongly still have symbols for the rest of their uses.
+fn resolve(source: &str) -> Option<(Vec<Statement>, SymbolTable)> {
+    let program = Parser::new(Lexer::new(source))
+        .and_then(|mut parser| parser.program())
+        .ok()?;
+    let mut resolver = Resolver::new();
+    resolver.program(&program);
+    Some((program.statements, resolver.symbols))
+}
+
+/// Find the doc comment above the LABEL statement declaring a label
+fn label_doc(statements: &[Statement], name: &str) -> Option<String> {
+    statements.iter().find_map(|statement| match &statement.kind {
+        StatementKind::Label { name: label, doc } if label.name == name => doc.clone(),
+        StatementKind::If { body, .. } | StatementKind::While { body, .. } => label_doc(body, name),
+        _ => None,
+    })
+}
+
+fn type_name(ty: Option<Type>) -> &'static str {
+    match ty {
+        // Every variable is a float, so one without a type recorded is too
+        Some(Type::Float) | None => "float",
+    }
+}
+
+/// The span of the symbol's name that an offset falls in
+fn span_at(symbol: &Symbol, offset: usize) -> Span {
+    std::iter::once(symbol.declared)
+        .chain(symbol.uses.iter().map(|using| using.span))
+        .find(|span| span.start <= offset && offset <= span.end)
+        .unwrap_or(symbol.declared)
+}
+
+/// Return true if the text lexes as a single identifier, and so can name a variable or label.
+/// The lexer ends the text with a newline of its own.
+fn is_identifier(text: &str) -> bool {
+    if text.contains(char::is_whitespace) {
+        return false;
+    }
+    let kinds: Vec<TokenType> = match super::tokenize(text) {
+        Ok(tokens) => tokens.into_iter().map(|token| token.kind).collect(),
+        Err(_) => return false,
+    };
+    kinds == [TokenType::Ident, TokenType::Newline, TokenType::Eof]
+}
+
+fn lsp_diagnostic(uri: &str, source: &str, diagnostic: &Diagnostic) -> Value {
+    let severity: usize = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    let mut members = vec![
+        ("range", range(source, diagnostic.span)),
+        ("severity", severity.into()),
+        ("source", "teeny".into()),
+        ("message", diagnostic.message.as_str().into()),
+    ];
+    if let Some(code) = diagnostic.code {
+        members.push(("code", code.into()));
+    }
+    if !diagnostic.labels.is_empty() {
+        let related = diagnostic
+            .labels
+            .iter()
+            .map(|label| {
+                Value::object(vec![
+                    ("location", location(uri, source, label.span)),
+                    ("message", label.message.as_str().into()),
+                ])
+            })
+            .collect();
+        members.push(("relatedInformation", Value::Array(related)));
+    }
+    Value::object(members)
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    notification(
+        "textDocument/publishDiagnostics",
+        Value::object(vec![("uri", uri.into()), ("diagnostics", Value::Array(diagnostics))]),
+    )
+}
+
+fn completion_item(label: &str, kind: usize, detail: &str) -> Value {
+    Value::object(vec![
+        ("label", label.into()),
+        ("kind", kind.into()),
+        ("detail", detail.into()),
+    ])
+}
+
+fn location(uri: &str, source: &str, span: Span) -> Value {
+    Value::object(vec![("uri", uri.into()), ("range", range(source, span))])
+}
+
+fn range(source: &str, span: Span) -> Value {
+    Value::object(vec![
+        ("start", position(source, span.start)),
+        ("end", position(source, span.end)),
+    ])
+}
+
+/// The LSP position of a character offset in the source. LSP counts columns in UTF-16 code units.
+fn position(source: &str, offset: usize) -> Value {
+    let mut line = 0;
+    let mut character = 0;
+    for c in source.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    Value::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+/// The character offset in the source of an LSP position
+fn offset(source: &str, position: &Value) -> usize {
+    let field = |name| position.get(&[name]).and_then(Value::as_f64).unwrap_or(0.0) as usize;
+    let (line, character) = (field("line"), field("character"));
+
+    let mut offset = 0;
+    let mut lines = source.split_inclusive('\n');
+    for text in lines.by_ref().take(line) {
+        offset += text.chars().count();
+    }
+    let mut units = 0;
+    for c in lines.next().unwrap_or("").chars() {
+        if units >= character || c == '\n' {
+            break;
+        }
+        units += c.len_utf16();
+        offset += 1;
+    }
+    offset
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    Value::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn response(id: Value, result: Result<Value, (f64, String)>) -> Value {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err((code, message)) => (
+            "error",
+            Value::object(vec![("code", Value::Number(code)), ("message", message.into())]),
+        ),
+    };
+    Value::object(vec![("jsonrpc", "2.0".into()), ("id", id), outcome])
+}
+
+/// Read one message: headers, of which only `Content-Length` matters, a blank line, then that many
+/// bytes of JSON. Returns None at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+fn send(output: &mut impl Write, message: Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Frame each message the way a client would
+    fn messages(messages: &[Value]) -> Cursor<Vec<u8>> {
+        let mut input = Vec::new();
+        for message in messages {
+            send(&mut input, message.clone()).unwrap();
+        }
+        Cursor::new(input)
+    }
+
+    fn request(id: usize, method: &str, params: Value) -> Value {
+        Value::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn open(text: &str) -> Value {
+        notification(
+            "textDocument/didOpen",
+            Value::object(vec![(
+                "textDocument",
+                Value::object(vec![("uri", "file:///a.teeny".into()), ("text", text.into())]),
+            )]),
+        )
+    }
+
+    fn at(line: usize, character: usize) -> Value {
+        Value::object(vec![
+            ("textDocument", Value::object(vec![("uri", "file:///a.teeny".into())])),
+            (
+                "position",
+                Value::object(vec![("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    fn rename(line: usize, character: usize, new_name: &str) -> Value {
+        let Value::Object(mut members) = at(line, character) else {
+            unreachable!()
+        };
+        members.push(("newName".to_owned(), new_name.into()));
+        Value::Object(members)
+    }
+
+    /// Run the server over the messages, returning its exit status and everything it sent
+    fn serve(input: &[Value]) -> (i32, Vec<Value>) {
+        let mut output = Vec::new();
+        let status = Server::new().run(&mut messages(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut sent = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            sent.push(Value::parse(&message).unwrap());
+        }
+        (status, sent)
+    }
+
+    /// The response to the request with the id
+    fn response_to(sent: &[Value], id: usize) -> &Value {
+        sent.iter()
+            .find(|message| message.get(&["id"]) == Some(&Value::from(id)))
+            .expect("the request was answered")
+    }
+
+    #[test]
+    fn initialize_and_shut_down() {
+        let (status, sent) = serve(&[
+            request(1, "initialize", Value::object(vec![])),
+            request(2, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+        assert_eq!(status, 0);
+        assert_eq!(
+            response_to(&sent, 1).get(&["result", "capabilities", "renameProvider"]),
+            Some(&Value::Bool(true))
+        );
+        assert_eq!(response_to(&sent, 2).get(&["result"]), Some(&Value::Null));
+    }
+
+    #[test]
+    fn exit_without_shutdown_fails() {
+        let (status, sent) = serve(&[notification("exit", Value::Null)]);
+        assert_eq!(status, 1);
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn open_publishes_diagnostics() {
+        let (_, sent) = serve(&[open("PRINT x\n")]);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].get(&["method"]).and_then(Value::as_str),
+            Some("textDocument/publishDiagnostics")
+        );
+        let diagnostics = sent[0]
+            .get(&["params", "diagnostics"])
+            .and_then(Value::as_array)
+            .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get(&["severity"]), Some(&Value::from(1)));
+        assert_eq!(
+            diagnostics[0].get(&["range", "start"]),
+            Some(&Value::object(vec![("line", 0.into()), ("character", 6.into())]))
+        );
+    }
+
+    #[test]
+    fn definition_and_hover() {
+        let (_, sent) = serve(&[
+            open("## The top\nLABEL top\nGOTO top\n"),
+            request(1, "textDocument/definition", at(2, 6)),
+            request(2, "textDocument/hover", at(2, 6)),
+        ]);
+        assert_eq!(
+            response_to(&sent, 1).get(&["result", "range", "start"]),
+            Some(&Value::object(vec![("line", 1.into()), ("character", 6.into())]))
+        );
+        assert_eq!(
+            response_to(&sent, 2)
+                .get(&["result", "contents", "value"])
+                .and_then(Value::as_str),
+            Some("label `top`\n\nThe top")
+        );
+    }
+
+    #[test]
+    fn rename_edits_every_use() {
+        let (_, sent) = serve(&[
+            open("LET a = 1\nPRINT a\n"),
+            request(1, "textDocument/rename", rename(1, 6, "b")),
+        ]);
+        let edits = response_to(&sent, 1)
+            .get(&["result", "changes", "file:///a.teeny"])
+            .and_then(Value::as_array)
+            .unwrap();
+        assert_eq!(edits.len(), 2);
+        assert!(edits
+            .iter()
+            .all(|edit| edit.get(&["newText"]) == Some(&Value::from("b"))));
+    }
+
+    #[test]
+    fn rename_refuses_a_taken_or_invalid_name() {
+        let (_, sent) = serve(&[
+            open("LET a = 1\nLET b = 2\nPRINT a + b\n"),
+            request(1, "textDocument/rename", rename(0, 4, "b")),
+            request(2, "textDocument/rename", rename(0, 4, "PRINT")),
+            request(3, "textDocument/rename", rename(0, 4, "a")),
+        ]);
+        for id in [1, 2] {
+            assert_eq!(
+                response_to(&sent, id).get(&["error", "code"]),
+                Some(&Value::Number(INVALID_PARAMS))
+            );
+        }
+        // Renaming a symbol to its own name changes nothing, but isn't an error
+        assert!(response_to(&sent, 3).get(&["result", "changes"]).is_some());
+    }
+
+    #[test]
+    fn bad_messages_get_errors() {
+        let mut input = messages(&[request(2, "textDocument/unknown", Value::Null)]).into_inner();
+        let mut bad = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+        bad.append(&mut input);
+
+        let mut output = Vec::new();
+        Server::new().run(&mut Cursor::new(bad), &mut output).unwrap();
+        let mut output = Cursor::new(output);
+        let parse_error = Value::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        let unknown = Value::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+
+        assert_eq!(parse_error.get(&["error", "code"]), Some(&Value::Number(PARSE_ERROR)));
+        assert_eq!(unknown.get(&["error", "code"]), Some(&Value::Number(METHOD_NOT_FOUND)));
+    }
+}
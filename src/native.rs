@@ -0,0 +1,350 @@
+use super::ast::BinaryOp;
+use super::ir::{Function, Inst, Terminator, Value};
+use super::regalloc::{self, Allocation, Location};
+use super::ssa;
+use super::x86::{Cond, Gpr, MInst, Program, SseOp, Xmm};
+
+/// Values are allocated to xmm0 to xmm12. The others are scratch registers for loading spilled
+/// operands, holding constants and computing results that go to the stack.
+const REGISTERS: usize = 13;
+const OPERAND: Xmm = Xmm(13);
+const SECOND_OPERAND: Xmm = Xmm(14);
+const RESULT: Xmm = Xmm(15);
+
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+/// Compile a function in SSA form to x86-64 machine code
+pub fn compile(function: &Function) -> Program {
+    let mut function = function.clone();
+    ssa::destruct(&mut function);
+    let allocation = regalloc::allocate(&function, REGISTERS);
+
+    // Spill slots come first in the frame, then a slot per register to save it across calls
+    let slots = allocation.stack_slots + REGISTERS;
+    let frame_size = (slots * 8).next_multiple_of(16) as u32;
+
+    let mut selector = Selector {
+        allocation: &allocation,
+        code: vec![
+            MInst::Push(Gpr::Rbp),
+            MInst::MovGpr {
+                dst: Gpr::Rbp,
+                src: Gpr::Rsp,
+            },
+            MInst::SubImm {
+                dst: Gpr::Rsp,
+                imm: frame_size,
+            },
+        ],
+        strings: Vec::new(),
+    };
+
+    for (index, &block) in allocation.order.iter().enumerate() {
+        let next = allocation.order.get(index + 1).copied();
+        selector.code.push(MInst::Label(block));
+
+        for (position, inst) in function.blocks[block].insts.iter().enumerate() {
+            let saved = allocation.live_across_calls.get(&(block, position));
+            selector.inst(inst, saved.map_or(&[][..], Vec::as_slice));
+        }
+
+        match function.blocks[block].terminator {
+            Terminator::Jump(target) => {
+                if Some(target) != next {
+                    selector.code.push(MInst::Jmp(target));
+                }
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                // Anything but zero is true, including NaN
+                let cond = selector.operand(cond, OPERAND);
+                selector.code.push(MInst::Sse {
+                    op: SseOp::Xor,
+                    dst: SECOND_OPERAND,
+                    src: SECOND_OPERAND,
+                });
+                selector.code.push(MInst::Ucomisd {
+                    left: cond,
+                    right: SECOND_OPERAND,
+                });
+                selector.code.push(MInst::Jcc {
+                    cond: Cond::Parity,
+                    target: then_block,
+                });
+                selector.code.push(MInst::Jcc {
+                    cond: Cond::NotEqual,
+                    target: then_block,
+                });
+                if Some(else_block) != next {
+                    selector.code.push(MInst::Jmp(else_block));
+                }
+            }
+            Terminator::Return => {
+                selector.code.push(MInst::Zero(Gpr::Rax));
+                selector.code.push(MInst::Leave);
+                selector.code.push(MInst::Ret);
+            }
+        }
+    }
+
+    Program {
+        code: selector.code,
+        strings: selector.strings,
+    }
+}
+
+struct Selector<'a> {
+    allocation: &'a Allocation,
+    code: Vec<MInst>,
+    strings: Vec<String>,
+}
+
+impl Selector<'_> {
+    /// Select the machine instructions for an instruction.
+    /// `saved` are the registers to save around it if it calls into the runtime.
+    fn inst(&mut self, inst: &Inst, saved: &[usize]) {
+        match inst {
+            Inst::Const { dst, value } => {
+                let result = self.result(*dst);
+                self.constant(result, value.to_bits());
+                self.store(*dst, result);
+            }
+            Inst::Copy { dst, src } => {
+                if self.allocation.locations[dst] == self.allocation.locations[src] {
+                    return;
+                }
+                let src = self.operand(*src, OPERAND);
+                self.move_to(*dst, src);
+            }
+            Inst::Neg { dst, src } => {
+                let src = self.operand(*src, OPERAND);
+                let result = self.result(*dst);
+                self.copy(result, src);
+                self.constant(SECOND_OPERAND, SIGN_BIT);
+                self.code.push(MInst::Sse {
+                    op: SseOp::Xor,
+                    dst: result,
+                    src: SECOND_OPERAND,
+                });
+                self.store(*dst, result);
+            }
+            Inst::Binary { dst, op, left, right } => self.binary(*dst, *op, *left, *right),
+            Inst::Round { dst, src } => {
+                let src = self.operand(*src, OPERAND);
+                let result = self.result(*dst);
+                self.code.push(MInst::Cvtsd2ss { dst: result, src });
+                self.code.push(MInst::Cvtss2sd {
+                    dst: result,
+                    src: result,
+                });
+                self.store(*dst, result);
+            }
+            Inst::Print { src } => {
+                self.save(saved);
+                let src = self.operand(*src, OPERAND);
+                self.copy(Xmm(0), src);
+                self.code.push(MInst::PrintNumber);
+                self.restore(saved);
+            }
+            Inst::PrintStr { text } => {
+                self.save(saved);
+                self.code.push(MInst::PrintString(self.strings.len()));
+                self.strings.push(text.clone());
+                self.restore(saved);
+            }
+            Inst::Input { dst } => {
+                self.save(saved);
+                self.code.push(MInst::Input);
+                // The result isn't live across the call, so restoring can't overwrite it
+                self.move_to(*dst, Xmm(0));
+                self.restore(saved);
+            }
+            Inst::Phi { .. } | Inst::Load { .. } | Inst::Store { .. } => {
+                unreachable!("the function is out of SSA form")
+            }
+        }
+    }
+
+    fn binary(&mut self, dst: Value, op: BinaryOp, left: Value, right: Value) {
+        let arithmetic = match op {
+            BinaryOp::Add => Some(SseOp::Add),
+            BinaryOp::Sub => Some(SseOp::Sub),
+            BinaryOp::Mul => Some(SseOp::Mul),
+            BinaryOp::Div => Some(SseOp::Div),
+            _ => None,
+        };
+        let result = self.result(dst);
+
+        match arithmetic {
+            Some(op) => {
+                let left = self.operand(left, OPERAND);
+                let right = self.operand(right, SECOND_OPERAND);
+                self.copy(result, left);
+                self.code.push(MInst::Sse {
+                    op,
+                    dst: result,
+                    src: right,
+                });
+            }
+            None => {
+                // cmpsd only tests for equal, less and less or equal and their negations,
+                // so greater than swaps the operands
+                let (predicate, left, right) = match op {
+                    BinaryOp::EqEq => (0, left, right),
+                    BinaryOp::Lt => (1, left, right),
+                    BinaryOp::LtEq => (2, left, right),
+                    BinaryOp::NotEq => (4, left, right),
+                    BinaryOp::Gt => (1, right, left),
+                    BinaryOp::GtEq => (2, right, left),
+                    _ => unreachable!(),
+                };
+                let left = self.operand(left, OPERAND);
+                let right = self.operand(right, SECOND_OPERAND);
+                self.copy(result, left);
+                self.code.push(MInst::Cmpsd {
+                    dst: result,
+                    src: right,
+                    predicate,
+                });
+
+                // Turn the mask of all ones into 1.0
+                self.constant(SECOND_OPERAND, 1f64.to_bits());
+                self.code.push(MInst::Sse {
+                    op: SseOp::And,
+                    dst: result,
+                    src: SECOND_OPERAND,
+                });
+            }
+        }
+
+        self.store(dst, result);
+    }
+
+    /// Return the register holding a value, loading it into `scratch` if it was spilled
+    fn operand(&mut self, value: Value, scratch: Xmm) -> Xmm {
+        match self.allocation.locations[&value] {
+            Location::Register(register) => Xmm(register as u8),
+            Location::Stack(slot) => {
+                self.code.push(MInst::MovsdLoad {
+                    dst: scratch,
+                    offset: slot_offset(slot),
+                });
+                scratch
+            }
+        }
+    }
+
+    /// Return the register to compute a value in, which is a scratch register if it was spilled
+    fn result(&self, value: Value) -> Xmm {
+        match self.allocation.locations[&value] {
+            Location::Register(register) => Xmm(register as u8),
+            Location::Stack(_) => RESULT,
+        }
+    }
+
+    /// Write a value computed by `result` to its stack slot if it was spilled
+    fn store(&mut self, value: Value, register: Xmm) {
+        if let Location::Stack(slot) = self.allocation.locations[&value] {
+            self.code.push(MInst::MovsdStore {
+                offset: slot_offset(slot),
+                src: register,
+            });
+        }
+    }
+
+    /// Move a register into wherever a value lives
+    fn move_to(&mut self, value: Value, src: Xmm) {
+        match self.allocation.locations[&value] {
+            Location::Register(register) => self.copy(Xmm(register as u8), src),
+            Location::Stack(slot) => self.code.push(MInst::MovsdStore {
+                offset: slot_offset(slot),
+                src,
+            }),
+        }
+    }
+
+    fn copy(&mut self, dst: Xmm, src: Xmm) {
+        if dst != src {
+            self.code.push(MInst::MovsdReg { dst, src });
+        }
+    }
+
+    fn constant(&mut self, dst: Xmm, bits: u64) {
+        self.code.push(MInst::MovImm {
+            dst: Gpr::Rax,
+            imm: bits,
+        });
+        self.code.push(MInst::MovqToXmm { dst, src: Gpr::Rax });
+    }
+
+    /// Save registers to their slots before a call into the runtime, which clobbers them all
+    fn save(&mut self, registers: &[usize]) {
+        for &register in registers {
+            self.code.push(MInst::MovsdStore {
+                offset: slot_offset(self.allocation.stack_slots + register),
+                src: Xmm(register as u8),
+            });
+        }
+    }
+
+    fn restore(&mut self, registers: &[usize]) {
+        for &register in registers {
+            self.code.push(MInst::MovsdLoad {
+                dst: Xmm(register as u8),
+                offset: slot_offset(self.allocation.stack_slots + register),
+            });
+        }
+    }
+}
+
+/// Return the offset from rbp of a stack slot
+fn slot_offset(slot: usize) -> i32 {
+    -8 * (slot as i32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parse, Options};
+
+    #[test]
+    fn saves_registers_around_calls() {
+        let function = ir(
+            &parse("INPUT a\nINPUT b\nPRINT a\nPRINT a + b\n").unwrap(),
+            &Options::default(),
+        );
+        let code = compile(&function).code;
+
+        // a and b are in xmm0 and xmm1, which printf clobbers, and are used after the first PRINT
+        let print = code.iter().position(|inst| *inst == MInst::PrintNumber).unwrap();
+        assert_eq!(
+            code[print - 2..print + 3],
+            [
+                MInst::MovsdStore {
+                    offset: -8,
+                    src: Xmm(0),
+                },
+                MInst::MovsdStore {
+                    offset: -16,
+                    src: Xmm(1),
+                },
+                MInst::PrintNumber,
+                MInst::MovsdLoad {
+                    dst: Xmm(0),
+                    offset: -8,
+                },
+                MInst::MovsdLoad {
+                    dst: Xmm(1),
+                    offset: -16,
+                },
+            ]
+        );
+
+        // Nothing is used after the last one
+        let last = code.iter().rposition(|inst| *inst == MInst::PrintNumber).unwrap();
+        assert_eq!(code[last + 1..], [MInst::Zero(Gpr::Rax), MInst::Leave, MInst::Ret]);
+    }
+}
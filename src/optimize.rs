@@ -0,0 +1,325 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, Program, Statement, StatementKind, UnaryOp};
+use std::collections::HashMap;
+
+/// How hard the compiler works on the program before generating code
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    /// Translate the program as written
+    O0,
+    /// Fold constants, simplify arithmetic identities and remove branches with constant conditions,
+    /// then run the IR passes. The C, JavaScript, Python and Rust backends work from the syntax
+    /// tree, so the IR passes don't reach them.
+    O1,
+}
+
+/// Return an optimized copy of the program.
+///
+/// Constant subexpressions are folded and `x * 1`, `x / 1`, `x + -0` and `x - 0` are simplified.
+/// Variables holding a known constant are replaced by it until control flow merges, which is
+/// at a LABEL, after an IF or WHILE, or at the head of a WHILE that assigns them.
+/// Folding keeps the program's output the same, so it rounds where C computes in single precision.
+pub fn optimize(program: &Program, level: OptLevel) -> Program {
+    if level == OptLevel::O0 {
+        return program.clone();
+    }
+
+    let mut folder = Folder { known: HashMap::new() };
+    Program {
+        statements: folder.statements(&program.statements),
+    }
+}
+
+struct Folder {
+    /// Variables known to hold a constant at the current statement
+    known: HashMap<String, f64>,
+}
+
+impl Folder {
+    fn statements(&mut self, statements: &[Statement]) -> Vec<Statement> {
+        let mut folded = Vec::new();
+        for statement in statements {
+            self.statement(statement, &mut folded);
+        }
+        folded
+    }
+
+    /// Fold the statement into the output, which may replace it with its body or nothing at all
+    fn statement(&mut self, statement: &Statement, out: &mut Vec<Statement>) {
+        let kind = match &statement.kind {
+            StatementKind::Print(Printable::String(_)) => statement.kind.clone(),
+            StatementKind::Print(Printable::Expression(expression)) => {
+                StatementKind::Print(Printable::Expression(self.expression(expression)))
+            }
+            StatementKind::Let { name, value } => {
+                let value = self.expression(value);
+                match number(&value) {
+                    // The variable is a C float, so it only keeps a float's worth of precision
+                    Some(constant) => self.known.insert(name.name.clone(), constant as f32 as f64),
+                    None => self.known.remove(&name.name),
+                };
+                StatementKind::Let {
+                    name: name.clone(),
+                    value,
+                }
+            }
+            StatementKind::Input(name) => {
+                self.known.remove(&name.name);
+                statement.kind.clone()
+            }
+            StatementKind::Label { .. } => {
+                // Anything could jump here, so nothing is known any more
+                self.known.clear();
+                statement.kind.clone()
+            }
+            StatementKind::Goto(_) => {
+                out.push(statement.clone());
+                self.known.clear();
+                return;
+            }
+            StatementKind::If { condition, body } => {
+                let condition = self.expression(condition);
+                match number(&condition) {
+                    // The body always runs, so it can replace the IF
+                    Some(value) if value != 0.0 => {
+                        out.extend(self.statements(body));
+                        return;
+                    }
+                    // The body never runs, unless a GOTO jumps into it
+                    Some(_) if !has_label(body) => return,
+                    _ => {}
+                }
+
+                let before = self.known.clone();
+                let body = self.statements(body);
+                self.known.retain(|name, value| before.get(name) == Some(value));
+                StatementKind::If { condition, body }
+            }
+            StatementKind::While { condition, body } => {
+                // The condition is also reached from the end of the body, or from a label inside it
+                if has_label(body) {
+                    self.known.clear();
+                } else {
+                    for name in assigned(body) {
+                        self.known.remove(&name);
+                    }
+                }
+
+                let condition = self.expression(condition);
+                if number(&condition) == Some(0.0) && !has_label(body) {
+                    return;
+                }
+
+                let header = self.known.clone();
+                let body = self.statements(body);
+                self.known = header;
+                StatementKind::While { condition, body }
+            }
+        };
+
+        out.push(Statement {
+            kind,
+            span: statement.span,
+        });
+    }
+
+    fn expression(&self, expression: &Expression) -> Expression {
+        // Arithmetic on nothing but variables is done in single precision. Putting a number in
+        // place of one of them would do it in double precision, so it's folded whole or not at all.
+        if expression.is_float() {
+            return match self.float_constant(expression) {
+                Some(value) => Expression {
+                    kind: ExpressionKind::Number(value),
+                    span: expression.span,
+                },
+                None => expression.clone(),
+            };
+        }
+
+        let span = expression.span;
+        let kind = match &expression.kind {
+            ExpressionKind::Number(_) => return expression.clone(),
+            ExpressionKind::Ident(name) => match self.known.get(name) {
+                Some(value) => ExpressionKind::Number(*value),
+                None => return expression.clone(),
+            },
+            ExpressionKind::Unary(op, operand) => {
+                let operand = self.expression(operand);
+                match (op, number(&operand)) {
+                    (_, Some(value)) => ExpressionKind::Number(op.apply(value)),
+                    (UnaryOp::Plus, None) => return operand,
+                    (UnaryOp::Minus, None) => ExpressionKind::Unary(*op, Box::new(operand)),
+                }
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                match (number(&left), number(&right)) {
+                    // Division by zero is left for the program to do at run time
+                    (Some(l), Some(r)) if op.apply(l, r).is_finite() => ExpressionKind::Number(op.apply(l, r)),
+                    // The arithmetic is in double precision, which a float operand on its own isn't
+                    _ if left.is_float() || right.is_float() => {
+                        ExpressionKind::Binary(*op, Box::new(left), Box::new(right))
+                    }
+                    // Adding zero turns -0 into 0, unless the zero is -0 too
+                    (_, Some(r)) if identity(*op, r, true) => return left,
+                    (Some(l), _) if identity(*op, l, false) => return right,
+                    _ => ExpressionKind::Binary(*op, Box::new(left), Box::new(right)),
+                }
+            }
+        };
+
+        Expression { kind, span }
+    }
+
+    /// Return the value of arithmetic on variables, rounded to single precision after each operation
+    /// as C does, if every variable is known and the result is finite
+    fn float_constant(&self, expression: &Expression) -> Option<f64> {
+        let value = match &expression.kind {
+            ExpressionKind::Number(value) => *value,
+            ExpressionKind::Ident(name) => *self.known.get(name)?,
+            ExpressionKind::Unary(op, operand) => op.apply(self.float_constant(operand)?),
+            ExpressionKind::Binary(op, left, right) => {
+                op.apply(self.float_constant(left)?, self.float_constant(right)?) as f32 as f64
+            }
+        };
+        Some(value).filter(|value| value.is_finite())
+    }
+}
+
+/// Return true if an operation with a constant operand gives the other operand back exactly,
+/// whatever it is. `on_right` is true if the constant is the right operand.
+fn identity(op: BinaryOp, constant: f64, on_right: bool) -> bool {
+    match op {
+        BinaryOp::Add => constant == 0.0 && constant.is_sign_negative(),
+        BinaryOp::Sub => on_right && constant == 0.0 && constant.is_sign_positive(),
+        BinaryOp::Mul => constant == 1.0,
+        BinaryOp::Div => on_right && constant == 1.0,
+        _ => false,
+    }
+}
+
+fn number(expression: &Expression) -> Option<f64> {
+    match expression.kind {
+        ExpressionKind::Number(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Return true if a label is declared anywhere in the statements, including nested bodies
+pub fn has_label(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match &statement.kind {
+        StatementKind::Label { .. } => true,
+        StatementKind::If { body, .. } | StatementKind::While { body, .. } => has_label(body),
+        _ => false,
+    })
+}
+
+/// Return every variable the statements assign, including in nested bodies
+fn assigned(statements: &[Statement]) -> Vec<String> {
+    let mut names = Vec::new();
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Let { name, .. } | StatementKind::Input(name) => names.push(name.name.clone()),
+            StatementKind::If { body, .. } | StatementKind::While { body, .. } => names.extend(assigned(body)),
+            _ => {}
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interp, ir, parse, Options};
+
+    /// Run a program through the interpreter at -O0 and -O1, which have to print the same
+    fn run_both(source: &str, input: &str) -> String {
+        let program = parse(source).unwrap();
+        let outputs: Vec<String> = [OptLevel::O0, OptLevel::O1]
+            .into_iter()
+            .map(|opt_level| {
+                let function = ir(
+                    &program,
+                    &Options {
+                        opt_level,
+                        ..Options::default()
+                    },
+                );
+                let mut output = Vec::new();
+                interp::run(&function, &mut input.as_bytes(), &mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            })
+            .collect();
+        assert_eq!(outputs[0], outputs[1]);
+        outputs[0].clone()
+    }
+
+    /// Return the statements of a program folded at -O1, written back out
+    fn folded(source: &str) -> String {
+        let program = optimize(&parse(source).unwrap(), OptLevel::O1);
+        program
+            .statements
+            .iter()
+            .map(|statement| format!("{}\n", statement))
+            .collect()
+    }
+
+    #[test]
+    fn float_arithmetic_rounds() {
+        // 4097 * 4097 is 16785409, which single precision rounds to 16785408
+        let source = "\
+LET x = 4097
+LET y = 4097
+PRINT x * y + 0.5
+IF x * y == 16785409 THEN
+    PRINT \"exact\"
+ENDIF
+LET a = 16777216
+LET b = 1
+PRINT a + b - b
+";
+        assert_eq!(run_both(source, ""), "16785408.00\n16777215.00\n");
+        assert_eq!(
+            folded(source),
+            "\
+LET x = 4097
+LET y = 4097
+PRINT 16785408.5
+LET a = 16777216
+LET b = 1
+PRINT 16777215
+"
+        );
+    }
+
+    #[test]
+    fn partly_known_float_arithmetic_stays() {
+        // Putting 4097 in for x would multiply in double precision, but x * 2 already is
+        let source = "LET x = 4097\nINPUT y\nPRINT x * y + 0.5\nPRINT x * 2 * y\n";
+        assert_eq!(run_both(source, "4097\n"), "16785408.00\n33570816.00\n");
+        assert_eq!(
+            folded(source),
+            "LET x = 4097\nINPUT y\nPRINT x * y + 0.5\nPRINT 8194 * y\n"
+        );
+    }
+
+    #[test]
+    fn identities_keep_negative_zero() {
+        let source = "\
+INPUT z
+LET w = z + 0
+PRINT 1 / w
+LET w = z - 0
+PRINT 1 / w
+LET w = z * 1
+PRINT 1 / w
+";
+        assert_eq!(run_both(source, "-0\n"), "inf\n-inf\n-inf\n");
+
+        // Only identities giving back an operand exactly, in double precision, are simplified
+        assert_eq!(
+            folded("INPUT x\nINPUT y\nPRINT x + 0.5 - 0\nPRINT x + 0.5 * 1\nPRINT x * 1 * y\n"),
+            "INPUT x\nINPUT y\nPRINT x + 0.5\nPRINT x + 0.5\nPRINT x * 1 * y\n"
+        );
+    }
+}
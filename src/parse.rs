@@ -1,37 +1,32 @@
-use super::emitter::Emitter;
+use super::ast::{BinaryOp, Expression, ExpressionKind, Ident, Printable, Program, Statement, StatementKind, UnaryOp};
+use super::diagnostic::Diagnostic;
 use super::lex::Lexer;
+use super::span::Span;
 use super::token::Token;
 use super::token::TokenType;
-use std::collections::HashSet;
 
 #[derive(Debug)]
-pub struct Parser<'a> {
+pub struct Parser {
     lexer: Lexer,
-    emitter: &'a mut Emitter,
     cur_token: Token,
     peek_token: Token,
-    symbols: HashSet<String>,
-    labels_declared: HashSet<String>,
-    labels_gotoed: HashSet<String>,
+    prev_span: Span,
 }
 
-impl<'a> Parser<'a> {
+impl Parser {
     /// Parser object keeps track of current token and checks if the code matches the grammar
-    pub fn new(lexer: Lexer, emitter: &'a mut Emitter) -> Self {
+    pub fn new(lexer: Lexer) -> Result<Self, Diagnostic> {
         let mut new_self = Self {
             lexer,
-            emitter,
             cur_token: Token::default(),
             peek_token: Token::default(),
-            symbols: Default::default(),         // Variables declared so far
-            labels_declared: Default::default(), // Labels declared so far
-            labels_gotoed: Default::default(),   // Labels goto'ed so far
+            prev_span: Span::default(), // Span of the last token consumed
         };
 
-        new_self.next_token();
-        new_self.next_token(); // Call this twice to initialize current and peek
+        new_self.next_token()?;
+        new_self.next_token()?; // Call this twice to initialize current and peek
 
-        new_self
+        Ok(new_self)
     }
 
     /// Return true if the current token matches
@@ -40,22 +35,34 @@ impl<'a> Parser<'a> {
     }
 
     /// Try to match current token. If matched advances the current token, If not, error.
-    pub fn match_token(&mut self, kind: TokenType) {
+    pub fn match_token(&mut self, kind: TokenType) -> Result<(), Diagnostic> {
         if self.cur_token.kind != kind {
-            self.abort(&format!("Expected {:?}, got {:?}", kind, self.cur_token.kind))
+            return Err(self.error(&format!("Expected {:?}, got {:?}", kind, self.cur_token.kind)));
         }
 
-        self.next_token();
+        self.next_token()
     }
 
     /// Advances the current token
-    pub fn next_token(&mut self) {
+    pub fn next_token(&mut self) -> Result<(), Diagnostic> {
+        self.prev_span = self.cur_token.span;
         self.cur_token = self.peek_token.clone();
-        self.peek_token = self.lexer.get_token();
+        self.peek_token = self.lexer.get_token()?;
+        Ok(())
     }
 
-    pub fn abort(&self, message: &str) {
-        panic!("{}", message)
+    /// Syntax error, return an error pointing at the current token.
+    pub fn error(&self, message: &str) -> Diagnostic {
+        Diagnostic::error(message, self.cur_token.span)
+    }
+
+    /// Describe the current token for an error message
+    fn found(&self) -> String {
+        match self.cur_token.kind {
+            TokenType::Newline => "end of line".to_owned(),
+            TokenType::Eof => "end of file".to_owned(),
+            _ => self.cur_token.text.clone(),
+        }
     }
 
     // ////////////////////////
@@ -63,227 +70,212 @@ impl<'a> Parser<'a> {
     // ////////////////////////
 
     /// nl ::= '\n'+
-    pub fn nl(&mut self) {
+    pub fn nl(&mut self) -> Result<(), Diagnostic> {
         // Require at least one newline
-        self.match_token(TokenType::Newline);
+        self.match_token(TokenType::Newline)?;
 
         // But we will allow extra newlines too, of course
         while self.check_token(TokenType::Newline) {
-            self.match_token(TokenType::Newline);
+            self.match_token(TokenType::Newline)?;
         }
+        Ok(())
     }
 
     /// program ::= {statement}
-    pub fn program(&mut self) {
-        self.emitter.header_line("#include <stdio.h>");
-        self.emitter.header_line("int main(void) {");
+    pub fn program(&mut self) -> Result<Program, Diagnostic> {
+        let mut statements = Vec::new();
 
         // Since some newlines are required in our grammar, need to skip the excess
         while self.check_token(TokenType::Newline) {
-            self.next_token();
+            self.next_token()?;
         }
 
         // Parse all the statements in the program
         while !self.check_token(TokenType::Eof) {
-            self.statement();
+            statements.push(self.statement()?);
         }
 
-        // Wrap things up
-        self.emitter.emit_line("return 0;");
-        self.emitter.emit_line("}");
-
-        // Check that each label referenced in a GOTO is declared
-        for x in self.labels_gotoed.iter() {
-            if !self.labels_declared.contains(x) {
-                self.abort(&format!("Label {} referenced but not declared", x))
-            }
-        }
+        Ok(Program { statements })
     }
 
     /// One of the following statements...
-    pub fn statement(&mut self) {
+    pub fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let start = self.cur_token.span;
+
         // Check the first token to see what kind of statement this is.
         // "PRINT" (expression | string)
-        if self.check_token(TokenType::Print) {
-            self.next_token();
+        let kind = if self.check_token(TokenType::Print) {
+            self.next_token()?;
 
             if self.check_token(TokenType::String) {
-                self.emitter
-                    .emit_line(&format!("{}{}{}", "printf(\"", self.cur_token.text, "\\n\");"));
-                self.next_token();
+                let text = self.cur_token.text.clone();
+                self.next_token()?;
+                StatementKind::Print(Printable::String(text))
             } else {
-                self.emitter.emit("printf(\"%.2f\\n\", (float)(");
-                self.expression();
-                self.emitter.emit_line("));");
+                StatementKind::Print(Printable::Expression(self.expression()?))
             }
         } else if self.check_token(TokenType::If) {
             // Branched statement
             // "IF" comparison "THEN" {statement} "ENDIF"
-            self.next_token();
-            self.emitter.emit("if(");
-            self.comparison();
+            self.next_token()?;
+            let condition = self.comparison()?;
 
-            self.match_token(TokenType::Then);
-            self.nl();
-            self.emitter.emit_line("){");
+            self.match_token(TokenType::Then)?;
+            self.nl()?;
 
+            let mut body = Vec::new();
             while !self.check_token(TokenType::EndIf) {
-                self.statement();
+                body.push(self.statement()?);
             }
 
-            self.match_token(TokenType::EndIf);
+            self.match_token(TokenType::EndIf)?;
+            StatementKind::If { condition, body }
         } else if self.check_token(TokenType::While) {
             // Branched statement
             // "WHILE" comparison "REPEAT" {statement} "ENDWHILE"
-            self.next_token();
-            self.emitter.emit("while(");
-            self.comparison();
+            self.next_token()?;
+            let condition = self.comparison()?;
 
-            self.match_token(TokenType::Repeat);
-            self.nl();
-            self.emitter.emit_line("){");
+            self.match_token(TokenType::Repeat)?;
+            self.nl()?;
 
+            let mut body = Vec::new();
             while !self.check_token(TokenType::EndWhile) {
-                self.statement();
+                body.push(self.statement()?);
             }
 
-            self.match_token(TokenType::EndWhile);
-            self.emitter.emit_line("}")
+            self.match_token(TokenType::EndWhile)?;
+            StatementKind::While { condition, body }
         } else if self.check_token(TokenType::Label) {
             // "LABEL" ident
-            self.next_token();
-
-            // Make sure this label doesn't already exist.
-            if self.labels_declared.contains(&self.cur_token.text) {
-                self.abort(&format!("Label already exists: {}", self.cur_token.text))
+            // A doc comment written above the statement was attached to the LABEL keyword.
+            let doc = self.cur_token.doc.take();
+            self.next_token()?;
+            StatementKind::Label {
+                name: self.ident()?,
+                doc,
             }
-
-            self.emitter.emit_line(&format!("{}:", self.cur_token.text));
-            self.match_token(TokenType::Ident);
         } else if self.check_token(TokenType::Goto) {
             // "GOTO" ident
-            self.next_token();
-
-            self.labels_gotoed.insert(self.cur_token.text.clone());
-            self.emitter.emit_line(&format!("goto {};", self.cur_token.text));
-            self.match_token(TokenType::Ident);
+            self.next_token()?;
+            StatementKind::Goto(self.ident()?)
         } else if self.check_token(TokenType::Let) {
             // "LET" ident "=" expression
-            self.next_token();
-
-            // If variable doesn't already exist, declare it
-            if !self.symbols.contains(&self.cur_token.text) {
-                self.symbols.insert(self.cur_token.text.clone());
-                self.emitter.header_line(&format!("float {};", self.cur_token.text));
+            self.next_token()?;
+            let name = self.ident()?;
+            self.match_token(TokenType::Eq)?;
+            StatementKind::Let {
+                name,
+                value: self.expression()?,
             }
-
-            self.emitter.emit(&format!("{} = ", self.cur_token.text));
-            self.match_token(TokenType::Ident);
-            self.match_token(TokenType::Eq);
-            self.expression();
-            self.emitter.emit_line(";");
         } else if self.check_token(TokenType::Input) {
             // "INPUT" ident
-            self.next_token();
-
-            // If variable doesn't already exist, declare it
-            if !self.symbols.contains(&self.cur_token.text) {
-                self.symbols.insert(self.cur_token.text.clone());
-                self.emitter.header_line(&format!("float {};", self.cur_token.text));
-            }
-
-            self.emitter.emit_line(&format!(
-                "{}{}{}",
-                "if (0 == scanf(\"%f\", &", self.cur_token.text, ")) {"
-            ));
-            self.emitter.emit_line(&format!("{} = 0;", self.cur_token.text));
-            self.emitter.emit_line("scanf(\"%*s\");");
-            self.emitter.emit_line("}");
-            self.match_token(TokenType::Ident);
+            self.next_token()?;
+            StatementKind::Input(self.ident()?)
         } else {
-            self.abort(&format!(
+            return Err(self.error(&format!(
                 "Invalid statement at {} ({:?})",
                 self.cur_token.text, self.cur_token.kind
-            ))
-        }
+            )));
+        };
+        let span = start.to(self.prev_span);
 
         // Newline
-        self.nl();
+        self.nl()?;
+        Ok(Statement { kind, span })
     }
 
     /// comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
-    pub fn comparison(&mut self) {
-        self.expression();
+    pub fn comparison(&mut self) -> Result<Expression, Diagnostic> {
+        let mut left = self.expression()?;
 
         // Must be at least one comparison operator and another expression
-        if self.is_comparison_operator() {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.expression();
-        } else {
-            self.abort(&format!("Expected comparison operator at: {}", self.cur_token.text))
+        if !self.is_comparison_operator() {
+            return Err(self.error(&format!("Expected comparison operator at: {}", self.found())));
         }
 
         // Can have 0 or more comparison operator and expressions
         while self.is_comparison_operator() {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.expression();
+            let op = self.binary_operator();
+            self.next_token()?;
+            left = binary(op, left, self.expression()?);
         }
+
+        Ok(left)
     }
 
     /// expression ::= term {( "-" | "+" ) term}
-    pub fn expression(&mut self) {
-        self.term();
+    pub fn expression(&mut self) -> Result<Expression, Diagnostic> {
+        let mut left = self.term()?;
 
         //  Can have 0 or more +/- and expressions
         while self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.term();
+            let op = self.binary_operator();
+            self.next_token()?;
+            left = binary(op, left, self.term()?);
         }
+
+        Ok(left)
     }
 
     /// term ::= unary {( "/" | "*" ) unary}
-    pub fn term(&mut self) {
-        self.unary();
+    pub fn term(&mut self) -> Result<Expression, Diagnostic> {
+        let mut left = self.unary()?;
 
         // Can have 0 or more *// and expressions
         while self.check_token(TokenType::Slash) || self.check_token(TokenType::Asterisk) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
-            self.unary();
+            let op = self.binary_operator();
+            self.next_token()?;
+            left = binary(op, left, self.unary()?);
         }
+
+        Ok(left)
     }
 
     /// unary ::= ["+" | "-"] primary
-    pub fn unary(&mut self) {
+    pub fn unary(&mut self) -> Result<Expression, Diagnostic> {
         if self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+            let op = if self.check_token(TokenType::Plus) {
+                UnaryOp::Plus
+            } else {
+                UnaryOp::Minus
+            };
+            let start = self.cur_token.span;
+            self.next_token()?;
+
+            let operand = self.primary()?;
+            return Ok(Expression {
+                span: start.to(operand.span),
+                kind: ExpressionKind::Unary(op, Box::new(operand)),
+            });
         }
         self.primary()
     }
 
     /// primary ::= number | ident
-    pub fn primary(&mut self) {
-        if self.check_token(TokenType::Number) {
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+    pub fn primary(&mut self) -> Result<Expression, Diagnostic> {
+        let kind = if self.check_token(TokenType::Number) {
+            ExpressionKind::Number(self.cur_token.text.parse().unwrap())
         } else if self.check_token(TokenType::Ident) {
-            if !self.symbols.contains(&self.cur_token.text) {
-                self.abort(&format!(
-                    "Referencing variable before assignment: {}",
-                    self.cur_token.text
-                ))
-            }
-
-            self.emitter.emit(&self.cur_token.text);
-            self.next_token();
+            ExpressionKind::Ident(self.cur_token.text.clone())
         } else {
             // Error!
-            self.abort(&format!("Unexpected token at {}", self.cur_token.text))
-        }
+            return Err(self.error(&format!("Unexpected token at {}", self.found())));
+        };
+
+        let span = self.cur_token.span;
+        self.next_token()?;
+        Ok(Expression { kind, span })
+    }
+
+    /// Match an identifier and return its name and where it was written
+    fn ident(&mut self) -> Result<Ident, Diagnostic> {
+        let ident = Ident {
+            name: self.cur_token.text.clone(),
+            span: self.cur_token.span,
+        };
+        self.match_token(TokenType::Ident)?;
+        Ok(ident)
     }
 
     fn is_comparison_operator(&self) -> bool {
@@ -294,4 +286,28 @@ impl<'a> Parser<'a> {
             || self.check_token(TokenType::EqEq)
             || self.check_token(TokenType::NotEq)
     }
+
+    /// Return the binary operator for the current token
+    fn binary_operator(&self) -> BinaryOp {
+        match self.cur_token.kind {
+            TokenType::Plus => BinaryOp::Add,
+            TokenType::Minus => BinaryOp::Sub,
+            TokenType::Asterisk => BinaryOp::Mul,
+            TokenType::Slash => BinaryOp::Div,
+            TokenType::EqEq => BinaryOp::EqEq,
+            TokenType::NotEq => BinaryOp::NotEq,
+            TokenType::Lt => BinaryOp::Lt,
+            TokenType::LtEq => BinaryOp::LtEq,
+            TokenType::Gt => BinaryOp::Gt,
+            TokenType::GtEq => BinaryOp::GtEq,
+            _ => unreachable!("{:?} is not a binary operator", self.cur_token.kind),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expression, right: Expression) -> Expression {
+    Expression {
+        span: left.span.to(right.span),
+        kind: ExpressionKind::Binary(op, Box::new(left), Box::new(right)),
+    }
 }
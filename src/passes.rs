@@ -0,0 +1,383 @@
+use super::ir::{Function, Inst, Terminator, Value};
+use super::loops;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The optimization passes run on the SSA form at `-O1`. Each can be turned off with `--disable-pass`.
+/// They affect every backend generated from the IR, but not C, JavaScript, Python or Rust.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// Turn branches on constants into jumps and remove the blocks nothing jumps to any more
+    UnreachableBlocks,
+    /// Replace the results of copies, and of phis that always pick the same value, by their source
+    CopyPropagation,
+    /// Remove instructions whose results are never used
+    DeadCode,
+    /// Move instructions computing the same value on every iteration of a loop in front of the loop
+    LoopInvariantCodeMotion,
+    /// Replace multiplications of a loop counter by a constant with additions
+    StrengthReduction,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 5] = [
+        Pass::UnreachableBlocks,
+        Pass::CopyPropagation,
+        Pass::DeadCode,
+        Pass::LoopInvariantCodeMotion,
+        Pass::StrengthReduction,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::UnreachableBlocks => "unreachable-blocks",
+            Pass::CopyPropagation => "copy-propagation",
+            Pass::DeadCode => "dead-code",
+            Pass::LoopInvariantCodeMotion => "loop-invariant-code-motion",
+            Pass::StrengthReduction => "strength-reduction",
+        }
+    }
+
+    pub fn from_name(pass: &str) -> Option<Pass> {
+        Pass::ALL.into_iter().find(|known| known.name() == pass)
+    }
+
+    /// Run the pass, returning true if it changed the function
+    fn run(&self, function: &mut Function) -> bool {
+        match self {
+            Pass::UnreachableBlocks => remove_unreachable_blocks(function),
+            Pass::CopyPropagation => propagate_copies(function),
+            Pass::DeadCode => eliminate_dead_code(function),
+            Pass::LoopInvariantCodeMotion => loops::hoist_invariants(function),
+            Pass::StrengthReduction => loops::reduce_strength(function),
+        }
+    }
+}
+
+/// Run every pass that isn't disabled until none of them finds anything more to do
+pub fn run(function: &mut Function, disabled: &HashSet<Pass>) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pass in Pass::ALL {
+            if !disabled.contains(&pass) {
+                changed |= pass.run(function);
+            }
+        }
+    }
+}
+
+/// Fold branches whose condition is a constant, or whose targets are the same block, into jumps,
+/// then remove the blocks that can no longer be reached
+pub fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let constants: HashMap<Value, f64> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst {
+            Inst::Const { dst, value } => Some((*dst, *value)),
+            _ => None,
+        })
+        .collect();
+
+    let mut changed = false;
+    for id in 0..function.blocks.len() {
+        let Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        } = function.blocks[id].terminator
+        else {
+            continue;
+        };
+
+        let (kept, dropped) = match constants.get(&cond) {
+            Some(&value) if value != 0.0 => (then_block, else_block),
+            Some(_) => (else_block, then_block),
+            None if then_block == else_block => (then_block, else_block),
+            None => continue,
+        };
+
+        function.blocks[id].terminator = Terminator::Jump(kept);
+        changed = true;
+
+        // The dropped target loses this block as a predecessor, so its phis lose an argument.
+        // When both targets are the same block it was listed twice, so only one goes.
+        for inst in &mut function.blocks[dropped].insts {
+            if let Inst::Phi { args, .. } = inst {
+                if let Some(index) = args.iter().position(|(predecessor, _)| *predecessor == id) {
+                    args.remove(index);
+                }
+            }
+        }
+    }
+
+    function.remove_unreachable_blocks() || changed
+}
+
+/// Replace every use of a copy's result by the copied value, and likewise for phis whose
+/// arguments are all the same value, then remove those instructions
+pub fn propagate_copies(function: &mut Function) -> bool {
+    let mut changed = false;
+
+    loop {
+        let mut replacements = BTreeMap::new();
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            match inst {
+                Inst::Copy { dst, src } => {
+                    replacements.insert(*dst, *src);
+                }
+                Inst::Phi { dst, args } => {
+                    // A phi reading itself only passes its own value around the loop
+                    let mut sources = args.iter().map(|(_, value)| *value).filter(|value| value != dst);
+                    if let Some(first) = sources.next() {
+                        if sources.all(|value| value == first) {
+                            replacements.insert(*dst, first);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if replacements.is_empty() {
+            return changed;
+        }
+        changed = true;
+
+        // A value copied into a variable keeps the variable's name in dumps, the first one if there are several
+        for (dst, src) in &replacements {
+            if !function.names.contains_key(src) {
+                if let Some(name) = function.names.get(dst).cloned() {
+                    function.names.insert(*src, name);
+                }
+            }
+        }
+
+        // Follow chains of copies to the value at their start
+        let resolve = |mut value: Value| {
+            while let Some(&src) = replacements.get(&value) {
+                value = src;
+            }
+            value
+        };
+
+        for block in &mut function.blocks {
+            block
+                .insts
+                .retain(|inst| !inst.dst().is_some_and(|dst| replacements.contains_key(&dst)));
+            for inst in &mut block.insts {
+                for value in inst.uses_mut() {
+                    *value = resolve(*value);
+                }
+            }
+            for value in block.terminator.uses_mut() {
+                *value = resolve(*value);
+            }
+        }
+    }
+}
+
+/// Remove instructions with no side effects whose results are never used, directly or through
+/// other instructions that end up being used
+pub fn eliminate_dead_code(function: &mut Function) -> bool {
+    let mut definitions = HashMap::new();
+    let mut worklist = Vec::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Some(dst) = inst.dst() {
+                definitions.insert(dst, inst);
+            }
+            if inst.has_side_effects() {
+                worklist.extend(inst.uses());
+            }
+        }
+        worklist.extend(block.terminator.uses());
+    }
+
+    let mut live = HashSet::new();
+    while let Some(value) = worklist.pop() {
+        if live.insert(value) {
+            if let Some(inst) = definitions.get(&value) {
+                worklist.extend(inst.uses());
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let before = block.insts.len();
+        block
+            .insts
+            .retain(|inst| inst.has_side_effects() || inst.dst().is_some_and(|dst| live.contains(&dst)));
+        changed |= block.insts.len() != before;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{self, OptLevel};
+    use crate::{ir, parse, Options};
+
+    /// Lower a program to IR in SSA form, after folding its constants if `folded`
+    fn lower(source: &str, folded: bool) -> Function {
+        let mut program = parse(source).unwrap();
+        if folded {
+            program = optimize::optimize(&program, OptLevel::O1);
+        }
+        ir(&program, &Options::default())
+    }
+
+    /// Check the IR of a program before and after running one pass on it
+    fn check(source: &str, folded: bool, pass: Pass, before: &str, after: &str) {
+        let mut function = lower(source, folded);
+        assert_eq!(function.to_string(), before);
+        assert!(pass.run(&mut function));
+        assert_eq!(function.to_string(), after);
+    }
+
+    #[test]
+    fn unreachable_blocks() {
+        let source = "WHILE 1 < 2 REPEAT\n    PRINT \"forever\"\nENDWHILE\nPRINT \"never\"\n";
+        check(
+            source,
+            true,
+            Pass::UnreachableBlocks,
+            "\
+b0:
+    jump b1
+b1:
+    %0 = const 1
+    branch %0, b2, b3
+b2:
+    print \"forever\"
+    jump b1
+b3:
+    print \"never\"
+    return
+",
+            "\
+b0:
+    jump b1
+b1:
+    %0 = const 1
+    jump b2
+b2:
+    print \"forever\"
+    jump b1
+",
+        );
+    }
+
+    /// The shuffle of hello.teeny's fibonacci loop
+    #[test]
+    fn copy_propagation() {
+        let source = "\
+LET a = 0
+LET b = 1
+WHILE a < 100 REPEAT
+    PRINT a
+    LET c = a + b
+    LET a = b
+    LET b = c
+ENDWHILE
+";
+        check(
+            source,
+            false,
+            Pass::CopyPropagation,
+            "\
+b0:
+    %17 = const 0
+    %0 = const 0
+    %a.15 = copy %0
+    %1 = const 1
+    %b.16 = copy %1
+    jump b1
+b1:
+    %a.12 = phi [b0: %a.15], [b2: %a.19]
+    %b.13 = phi [b0: %b.16], [b2: %b.20]
+    %c.14 = phi [b0: %17], [b2: %c.18]
+    %3 = const 100
+    %4 = lt %a.12, %3
+    branch %4, b2, b3
+b2:
+    print %a.12
+    %8 = add %a.12, %b.13
+    %9 = round %8
+    %c.18 = copy %9
+    %a.19 = copy %b.13
+    %b.20 = copy %c.18
+    jump b1
+b3:
+    return
+",
+            // The phis pass the values around the loop themselves
+            "\
+b0:
+    %17 = const 0
+    %a.0 = const 0
+    %b.1 = const 1
+    jump b1
+b1:
+    %a.12 = phi [b0: %a.0], [b2: %b.13]
+    %b.13 = phi [b0: %b.1], [b2: %c.9]
+    %c.14 = phi [b0: %17], [b2: %c.9]
+    %3 = const 100
+    %4 = lt %a.12, %3
+    branch %4, b2, b3
+b2:
+    print %a.12
+    %8 = add %a.12, %b.13
+    %c.9 = round %8
+    jump b1
+b3:
+    return
+",
+        );
+    }
+
+    #[test]
+    fn dead_code() {
+        check(
+            "INPUT x\nLET y = x * 2\nLET y = x\nPRINT y\n",
+            false,
+            Pass::DeadCode,
+            "\
+b0:
+    %0 = input
+    %1 = round %0
+    %x.8 = copy %1
+    %3 = const 2
+    %4 = mul %x.8, %3
+    %5 = round %4
+    %y.9 = copy %5
+    %y.10 = copy %x.8
+    print %y.10
+    return
+",
+            "\
+b0:
+    %0 = input
+    %1 = round %0
+    %x.8 = copy %1
+    %y.10 = copy %x.8
+    print %y.10
+    return
+",
+        );
+    }
+
+    #[test]
+    fn disabled_passes_dont_run() {
+        let mut function = lower("INPUT x\nLET y = x * 2\nLET y = x\nPRINT y\n", false);
+        let before = function.clone();
+        run(&mut function, &HashSet::new());
+        assert_ne!(function, before);
+
+        let mut function = before.clone();
+        run(&mut function, &Pass::ALL.into_iter().collect());
+        assert_eq!(function, before);
+    }
+}
@@ -0,0 +1,382 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, Program, Statement, StatementKind};
+use super::cfg::{Cfg, Terminator};
+use super::emitter::{Emitter, Section};
+use super::optimize::has_label;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Python keywords and the builtins the script uses. Variables named after them get an
+/// underscore after, which no teeny identifier can have.
+const RESERVED: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+    "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+    "not", "or", "pass", "raise", "return", "try", "while", "with", "yield", "print", "float",
+];
+
+/// Modules the helpers use
+const IMPORTS: &str = r#"import math
+import re
+import struct
+import sys
+"#;
+
+/// The state of reading numbers from standard input
+const GLOBALS: &str = r#"_NUMBER = re.compile(r"[+-]?(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?")
+_pending = ""
+"#;
+
+/// Helpers for doing arithmetic, printing and reading numbers like the C program does
+const PRELUDE: &str = r#"
+def _fround(x):
+    """Round to the nearest single precision float, like storing to a C float"""
+    try:
+        return struct.unpack("f", struct.pack("f", x))[0]
+    except OverflowError:
+        return math.copysign(math.inf, x)
+
+
+def _div(a, b):
+    """Divide like C, where dividing by zero gives infinity or NaN instead of raising"""
+    try:
+        return a / b
+    except ZeroDivisionError:
+        if a == 0 or math.isnan(a):
+            return math.nan
+        return math.copysign(math.inf, a) * math.copysign(1.0, b)
+
+
+def _input():
+    """Read a number the way scanf("%lf") does. If the next word isn't a number it's skipped and
+    the number read is zero, as it is at the end of the input."""
+    global _pending
+    while not _pending.strip():
+        _pending = sys.stdin.readline()
+        if not _pending:
+            return 0.0
+    _pending = _pending.lstrip()
+    match = _NUMBER.match(_pending)
+    if match is None:
+        _pending = _pending[len(_pending.split(None, 1)[0]):]
+        return 0.0
+    _pending = _pending[match.end():]
+    return _fround(float(match.group()))
+
+"#;
+
+/// Walks the AST and writes the equivalent Python 3 script through the emitter.
+/// Variables are rounded to single precision as the C program's floats are.
+#[derive(Debug)]
+pub struct PyGen<'a, W: Write> {
+    emitter: &'a mut Emitter<W>,
+    declared: HashSet<String>,
+}
+
+impl<'a, W: Write> PyGen<'a, W> {
+    pub fn new(emitter: &'a mut Emitter<W>) -> Self {
+        Self {
+            emitter,
+            declared: HashSet::new(),
+        }
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        self.emitter.line_to(Section::Includes, IMPORTS);
+        self.emitter.line_to(Section::Globals, GLOBALS);
+        self.emitter.line_to(Section::Functions, PRELUDE);
+        self.emitter.begin_main("def main():");
+
+        // Python has no goto, so a program with labels runs its blocks from a dispatch loop
+        if has_label(&program.statements) {
+            self.dispatch(&Cfg::new(program));
+        } else {
+            for statement in &program.statements {
+                self.statement(statement);
+            }
+            if program.statements.is_empty() {
+                self.emitter.emit_line("pass");
+            }
+        }
+
+        self.emitter.dedent();
+        self.emitter.emit_line("");
+        self.emitter.emit_line("");
+        self.emitter.emit_line("if __name__ == \"__main__\":");
+        self.emitter.emit_line("    main()");
+    }
+
+    /// Emit a statement other than a LABEL or GOTO
+    pub fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(Printable::String(text)) => self.emitter.emit_line(&format!("print(\"{}\")", text)),
+            StatementKind::Print(Printable::Expression(expression)) => {
+                let value = self.expression(expression).0;
+                self.emitter.emit_line(&format!("print(\"%.2f\" % _fround({}))", value));
+            }
+            StatementKind::If { condition, body } => {
+                let condition = self.expression(condition).0;
+                self.block(&format!("if {}:", condition), body);
+            }
+            StatementKind::While { condition, body } => {
+                let condition = self.expression(condition).0;
+                self.block(&format!("while {}:", condition), body);
+            }
+            StatementKind::Let { name, value } => {
+                let name = self.variable(&name.name);
+                let value = self.expression(value).0;
+                self.emitter.emit_line(&format!("{} = _fround({})", name, value));
+            }
+            StatementKind::Input(name) => {
+                let name = self.variable(&name.name);
+                self.emitter.emit_line(&format!("{} = _input()", name));
+            }
+            StatementKind::Label { .. } | StatementKind::Goto(_) => {
+                unreachable!("programs with labels are emitted from their control flow graph")
+            }
+        }
+    }
+
+    fn block(&mut self, head: &str, body: &[Statement]) {
+        self.emitter.emit_line(head);
+        self.emitter.indent();
+        for statement in body {
+            self.statement(statement);
+        }
+        if body.is_empty() {
+            self.emitter.emit_line("pass");
+        }
+        self.emitter.dedent();
+    }
+
+    /// Emit a loop with a branch per reachable block, each setting the block to run next
+    fn dispatch(&mut self, cfg: &Cfg) {
+        let reachable = cfg.reachable();
+
+        self.emitter.emit_line(&format!("_block = {}", cfg.entry));
+        self.emitter.emit_line("while True:");
+        self.emitter.indent();
+
+        let mut first = true;
+        for (id, block) in cfg.blocks.iter().enumerate().filter(|(id, _)| reachable[*id]) {
+            let keyword = if first { "if" } else { "elif" };
+            first = false;
+            match &block.label {
+                Some(label) => self
+                    .emitter
+                    .emit_line(&format!("{} _block == {}:  # {}", keyword, id, label)),
+                None => self.emitter.emit_line(&format!("{} _block == {}:", keyword, id)),
+            }
+            self.emitter.indent();
+
+            for statement in &block.statements {
+                self.statement(statement);
+            }
+            match &block.terminator {
+                Terminator::Jump(target) | Terminator::Goto { target, .. } => {
+                    self.emitter.emit_line(&format!("_block = {}", target));
+                }
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    let condition = self.expression(condition).0;
+                    self.emitter
+                        .emit_line(&format!("_block = {} if {} else {}", then_block, condition, else_block));
+                }
+                Terminator::Return => self.emitter.emit_line("return"),
+            }
+
+            self.emitter.dedent();
+        }
+
+        self.emitter.dedent();
+    }
+
+    /// Return an expression as Python, along with the precedence of its outermost operator
+    fn expression(&mut self, expression: &Expression) -> (String, u8) {
+        match &expression.kind {
+            ExpressionKind::Number(value) => (format!("{:?}", value), u8::MAX),
+            ExpressionKind::Ident(name) => (self.variable(name), u8::MAX),
+            ExpressionKind::Unary(op, operand) => {
+                let operand = self.operand(operand, u8::MAX);
+                (format!("{}{}", op.as_str(), operand), u8::MAX)
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                let (code, precedence) = if *op == BinaryOp::Div {
+                    let left = self.expression(left).0;
+                    let right = self.expression(right).0;
+                    (format!("_div({}, {})", left, right), u8::MAX)
+                } else {
+                    let precedence = precedence(*op);
+                    // Python chains comparisons, so a comparison inside another needs parentheses
+                    // on either side to compare its result like C does
+                    let left = self.operand(left, if precedence == 1 { 2 } else { precedence });
+                    let right = self.operand(right, precedence + 1);
+                    (format!("{} {} {}", left, op.as_str(), right), precedence)
+                };
+
+                // Arithmetic between floats is rounded to a float at every step in C
+                let arithmetic = matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div);
+                if arithmetic && expression.is_float() {
+                    (format!("_fround({})", code), u8::MAX)
+                } else {
+                    (code, precedence)
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self, expression: &Expression, min_precedence: u8) -> String {
+        let (code, precedence) = self.expression(expression);
+        if precedence < min_precedence {
+            format!("({})", code)
+        } else {
+            code
+        }
+    }
+
+    /// Return the Python name of a variable, setting it to zero at the start of main the first
+    /// time it's seen
+    fn variable(&mut self, name: &str) -> String {
+        let py_name = if RESERVED.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_owned()
+        };
+        if self.declared.insert(name.to_owned()) {
+            self.emitter
+                .line_to(Section::Declarations, &format!("{} = 0.0", py_name));
+        }
+        py_name
+    }
+}
+
+/// Python operator precedence, higher binds tighter. Every comparison shares one level.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Mul | BinaryOp::Div => 3,
+        BinaryOp::Add | BinaryOp::Sub => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, Emit, Options};
+
+    /// Return the code generated for the program from the start of its main function
+    fn main_of(source: &str) -> String {
+        let options = Options {
+            emit: Emit::Py,
+            ..Options::default()
+        };
+        let code = String::from_utf8(compile_str(source, &options).unwrap().code).unwrap();
+        code[code.find("def main").unwrap()..].to_owned()
+    }
+
+    #[test]
+    fn structured_program() {
+        let source = "\
+PRINT \"hi\"
+INPUT n
+LET i = 0
+WHILE i < n REPEAT
+    IF i == 2 THEN
+        PRINT i * -1
+    ENDIF
+    LET i = i + 1
+ENDWHILE
+";
+        assert_eq!(
+            main_of(source),
+            "\
+def main():
+    n = 0.0
+    i = 0.0
+    print(\"hi\")
+    n = _input()
+    i = _fround(0.0)
+    while i < n:
+        if i == 2.0:
+            print(\"%.2f\" % _fround(i * -1.0))
+        i = _fround(i + 1.0)
+
+
+if __name__ == \"__main__\":
+    main()
+"
+        );
+    }
+
+    #[test]
+    fn goto_program() {
+        let source = "\
+LET i = 0
+LABEL top
+LET i = i + 1
+IF i < 3 THEN
+    GOTO top
+ENDIF
+PRINT i
+";
+        assert_eq!(
+            main_of(source),
+            "\
+def main():
+    i = 0.0
+    _block = 0
+    while True:
+        if _block == 0:
+            i = _fround(0.0)
+            _block = 1
+        elif _block == 1:  # top
+            i = _fround(i + 1.0)
+            _block = 2 if i < 3.0 else 3
+        elif _block == 2:
+            _block = 1
+        elif _block == 3:
+            print(\"%.2f\" % _fround(i))
+            return
+
+
+if __name__ == \"__main__\":
+    main()
+"
+        );
+    }
+
+    #[test]
+    fn reserved_names() {
+        let source = "\
+LET new = 1
+LET lambda = 2
+LET fn = 3
+LET self = 4
+PRINT new + lambda / fn - self
+IF new < lambda == fn > self THEN
+    PRINT \"chained\"
+ENDIF
+";
+        assert_eq!(
+            main_of(source),
+            "\
+def main():
+    new = 0.0
+    lambda_ = 0.0
+    fn = 0.0
+    self = 0.0
+    new = _fround(1.0)
+    lambda_ = _fround(2.0)
+    fn = _fround(3.0)
+    self = _fround(4.0)
+    print(\"%.2f\" % _fround(_fround(_fround(new + _fround(_div(lambda_, fn))) - self)))
+    if ((new < lambda_) == fn) > self:
+        print(\"chained\")
+
+
+if __name__ == \"__main__\":
+    main()
+"
+        );
+    }
+}
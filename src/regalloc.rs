@@ -0,0 +1,262 @@
+use super::ir::{BlockId, Function, Inst, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Where a value lives for its whole lifetime
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Location {
+    /// One of the allocatable registers, by index
+    Register(usize),
+    /// A stack slot, by index
+    Stack(usize),
+}
+
+/// The result of register allocation for a function out of SSA form
+#[derive(Debug)]
+pub struct Allocation {
+    /// The blocks in the order they're laid out
+    pub order: Vec<BlockId>,
+    pub locations: HashMap<Value, Location>,
+    /// Number of stack slots used by spilled values
+    pub stack_slots: usize,
+    /// The registers holding values that live across each instruction calling into the runtime,
+    /// by block and instruction index
+    pub live_across_calls: HashMap<(BlockId, usize), Vec<usize>>,
+}
+
+/// The range of positions, in the linear order of instructions, where a value is live
+#[derive(Copy, Clone, Debug)]
+struct Interval {
+    value: Value,
+    start: usize,
+    end: usize,
+}
+
+/// Allocate the values of a function to `registers` registers with linear scan,
+/// following Poletto and Sarkar. A value that doesn't get a register is spilled to a
+/// stack slot for its whole lifetime.
+///
+/// The function must be out of SSA form: a value may be assigned more than once, so each
+/// value gets a single interval from its first definition or use to its last.
+pub fn allocate(function: &Function, registers: usize) -> Allocation {
+    let order = function.reverse_postorder();
+    let live_out = live_out(function);
+
+    // Every instruction and terminator gets a position, in layout order
+    let mut position = 0;
+    let mut ranges: HashMap<Value, (usize, usize)> = HashMap::new();
+    let mut calls = Vec::new();
+    let mut cover = |value: Value, at: usize| {
+        let range = ranges.entry(value).or_insert((at, at));
+        range.0 = range.0.min(at);
+        range.1 = range.1.max(at);
+    };
+
+    for &block in &order {
+        let start = position;
+        let live_in = live_in(function, block, &live_out[block]);
+        for &value in &live_in {
+            cover(value, start);
+        }
+
+        for (index, inst) in function.blocks[block].insts.iter().enumerate() {
+            for value in inst.uses() {
+                cover(value, position);
+            }
+            if let Some(dst) = inst.dst() {
+                cover(dst, position);
+            }
+            if matches!(inst, Inst::Print { .. } | Inst::PrintStr { .. } | Inst::Input { .. }) {
+                calls.push((block, index, position));
+            }
+            position += 1;
+        }
+
+        for value in function.blocks[block].terminator.uses() {
+            cover(value, position);
+        }
+        for &value in &live_out[block] {
+            cover(value, position);
+        }
+        position += 1;
+    }
+
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .map(|(value, (start, end))| Interval { value, start, end })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.value));
+    let all = intervals.clone();
+
+    let mut locations = HashMap::new();
+    let mut stack_slots = 0;
+    let mut free: BTreeSet<usize> = (0..registers).collect();
+    let mut active: Vec<Interval> = Vec::new();
+
+    for interval in intervals {
+        // Values used by the instruction defining this one are still live at its start,
+        // so an instruction never writes a register it also reads
+        active.retain(|other| {
+            if other.end < interval.start {
+                if let Some(Location::Register(register)) = locations.get(&other.value) {
+                    free.insert(*register);
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        if let Some(register) = free.pop_first() {
+            locations.insert(interval.value, Location::Register(register));
+            active.push(interval);
+            continue;
+        }
+
+        // Spill whichever interval ends last, so the register is free again as soon as possible
+        let furthest = (0..active.len()).max_by_key(|&index| active[index].end).unwrap();
+        if active[furthest].end > interval.end {
+            let spilled = active.swap_remove(furthest);
+            let register = locations[&spilled.value];
+            locations.insert(spilled.value, Location::Stack(stack_slots));
+            locations.insert(interval.value, register);
+            active.push(interval);
+        } else {
+            locations.insert(interval.value, Location::Stack(stack_slots));
+        }
+        stack_slots += 1;
+    }
+
+    // A register is clobbered by a call if its value is live both before and after it
+    let mut live_across_calls = HashMap::new();
+    for (block, index, at) in calls {
+        let mut live: Vec<usize> = all
+            .iter()
+            .filter(|interval| interval.start < at && interval.end > at)
+            .filter_map(|interval| match locations[&interval.value] {
+                Location::Register(register) => Some(register),
+                Location::Stack(_) => None,
+            })
+            .collect();
+        live.sort_unstable();
+        live_across_calls.insert((block, index), live);
+    }
+
+    Allocation {
+        order,
+        locations,
+        stack_slots,
+        live_across_calls,
+    }
+}
+
+/// Return the values live at the end of every block, by iterating the liveness equations to a fixed point
+fn live_out(function: &Function) -> Vec<HashSet<Value>> {
+    let mut live_out = vec![HashSet::new(); function.blocks.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in (0..function.blocks.len()).rev() {
+            let mut out = HashSet::new();
+            for successor in function.blocks[block].terminator.successors() {
+                out.extend(live_in(function, successor, &live_out[successor]));
+            }
+            if out != live_out[block] {
+                live_out[block] = out;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+/// Return the values live at the start of a block, given those live at its end
+fn live_in(function: &Function, block: BlockId, live_out: &HashSet<Value>) -> HashSet<Value> {
+    let mut live = live_out.clone();
+    for value in function.blocks[block].terminator.uses() {
+        live.insert(value);
+    }
+    for inst in function.blocks[block].insts.iter().rev() {
+        if let Some(dst) = inst.dst() {
+            live.remove(&dst);
+        }
+        live.extend(inst.uses());
+    }
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parse, ssa, Options};
+
+    /// Lower a program to IR out of SSA form, ready to be allocated
+    fn lower(source: &str) -> Function {
+        let mut function = ir(&parse(source).unwrap(), &Options::default());
+        ssa::destruct(&mut function);
+        function
+    }
+
+    /// Return the value a variable is copied to, in a program assigning it once
+    fn variable(function: &Function, name: &str) -> Value {
+        let mut values = function.names.iter().filter(|(_, other)| *other == name);
+        let (&value, _) = values.next().unwrap();
+        assert!(values.next().is_none());
+        value
+    }
+
+    #[test]
+    fn spills_when_out_of_registers() {
+        let function = lower("INPUT a\nINPUT b\nINPUT c\nPRINT a + b + c\n");
+        let allocation = allocate(&function, 2);
+        assert!(allocation.stack_slots > 0);
+
+        // Walking the one block backwards, a value being defined and those live after it all
+        // need places of their own
+        let block = &function.blocks[0];
+        let mut live: HashSet<Value> = block.terminator.uses().into_iter().collect();
+        for inst in block.insts.iter().rev() {
+            if let Some(dst) = inst.dst() {
+                live.remove(&dst);
+                let mut places = Vec::new();
+                for value in live.iter().chain([&dst]) {
+                    let location = allocation.locations[value];
+                    assert!(!matches!(location, Location::Register(register) if register >= 2));
+                    assert!(
+                        !places.contains(&location),
+                        "{:?} shares {:?} with a live value",
+                        inst,
+                        location
+                    );
+                    places.push(location);
+                }
+            }
+            live.extend(inst.uses());
+        }
+    }
+
+    #[test]
+    fn registers_live_across_calls() {
+        let function = lower("INPUT a\nINPUT b\nPRINT a\nPRINT a + b\n");
+        let allocation = allocate(&function, 13);
+        let (a, b) = (variable(&function, "a"), variable(&function, "b"));
+        let register = |value| match allocation.locations[&value] {
+            Location::Register(register) => register,
+            Location::Stack(_) => panic!("{} was spilled", value),
+        };
+
+        // a is live across the second INPUT, and both across the first PRINT but not the second
+        let calls: Vec<usize> = (0..function.blocks[0].insts.len())
+            .filter(|index| allocation.live_across_calls.contains_key(&(0, *index)))
+            .collect();
+        let live = |call: usize| allocation.live_across_calls[&(0, calls[call])].clone();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(live(0), vec![]);
+        assert_eq!(live(1), vec![register(a)]);
+        let mut both = vec![register(a), register(b)];
+        both.sort_unstable();
+        assert_eq!(live(2), both);
+        assert_eq!(live(3), vec![]);
+    }
+}
@@ -0,0 +1,321 @@
+use super::ast::{Expression, ExpressionKind, Printable, Program, Statement, StatementKind};
+use super::cfg::{Cfg, Terminator};
+use super::diagnostic::Diagnostic;
+use super::interp::{format_number, read_number};
+use super::lex::Lexer;
+use super::parse::Parser;
+use super::resolve::Resolver;
+use super::token::TokenType;
+use super::Diagnostics;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+pub const HELP: &str = "\
+Enter statements to run them. IF and WHILE blocks continue until their ENDIF or ENDWHILE, and an
+expression on its own prints its value. Variables keep their values until :reset.
+
+commands:
+  :vars          list the variables and their values
+  :reset         forget every variable
+  :load <file>   run a teeny file, keeping its variables
+  :help          print this message
+  :quit          leave, as does the end of the input";
+
+/// An interactive session. Statements run as they're entered, and variables keep their values
+/// from one entry to the next.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The variables assigned so far, by name
+    pub variables: BTreeMap<String, f64>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget every variable
+    pub fn reset(&mut self) {
+        self.variables.clear();
+    }
+
+    /// Parse and check an entry: statements, or an expression on its own, which is checked as a
+    /// PRINT of it. The variables of the session count as assigned.
+    pub fn check(&self, source: &str) -> Result<Program, Diagnostics> {
+        let program = parse_entry(source).map_err(|error| vec![error])?;
+
+        let mut resolver = Resolver::new();
+        for name in self.variables.keys() {
+            resolver.declare_variable(name);
+        }
+        resolver.program(&program);
+        let errors: Diagnostics = resolver.diagnostics.into_iter().filter(Diagnostic::is_error).collect();
+
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Run a checked entry, reading INPUT from `input` and printing to `output`. A variable the
+    /// entry reads before assigning has the value zero, as it would in the compiled program.
+    pub fn run(&mut self, program: &Program, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        let cfg = Cfg::new(program);
+        let mut block = cfg.entry;
+
+        loop {
+            for statement in &cfg.blocks[block].statements {
+                match &statement.kind {
+                    StatementKind::Print(Printable::String(text)) => writeln!(output, "{}", text)?,
+                    StatementKind::Print(Printable::Expression(expression)) => {
+                        writeln!(output, "{}", format_number(self.evaluate(expression) as f32 as f64))?
+                    }
+                    StatementKind::Let { name, value } => {
+                        let value = self.evaluate(value) as f32 as f64;
+                        self.variables.insert(name.name.clone(), value);
+                    }
+                    StatementKind::Input(name) => {
+                        // Whatever was printed should be seen before waiting for input
+                        output.flush()?;
+                        let value = read_number(input)? as f32 as f64;
+                        self.variables.insert(name.name.clone(), value);
+                    }
+                    _ => unreachable!("blocks only hold PRINT, LET and INPUT statements"),
+                }
+            }
+
+            block = match &cfg.blocks[block].terminator {
+                Terminator::Jump(target) | Terminator::Goto { target, .. } => *target,
+                // Anything but zero is true, including NaN
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    if self.evaluate(condition) != 0.0 {
+                        *then_block
+                    } else {
+                        *else_block
+                    }
+                }
+                Terminator::Return => return output.flush(),
+            };
+        }
+    }
+
+    /// Evaluate an expression the way the compiled program would. Variables hold single precision
+    /// floats, and arithmetic on nothing but variables is rounded to single precision too.
+    pub fn evaluate(&self, expression: &Expression) -> f64 {
+        match &expression.kind {
+            ExpressionKind::Number(value) => *value,
+            ExpressionKind::Ident(name) => self.variables.get(name).copied().unwrap_or(0.0),
+            ExpressionKind::Unary(op, operand) => op.apply(self.evaluate(operand)),
+            ExpressionKind::Binary(op, left, right) => {
+                let value = op.apply(self.evaluate(left), self.evaluate(right));
+                if expression.is_float() {
+                    value as f32 as f64
+                } else {
+                    value
+                }
+            }
+        }
+    }
+
+    /// Check and run an entry, printing any errors in it against the source they came from
+    fn enter(
+        &mut self,
+        source: &str,
+        file_name: &str,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        match self.check(source) {
+            Ok(program) => self.run(&program, input, output),
+            Err(errors) => {
+                for error in errors {
+                    write!(output, "{}", error.render(file_name, source))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Carry out a `:` command. Returns false for `:quit`.
+    fn command(&mut self, line: &str, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match (command, argument) {
+            (":vars", "") => {
+                for (name, value) in &self.variables {
+                    writeln!(output, "{} = {}", name, format_number(*value))?;
+                }
+            }
+            (":reset", "") => self.reset(),
+            (":load", "") => writeln!(output, "error: :load needs a file")?,
+            (":load", path) => match fs::read_to_string(path) {
+                Ok(source) => self.enter(&source, path, input, output)?,
+                Err(why) => writeln!(output, "error: couldn't read {}: {}", path, why)?,
+            },
+            (":help", "") => writeln!(output, "{}", HELP)?,
+            (":quit", "") => return Ok(false),
+            _ => writeln!(output, "error: unknown command {}, :help lists them", line)?,
+        }
+        Ok(true)
+    }
+}
+
+/// Run an interactive session, reading entries from `input`, which INPUT statements read from too,
+/// and writing everything to `output`. Prompts are written only if `prompt` is set, for someone
+/// typing at a terminal.
+pub fn run(input: &mut impl BufRead, output: &mut impl Write, prompt: bool) -> io::Result<()> {
+    let mut session = Session::new();
+    let mut entry = String::new();
+
+    loop {
+        if prompt {
+            write!(output, "{}", if entry.is_empty() { "> " } else { "... " })?;
+            output.flush()?;
+        }
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            // An unfinished block is run anyway, so the error saying what it lacks is printed
+            if !entry.is_empty() {
+                session.enter(&entry, "<repl>", input, output)?;
+            }
+            return output.flush();
+        }
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+
+        if entry.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with(':') {
+                if !session.command(trimmed, input, output)? {
+                    return output.flush();
+                }
+                continue;
+            }
+        }
+
+        entry.push_str(&line);
+        if open_blocks(&entry) == 0 {
+            session.enter(&entry, "<repl>", input, output)?;
+            entry.clear();
+        }
+    }
+}
+
+/// Parse an entry as a program, unless it starts with something other than a statement, in which
+/// case it's an expression on a line of its own to print
+fn parse_entry(source: &str) -> Result<Program, Diagnostic> {
+    let mut parser = Parser::new(Lexer::new(source))?;
+    let statement_starts = [
+        TokenType::Print,
+        TokenType::If,
+        TokenType::While,
+        TokenType::Label,
+        TokenType::Goto,
+        TokenType::Let,
+        TokenType::Input,
+        TokenType::Newline,
+        TokenType::Eof,
+    ];
+    if statement_starts.iter().any(|kind| parser.check_token(*kind)) {
+        return parser.program();
+    }
+
+    let expression = parser.expression()?;
+    if !parser.check_token(TokenType::Eof) {
+        parser.nl()?;
+    }
+    if !parser.check_token(TokenType::Eof) {
+        return Err(parser.error("Expected one expression or statements, not both"));
+    }
+    Ok(Program {
+        statements: vec![Statement {
+            span: expression.span,
+            kind: StatementKind::Print(Printable::Expression(expression)),
+        }],
+    })
+}
+
+/// Count the IF and WHILE blocks the source opens without closing. Source that doesn't lex counts
+/// as complete, so the error is reported straight away.
+fn open_blocks(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut open = 0usize;
+    loop {
+        match lexer.get_token().map(|token| token.kind) {
+            Ok(TokenType::If | TokenType::While) => open += 1,
+            Ok(TokenType::EndIf | TokenType::EndWhile) => open = open.saturating_sub(1),
+            Ok(TokenType::Eof) | Err(_) => return open,
+            Ok(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str, prompt: bool) -> String {
+        let mut output = Vec::new();
+        run(&mut input.as_bytes(), &mut output, prompt).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn variables_last_from_one_entry_to_the_next() {
+        let input = "\
+LET a = 2
+a * 3
+WHILE a < 4 REPEAT
+LET a = a + 1
+ENDWHILE
+:vars
+INPUT b
+7
+b + a
+PRINT c
+:bogus
+:reset
+:vars
+a
+";
+        assert_eq!(
+            session(input, false),
+            "\
+6.00
+a = 4.00
+11.00
+error: Referencing variable before assignment: c
+ --> <repl>:1:7
+  |
+1 | PRINT c
+  |       ^
+error: unknown command :bogus, :help lists them
+error: Referencing variable before assignment: a
+ --> <repl>:1:1
+  |
+1 | a
+  | ^
+"
+        );
+    }
+
+    #[test]
+    fn blocks_prompt_until_they_are_closed() {
+        let input = "IF 1 == 1 THEN\nPRINT \"yes\"\nENDIF\n:quit\nPRINT \"never\"\n";
+        assert_eq!(session(input, true), "> ... ... yes\n> ");
+    }
+}
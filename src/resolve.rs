@@ -0,0 +1,186 @@
+use super::ast::{Expression, ExpressionKind, Ident, Printable, Program, Statement, StatementKind};
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+use super::symbols::{SymbolKind, SymbolTable, Type, UseKind, PROGRAM_SCOPE};
+
+/// Semantic analysis pass over the AST.
+/// Builds the symbol table for a program and reports names that are used wrongly.
+#[derive(Debug)]
+pub struct Resolver {
+    pub symbols: SymbolTable,
+    pub diagnostics: Vec<Diagnostic>,
+    gotos: Vec<Ident>,
+    unassigned_reads: Vec<(String, Span)>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            diagnostics: Vec::new(),
+            gotos: Vec::new(),            // GOTO targets, checked once every label is known
+            unassigned_reads: Vec::new(), // Variables read before their first assignment in the text
+        }
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        self.statements(&program.statements);
+
+        // Check that each label referenced in a GOTO is declared
+        for target in std::mem::take(&mut self.gotos) {
+            match self.symbols.lookup(PROGRAM_SCOPE, SymbolKind::Label, &target.name) {
+                Some(label) => self.symbols.add_use(label, target.span, UseKind::Read),
+                None => self.diagnostics.push(Diagnostic::error(
+                    &format!("Label {} referenced but not declared", target.name),
+                    target.span,
+                )),
+            }
+        }
+
+        // A read can come before the first assignment in the text when a GOTO jumps backwards,
+        // so only variables that are never assigned anywhere are an error here. Whether they are
+        // assigned on every path to the read is up to the definite-assignment analysis.
+        for (name, span) in std::mem::take(&mut self.unassigned_reads) {
+            match self.symbols.lookup(PROGRAM_SCOPE, SymbolKind::Variable, &name) {
+                Some(variable) => self.symbols.add_use(variable, span, UseKind::Read),
+                None => self.diagnostics.push(Diagnostic::error(
+                    &format!("Referencing variable before assignment: {}", name),
+                    span,
+                )),
+            }
+        }
+
+        self.diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    }
+
+    /// Declare a variable that has a value before the program starts, as the REPL's variables do
+    /// from one entry to the next
+    pub fn declare_variable(&mut self, name: &str) {
+        self.assign(&Ident {
+            name: name.to_owned(),
+            span: Span::default(),
+        });
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(Printable::String(_)) => {}
+            StatementKind::Print(Printable::Expression(expression)) => self.expression(expression),
+            StatementKind::If { condition, body } | StatementKind::While { condition, body } => {
+                self.expression(condition);
+                self.statements(body);
+            }
+            StatementKind::Label { name, .. } => {
+                // Labels can be jumped to from anywhere in the program, even into a loop body
+                let declared = self
+                    .symbols
+                    .declare(PROGRAM_SCOPE, SymbolKind::Label, &name.name, None, name.span);
+
+                // Make sure this label doesn't already exist.
+                if let Err(existing) = declared {
+                    self.diagnostics.push(
+                        Diagnostic::error(&format!("Label already exists: {}", name.name), name.span)
+                            .with_label(self.symbols.symbol(existing).declared, "first declared here"),
+                    );
+                }
+            }
+            StatementKind::Goto(target) => self.gotos.push(target.clone()),
+            StatementKind::Let { name, value } => {
+                self.assign(name);
+                self.expression(value);
+            }
+            StatementKind::Input(name) => self.assign(name),
+        }
+    }
+
+    /// If variable doesn't already exist, declare it
+    fn assign(&mut self, name: &Ident) {
+        match self.symbols.lookup(PROGRAM_SCOPE, SymbolKind::Variable, &name.name) {
+            Some(variable) => self.symbols.add_use(variable, name.span, UseKind::Write),
+            None => {
+                // Variables are visible to the whole program, wherever they are first assigned
+                let declared = self.symbols.declare(
+                    PROGRAM_SCOPE,
+                    SymbolKind::Variable,
+                    &name.name,
+                    Some(Type::Float),
+                    name.span,
+                );
+                debug_assert!(declared.is_ok());
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Number(_) => {}
+            ExpressionKind::Ident(name) => match self.symbols.lookup(PROGRAM_SCOPE, SymbolKind::Variable, name) {
+                Some(variable) => self.symbols.add_use(variable, expression.span, UseKind::Read),
+                None => self.unassigned_reads.push((name.clone(), expression.span)),
+            },
+            ExpressionKind::Unary(_, operand) => self.expression(operand),
+            ExpressionKind::Binary(_, left, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn resolve(source: &str) -> Resolver {
+        let mut resolver = Resolver::new();
+        resolver.program(&parse(source).unwrap());
+        resolver
+    }
+
+    #[test]
+    fn duplicate_label_points_at_the_first() {
+        let source = "LABEL top\nPRINT 1\nLABEL top\n";
+        let resolver = resolve(source);
+        assert_eq!(resolver.diagnostics.len(), 1);
+        let diagnostic = &resolver.diagnostics[0];
+        assert_eq!(diagnostic.span, Span::new(24, 27));
+        assert_eq!(diagnostic.labels[0].span, Span::new(6, 9));
+        assert_eq!(diagnostic.labels[0].message, "first declared here");
+        assert_eq!(
+            diagnostic.render("loop.teeny", source),
+            "\
+error: Label already exists: top
+ --> loop.teeny:3:7
+  |
+3 | LABEL top
+  |       ^^^
+1 | LABEL top
+  |       --- first declared here
+"
+        );
+    }
+
+    #[test]
+    fn variables_outlive_the_block_they_are_first_assigned_in() {
+        let resolver = resolve("WHILE 0 > 1 REPEAT\n    LET n = 1\nENDWHILE\nPRINT n\n");
+        assert!(resolver.diagnostics.is_empty());
+        let n = resolver
+            .symbols
+            .lookup(PROGRAM_SCOPE, SymbolKind::Variable, "n")
+            .unwrap();
+        assert_eq!(resolver.symbols.symbol(n).uses.len(), 1);
+    }
+}
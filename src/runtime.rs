@@ -0,0 +1,567 @@
+use super::elf;
+use super::encode::{Alu, Assembler, Label, Mem, Shift};
+use super::x86::{Cond, Gpr, SseOp, Xmm};
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_EXIT_GROUP: u64 = 231;
+
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const MANTISSA: u64 = 0x000f_ffff_ffff_ffff;
+
+/// The runtime of executables built without a C library, doing input and output through system calls.
+///
+/// Its routines may clobber any register the C calling convention lets a function clobber,
+/// which includes every SSE register.
+pub struct Runtime {
+    /// Print xmm0 the way `printf("%.2f\n")` does
+    pub print_number: Label,
+    /// Read a number into xmm0 the way `scanf("%lf")` does, reading zero and skipping
+    /// a word of input if it isn't a number. Only numbers with at most 15 significant digits and
+    /// a power of ten of at most 10^22 are correctly rounded; see `emit_input`.
+    pub input: Label,
+    /// Read a byte of standard input into rax, or -1 at the end of input
+    read_byte: Label,
+    /// Give back the byte in rax, for the next read_byte to return
+    unread: Label,
+    /// The byte given back, or -1
+    peeked: Label,
+    /// Buffer for the read system call
+    byte: Label,
+}
+
+impl Runtime {
+    pub fn new(asm: &mut Assembler) -> Self {
+        Self {
+            print_number: asm.new_label(),
+            input: asm.new_label(),
+            read_byte: asm.new_label(),
+            unread: asm.new_label(),
+            peeked: asm.new_label(),
+            byte: asm.new_label(),
+        }
+    }
+
+    /// Emit the entry point, which runs main and exits with what it returns
+    pub fn start(&self, asm: &mut Assembler, main: Label) {
+        asm.call(main);
+        asm.mov(Gpr::Rdi, Gpr::Rax);
+        asm.mov_imm(Gpr::Rax, SYS_EXIT_GROUP);
+        asm.syscall();
+    }
+
+    /// Emit a write of `len` bytes at `buffer` to standard output
+    pub fn write(&self, asm: &mut Assembler, buffer: Mem, len: usize) {
+        asm.mov_imm(Gpr::Rax, SYS_WRITE);
+        asm.mov_imm(Gpr::Rdi, 1);
+        asm.lea(Gpr::Rsi, buffer);
+        asm.mov_imm(Gpr::Rdx, len as u64);
+        asm.syscall();
+    }
+
+    /// Emit the routines
+    pub fn routines(&self, asm: &mut Assembler) {
+        self.emit_print_number(asm);
+        self.emit_input(asm);
+        self.emit_read_byte(asm);
+    }
+
+    /// Emit the runtime's writable data, starting on a page of its own, and return where it starts.
+    /// `offset` is how far into a page the image is loaded.
+    pub fn data(&self, asm: &mut Assembler, offset: usize) -> usize {
+        let writable = (asm.code.len() + offset).next_multiple_of(elf::PAGE_SIZE) - offset;
+        asm.pad_to(writable);
+
+        asm.bind(self.peeked);
+        asm.bytes(&u64::MAX.to_le_bytes());
+        asm.bind(self.byte);
+        asm.bytes(&[0; 8]);
+
+        writable
+    }
+
+    fn emit_print_number(&self, asm: &mut Assembler) {
+        let special = asm.new_label();
+        let large = asm.new_label();
+        let sign = asm.new_label();
+
+        asm.bind(self.print_number);
+        asm.push(Gpr::Rbp);
+        asm.mov(Gpr::Rbp, Gpr::Rsp);
+        asm.alu_imm(Alu::Sub, Gpr::Rsp, 1024);
+
+        // The text is built backwards in r8 from the top of the frame, and the digits of large
+        // numbers at the bottom of the frame
+        asm.mov(Gpr::R8, Gpr::Rbp);
+        put(asm, b'\n');
+
+        asm.movq_from_xmm(Gpr::Rax, Xmm(0));
+        asm.mov(Gpr::R9, Gpr::Rax);
+        asm.shift(Shift::Right, Gpr::R9, 63);
+
+        // An exponent of all ones is infinity or NaN
+        asm.mov(Gpr::Rcx, Gpr::Rax);
+        asm.shift(Shift::Left, Gpr::Rcx, 1);
+        asm.shift(Shift::Right, Gpr::Rcx, 53);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 0x7ff);
+        asm.jcc(Cond::Equal, special);
+
+        // From 2^52 up every double is a whole number, but may not fit in an integer register
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 1075);
+        asm.jcc(Cond::AboveEqual, large);
+        self.emit_small_number(asm);
+        asm.jmp(sign);
+
+        asm.bind(large);
+        self.emit_large_number(asm);
+        asm.jmp(sign);
+
+        // Infinity has a zero mantissa, and NaN doesn't
+        let nan = asm.new_label();
+        asm.bind(special);
+        asm.mov_imm(Gpr::Rcx, MANTISSA);
+        asm.alu(Alu::And, Gpr::Rcx, Gpr::Rax);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 0);
+        asm.jcc(Cond::NotEqual, nan);
+        for byte in b"inf".iter().rev() {
+            put(asm, *byte);
+        }
+        asm.jmp(sign);
+        asm.bind(nan);
+        for byte in b"nan".iter().rev() {
+            put(asm, *byte);
+        }
+
+        // Like printf, negative zero and NaN with the sign bit set get a minus too
+        let write = asm.new_label();
+        asm.bind(sign);
+        asm.alu_imm(Alu::Cmp, Gpr::R9, 0);
+        asm.jcc(Cond::Equal, write);
+        put(asm, b'-');
+
+        asm.bind(write);
+        asm.mov(Gpr::Rdx, Gpr::Rbp);
+        asm.alu(Alu::Sub, Gpr::Rdx, Gpr::R8);
+        asm.mov_imm(Gpr::Rax, SYS_WRITE);
+        asm.mov_imm(Gpr::Rdi, 1);
+        asm.mov(Gpr::Rsi, Gpr::R8);
+        asm.syscall();
+        asm.leave();
+        asm.ret();
+    }
+
+    /// Emit the digits of a number below 2^52, whose bits are in rax and biased exponent in rcx,
+    /// rounded to hundredths exactly like printf: to nearest, with ties to even.
+    ///
+    /// The mantissa is split into the whole part in r10 and the fraction as a 64 bit fixed
+    /// point number in rax. The fraction times 100 is then the two digits in the high half of
+    /// the product and what's left to round in the low half. Numbers below 2^-12 have bits past
+    /// the 64th, but are too small to round up anyway.
+    fn emit_small_number(&self, asm: &mut Assembler) {
+        let fraction = asm.new_label();
+        let multiply = asm.new_label();
+        let round_up = asm.new_label();
+        let rounded = asm.new_label();
+        let integer = asm.new_label();
+
+        asm.mov_imm(Gpr::Rdx, MANTISSA);
+        asm.alu(Alu::And, Gpr::Rax, Gpr::Rdx);
+        asm.mov_imm(Gpr::Rdx, 1 << 52);
+        asm.alu(Alu::Or, Gpr::Rax, Gpr::Rdx);
+        asm.zero(Gpr::R10);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 1011);
+        asm.jcc(Cond::Below, multiply);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 1023);
+        asm.jcc(Cond::Below, fraction);
+
+        // The whole part is the mantissa shifted right past the 1075 - exponent fraction bits
+        asm.mov(Gpr::Rsi, Gpr::Rcx);
+        asm.mov_imm(Gpr::Rcx, 1075);
+        asm.alu(Alu::Sub, Gpr::Rcx, Gpr::Rsi);
+        asm.mov(Gpr::R10, Gpr::Rax);
+        asm.shift_cl(Shift::Right, Gpr::R10);
+        asm.mov(Gpr::Rcx, Gpr::Rsi);
+
+        // The fraction is what's left after shifting the whole part out to the left
+        asm.bind(fraction);
+        asm.alu_imm(Alu::Sub, Gpr::Rcx, 1011);
+        asm.shift_cl(Shift::Left, Gpr::Rax);
+        let multiplied = asm.new_label();
+        asm.jmp(multiplied);
+
+        asm.bind(multiply);
+        asm.zero(Gpr::Rax);
+        asm.bind(multiplied);
+        asm.mov_imm(Gpr::Rcx, 100);
+        asm.mul(Gpr::Rcx);
+
+        asm.mov_imm(Gpr::Rsi, 1 << 63);
+        asm.alu(Alu::Cmp, Gpr::Rax, Gpr::Rsi);
+        asm.jcc(Cond::Below, rounded);
+        asm.jcc(Cond::Above, round_up);
+        asm.mov(Gpr::Rdi, Gpr::Rdx);
+        asm.alu_imm(Alu::And, Gpr::Rdi, 1);
+        asm.alu_imm(Alu::Cmp, Gpr::Rdi, 0);
+        asm.jcc(Cond::Equal, rounded);
+        asm.bind(round_up);
+        asm.alu_imm(Alu::Add, Gpr::Rdx, 1);
+        asm.alu_imm(Alu::Cmp, Gpr::Rdx, 100);
+        asm.jcc(Cond::NotEqual, rounded);
+        asm.zero(Gpr::Rdx);
+        asm.alu_imm(Alu::Add, Gpr::R10, 1);
+
+        asm.bind(rounded);
+        asm.mov(Gpr::Rax, Gpr::Rdx);
+        asm.mov_imm(Gpr::Rcx, 10);
+        put_digit(asm, Gpr::Rcx);
+        put_digit(asm, Gpr::Rcx);
+        put(asm, b'.');
+        asm.mov(Gpr::Rax, Gpr::R10);
+        asm.bind(integer);
+        put_digit(asm, Gpr::Rcx);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, 0);
+        asm.jcc(Cond::NotEqual, integer);
+    }
+
+    /// Emit the digits of a whole number at least 2^52, whose bits are in rax and biased exponent in rcx, exactly.
+    /// The mantissa is written out as decimal digits, least significant first, then doubled
+    /// once per power of two in the exponent.
+    fn emit_large_number(&self, asm: &mut Assembler) {
+        let mantissa = asm.new_label();
+        let double = asm.new_label();
+        let digit = asm.new_label();
+        let no_carry = asm.new_label();
+        let doubled = asm.new_label();
+        let next = asm.new_label();
+        let emit = asm.new_label();
+        let emit_digit = asm.new_label();
+
+        asm.alu_imm(Alu::Sub, Gpr::Rcx, 1075);
+        asm.mov_imm(Gpr::Rdx, MANTISSA);
+        asm.alu(Alu::And, Gpr::Rax, Gpr::Rdx);
+        asm.mov_imm(Gpr::Rdx, 1 << 52);
+        asm.alu(Alu::Or, Gpr::Rax, Gpr::Rdx);
+
+        // The digits go from r10 to rsi
+        asm.lea(Gpr::R10, Mem::Base(Gpr::Rbp, -1024));
+        asm.mov(Gpr::Rsi, Gpr::R10);
+        asm.mov_imm(Gpr::Rdi, 10);
+        asm.bind(mantissa);
+        asm.zero(Gpr::Rdx);
+        asm.div(Gpr::Rdi);
+        asm.store_byte(Mem::Base(Gpr::Rsi, 0), Gpr::Rdx);
+        asm.alu_imm(Alu::Add, Gpr::Rsi, 1);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, 0);
+        asm.jcc(Cond::NotEqual, mantissa);
+
+        // Double the digits rcx times, with the carry in rdi
+        asm.bind(double);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 0);
+        asm.jcc(Cond::Equal, emit);
+        asm.zero(Gpr::Rdi);
+        asm.mov(Gpr::Rax, Gpr::R10);
+        asm.bind(digit);
+        asm.alu(Alu::Cmp, Gpr::Rax, Gpr::Rsi);
+        asm.jcc(Cond::Equal, doubled);
+        asm.load_byte(Gpr::Rdx, Mem::Base(Gpr::Rax, 0));
+        asm.alu(Alu::Add, Gpr::Rdx, Gpr::Rdx);
+        asm.alu(Alu::Add, Gpr::Rdx, Gpr::Rdi);
+        asm.zero(Gpr::Rdi);
+        asm.alu_imm(Alu::Cmp, Gpr::Rdx, 10);
+        asm.jcc(Cond::Below, no_carry);
+        asm.alu_imm(Alu::Sub, Gpr::Rdx, 10);
+        asm.mov_imm(Gpr::Rdi, 1);
+        asm.bind(no_carry);
+        asm.store_byte(Mem::Base(Gpr::Rax, 0), Gpr::Rdx);
+        asm.alu_imm(Alu::Add, Gpr::Rax, 1);
+        asm.jmp(digit);
+        asm.bind(doubled);
+        asm.alu_imm(Alu::Cmp, Gpr::Rdi, 0);
+        asm.jcc(Cond::Equal, next);
+        asm.store_byte_imm(Mem::Base(Gpr::Rsi, 0), 1);
+        asm.alu_imm(Alu::Add, Gpr::Rsi, 1);
+        asm.bind(next);
+        asm.alu_imm(Alu::Sub, Gpr::Rcx, 1);
+        asm.jmp(double);
+
+        asm.bind(emit);
+        put(asm, b'0');
+        put(asm, b'0');
+        put(asm, b'.');
+        asm.mov(Gpr::Rax, Gpr::R10);
+        asm.bind(emit_digit);
+        asm.load_byte(Gpr::Rdx, Mem::Base(Gpr::Rax, 0));
+        asm.alu_imm(Alu::Add, Gpr::Rdx, b'0' as i32);
+        asm.alu_imm(Alu::Sub, Gpr::R8, 1);
+        asm.store_byte(Mem::Base(Gpr::R8, 0), Gpr::Rdx);
+        asm.alu_imm(Alu::Add, Gpr::Rax, 1);
+        asm.alu(Alu::Cmp, Gpr::Rax, Gpr::Rsi);
+        asm.jcc(Cond::NotEqual, emit_digit);
+    }
+
+    fn emit_input(&self, asm: &mut Assembler) {
+        let skip_space = asm.new_label();
+        let plus = asm.new_label();
+        let digits = asm.new_label();
+        let after_integer = asm.new_label();
+        let after_fraction = asm.new_label();
+        let exponent = asm.new_label();
+        let exponent_plus = asm.new_label();
+        let exponent_digits = asm.new_label();
+        let exponent_positive = asm.new_label();
+        let scale = asm.new_label();
+        let sign = asm.new_label();
+        let done = asm.new_label();
+        let not_number = asm.new_label();
+        let unread_zero = asm.new_label();
+        let zero = asm.new_label();
+
+        asm.bind(self.input);
+        asm.push(Gpr::Rbp);
+        asm.mov(Gpr::Rbp, Gpr::Rsp);
+        asm.push(Gpr::Rbx);
+        asm.push(Gpr::R12);
+
+        asm.bind(skip_space);
+        asm.call(self.read_byte);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, -1);
+        asm.jcc(Cond::Equal, zero);
+        jump_if_space(asm, skip_space);
+
+        // r8 is set for a negative number
+        asm.zero(Gpr::R8);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'-' as i32);
+        asm.jcc(Cond::NotEqual, plus);
+        asm.mov_imm(Gpr::R8, 1);
+        asm.call(self.read_byte);
+        asm.jmp(digits);
+        asm.bind(plus);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'+' as i32);
+        asm.jcc(Cond::NotEqual, digits);
+        asm.call(self.read_byte);
+
+        // The digits make a whole number in rbx, counted in r9. It is divided by ten to the power
+        // of r10 at the end, the number of digits after the point less the exponent. Both are
+        // exact for up to 15 significant digits and powers up to 10^22, so the result is then
+        // correctly rounded. Past that the power of ten is rounded as it's squared, and the
+        // result can be a few units in the last place away from what scanf reads, which uses
+        // arbitrary precision for such numbers. Near the largest double that can tip it over
+        // into infinity. The interpreter parses with Rust's correctly rounded parser instead.
+        asm.bind(digits);
+        asm.zero(Gpr::R9);
+        asm.zero(Gpr::R10);
+        asm.zero(Gpr::Rbx);
+        self.emit_digits(asm, after_integer, false);
+        asm.bind(after_integer);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'.' as i32);
+        asm.jcc(Cond::NotEqual, after_fraction);
+        asm.call(self.read_byte);
+        self.emit_digits(asm, after_fraction, true);
+        asm.bind(after_fraction);
+        asm.alu_imm(Alu::Cmp, Gpr::R9, 0);
+        asm.jcc(Cond::Equal, not_number);
+
+        // The exponent goes in r12, and r9 is set if it's negative
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'e' as i32);
+        asm.jcc(Cond::Equal, exponent);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'E' as i32);
+        asm.jcc(Cond::NotEqual, scale);
+        asm.bind(exponent);
+        asm.zero(Gpr::R12);
+        asm.zero(Gpr::R9);
+        asm.call(self.read_byte);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'-' as i32);
+        asm.jcc(Cond::NotEqual, exponent_plus);
+        asm.mov_imm(Gpr::R9, 1);
+        asm.call(self.read_byte);
+        asm.jmp(exponent_digits);
+        asm.bind(exponent_plus);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, b'+' as i32);
+        asm.jcc(Cond::NotEqual, exponent_digits);
+        asm.call(self.read_byte);
+        let exponent_done = asm.new_label();
+        asm.bind(exponent_digits);
+        asm.mov(Gpr::Rcx, Gpr::Rax);
+        asm.alu_imm(Alu::Sub, Gpr::Rcx, b'0' as i32);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 9);
+        asm.jcc(Cond::Above, exponent_done);
+        // Anything past this overflows or underflows anyway
+        asm.alu_imm(Alu::Cmp, Gpr::R12, 10_000);
+        let exponent_next = asm.new_label();
+        asm.jcc(Cond::AboveEqual, exponent_next);
+        asm.mov_imm(Gpr::Rdx, 10);
+        asm.imul(Gpr::R12, Gpr::Rdx);
+        asm.alu(Alu::Add, Gpr::R12, Gpr::Rcx);
+        asm.bind(exponent_next);
+        asm.call(self.read_byte);
+        asm.jmp(exponent_digits);
+        asm.bind(exponent_done);
+        asm.alu_imm(Alu::Cmp, Gpr::R9, 0);
+        asm.jcc(Cond::Equal, exponent_positive);
+        asm.alu(Alu::Add, Gpr::R10, Gpr::R12);
+        asm.jmp(scale);
+        asm.bind(exponent_positive);
+        asm.alu(Alu::Sub, Gpr::R10, Gpr::R12);
+
+        // The byte after the number is left for the next read
+        asm.bind(scale);
+        asm.call(self.unread);
+        asm.cvtsi2sd(Xmm(0), Gpr::Rbx);
+        asm.alu_imm(Alu::Cmp, Gpr::Rbx, 0);
+        asm.jcc(Cond::Equal, sign);
+
+        // Raise ten to the power of rcx, the magnitude of r10, in xmm1 by squaring, which is exact
+        // up to 10^22
+        let power = asm.new_label();
+        let skip_factor = asm.new_label();
+        let powered = asm.new_label();
+        let multiply = asm.new_label();
+        asm.mov(Gpr::Rcx, Gpr::R10);
+        asm.alu_imm(Alu::Cmp, Gpr::R10, 0);
+        let positive = asm.new_label();
+        asm.jcc(Cond::GreaterEqual, positive);
+        asm.zero(Gpr::Rcx);
+        asm.alu(Alu::Sub, Gpr::Rcx, Gpr::R10);
+        asm.bind(positive);
+        asm.mov_imm(Gpr::Rdx, 1f64.to_bits());
+        asm.movq_to_xmm(Xmm(1), Gpr::Rdx);
+        asm.mov_imm(Gpr::Rdx, 10f64.to_bits());
+        asm.movq_to_xmm(Xmm(2), Gpr::Rdx);
+        asm.bind(power);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 0);
+        asm.jcc(Cond::Equal, powered);
+        asm.mov(Gpr::Rdx, Gpr::Rcx);
+        asm.alu_imm(Alu::And, Gpr::Rdx, 1);
+        asm.jcc(Cond::Equal, skip_factor);
+        asm.sse(SseOp::Mul, Xmm(1), Xmm(2));
+        asm.bind(skip_factor);
+        asm.sse(SseOp::Mul, Xmm(2), Xmm(2));
+        asm.shift(Shift::Right, Gpr::Rcx, 1);
+        asm.jmp(power);
+
+        asm.bind(powered);
+        asm.alu_imm(Alu::Cmp, Gpr::R10, 0);
+        asm.jcc(Cond::Less, multiply);
+        asm.sse(SseOp::Div, Xmm(0), Xmm(1));
+        asm.jmp(sign);
+        asm.bind(multiply);
+        asm.sse(SseOp::Mul, Xmm(0), Xmm(1));
+
+        asm.bind(sign);
+        asm.alu_imm(Alu::Cmp, Gpr::R8, 0);
+        asm.jcc(Cond::Equal, done);
+        asm.mov_imm(Gpr::Rdx, SIGN_BIT);
+        asm.movq_to_xmm(Xmm(1), Gpr::Rdx);
+        asm.sse(SseOp::Xor, Xmm(0), Xmm(1));
+
+        asm.bind(done);
+        asm.pop(Gpr::R12);
+        asm.pop(Gpr::Rbx);
+        asm.leave();
+        asm.ret();
+
+        // Like scanf("%*s"), skip the rest of the word that isn't a number
+        let skip_word = asm.new_label();
+        asm.bind(not_number);
+        asm.bind(skip_word);
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, -1);
+        asm.jcc(Cond::Equal, zero);
+        jump_if_space(asm, unread_zero);
+        asm.call(self.read_byte);
+        asm.jmp(skip_word);
+        asm.bind(unread_zero);
+        asm.call(self.unread);
+        asm.bind(zero);
+        asm.sse(SseOp::Xor, Xmm(0), Xmm(0));
+        asm.jmp(done);
+    }
+
+    /// Emit a loop adding the digits read to rbx and counting them in r9, jumping to `end` at the
+    /// first byte that isn't a digit. Digits after the point that fit count in r10, and digits
+    /// before it that don't take one off.
+    fn emit_digits(&self, asm: &mut Assembler, end: Label, fraction: bool) {
+        let digit = asm.new_label();
+        let dropped = asm.new_label();
+        let next = asm.new_label();
+        asm.bind(digit);
+        asm.mov(Gpr::Rcx, Gpr::Rax);
+        asm.alu_imm(Alu::Sub, Gpr::Rcx, b'0' as i32);
+        asm.alu_imm(Alu::Cmp, Gpr::Rcx, 9);
+        asm.jcc(Cond::Above, end);
+        asm.alu_imm(Alu::Add, Gpr::R9, 1);
+        // Keep at most 18 significant digits, so the number fits a signed integer
+        asm.mov_imm(Gpr::Rdx, 100_000_000_000_000_000);
+        asm.alu(Alu::Cmp, Gpr::Rbx, Gpr::Rdx);
+        asm.jcc(Cond::AboveEqual, dropped);
+        asm.mov_imm(Gpr::Rdx, 10);
+        asm.imul(Gpr::Rbx, Gpr::Rdx);
+        asm.alu(Alu::Add, Gpr::Rbx, Gpr::Rcx);
+        if fraction {
+            asm.alu_imm(Alu::Add, Gpr::R10, 1);
+        }
+        asm.jmp(next);
+        asm.bind(dropped);
+        if !fraction {
+            asm.alu_imm(Alu::Sub, Gpr::R10, 1);
+        }
+        asm.bind(next);
+        asm.call(self.read_byte);
+        asm.jmp(digit);
+    }
+
+    fn emit_read_byte(&self, asm: &mut Assembler) {
+        let read = asm.new_label();
+        let end = asm.new_label();
+
+        asm.bind(self.read_byte);
+        asm.load(Gpr::Rax, Mem::Rip(self.peeked));
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, -1);
+        asm.jcc(Cond::Equal, read);
+        asm.mov_imm(Gpr::Rcx, u64::MAX);
+        asm.store(Mem::Rip(self.peeked), Gpr::Rcx);
+        asm.ret();
+
+        asm.bind(read);
+        asm.mov_imm(Gpr::Rax, SYS_READ);
+        asm.zero(Gpr::Rdi);
+        asm.lea(Gpr::Rsi, Mem::Rip(self.byte));
+        asm.mov_imm(Gpr::Rdx, 1);
+        asm.syscall();
+        asm.alu_imm(Alu::Cmp, Gpr::Rax, 1);
+        asm.jcc(Cond::NotEqual, end);
+        asm.load_byte(Gpr::Rax, Mem::Rip(self.byte));
+        asm.ret();
+        asm.bind(end);
+        asm.mov_imm(Gpr::Rax, u64::MAX);
+        asm.ret();
+
+        asm.bind(self.unread);
+        asm.store(Mem::Rip(self.peeked), Gpr::Rax);
+        asm.ret();
+    }
+}
+
+/// Emit a byte of text, in front of what's been built so far in r8
+fn put(asm: &mut Assembler, byte: u8) {
+    asm.alu_imm(Alu::Sub, Gpr::R8, 1);
+    asm.store_byte_imm(Mem::Base(Gpr::R8, 0), byte);
+}
+
+/// Divide rax by the register holding ten and emit the remainder as a digit
+fn put_digit(asm: &mut Assembler, ten: Gpr) {
+    asm.zero(Gpr::Rdx);
+    asm.div(ten);
+    asm.alu_imm(Alu::Add, Gpr::Rdx, b'0' as i32);
+    asm.alu_imm(Alu::Sub, Gpr::R8, 1);
+    asm.store_byte(Mem::Base(Gpr::R8, 0), Gpr::Rdx);
+}
+
+/// Emit a jump if the byte in rax is whitespace, as isspace counts it
+fn jump_if_space(asm: &mut Assembler, target: Label) {
+    asm.alu_imm(Alu::Cmp, Gpr::Rax, b' ' as i32);
+    asm.jcc(Cond::Equal, target);
+    asm.mov(Gpr::Rcx, Gpr::Rax);
+    asm.alu_imm(Alu::Sub, Gpr::Rcx, 9);
+    asm.alu_imm(Alu::Cmp, Gpr::Rcx, 4);
+    asm.jcc(Cond::BelowEqual, target);
+}
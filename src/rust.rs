@@ -0,0 +1,433 @@
+use super::ast::{BinaryOp, Expression, ExpressionKind, Printable, Program, Statement, StatementKind};
+use super::cfg::{Cfg, Terminator};
+use super::emitter::{Emitter, Section};
+use super::optimize::has_label;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Rust keywords, which variables are named with as raw identifiers
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else", "enum",
+    "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move",
+    "mut", "override", "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Keywords that can't be raw identifiers either. Variables named after them get an underscore
+/// after, which no teeny identifier can have.
+const RESERVED: &[&str] = &["crate", "self", "Self", "super"];
+
+/// Lints the generated code would set off, and what the helpers use
+const INCLUDES: &str = r#"#![allow(dead_code, non_snake_case, unreachable_code, unused_assignments, unused_mut, unused_parens, unused_variables)]
+
+use std::io::BufRead;
+"#;
+
+/// Helpers for printing and reading numbers like the C program does
+const PRELUDE: &str = r#"/// Format a number the way printf("%.2f") does
+fn format_number(value: f32) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        format!("{}nan", sign)
+    } else if value.is_infinite() {
+        format!("{}inf", sign)
+    } else {
+        format!("{:.2}", value as f64)
+    }
+}
+
+/// Reads numbers from standard input the way scanf("%lf") does
+struct NumberReader {
+    pending: String,
+}
+
+impl NumberReader {
+    /// Read a number. If the next word isn't a number it's skipped and the number read is zero,
+    /// as it is at the end of the input.
+    fn read(&mut self) -> f32 {
+        while self.pending.trim().is_empty() {
+            self.pending.clear();
+            match std::io::stdin().lock().read_line(&mut self.pending) {
+                Ok(0) | Err(_) => return 0.0,
+                Ok(_) => {}
+            }
+        }
+
+        let text = self.pending.trim_start().to_owned();
+        let bytes = text.as_bytes();
+        let digits = |mut end: usize| {
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+            end
+        };
+        let start = if matches!(bytes.first(), Some(b'+' | b'-')) { 1 } else { 0 };
+        let mut end = digits(start);
+        let mut seen_digits = end > start;
+        if bytes.get(end) == Some(&b'.') {
+            let fraction_end = digits(end + 1);
+            seen_digits |= fraction_end > end + 1;
+            end = fraction_end;
+        }
+        if !seen_digits {
+            let word = text.find(char::is_whitespace).unwrap_or(text.len());
+            self.pending = text[word..].to_owned();
+            return 0.0;
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let sign = if matches!(bytes.get(end + 1), Some(b'+' | b'-')) { 1 } else { 0 };
+            let exponent_end = digits(end + 1 + sign);
+            if exponent_end > end + 1 + sign {
+                end = exponent_end;
+            }
+        }
+
+        self.pending = text[end..].to_owned();
+        text[..end].parse().unwrap_or(0.0)
+    }
+}
+"#;
+
+/// The Rust type of a C expression. C evaluates comparisons as ints, which are only ever
+/// compared again, so here they stay bools until they meet a number.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Type {
+    F32,
+    F64,
+    Bool,
+}
+
+/// Walks the AST and writes the equivalent Rust program through the emitter.
+/// Variables are `f32` like the C program's floats, and number literals `f64` like its doubles.
+#[derive(Debug)]
+pub struct RustGen<'a, W: Write> {
+    emitter: &'a mut Emitter<W>,
+    declared: HashSet<String>,
+}
+
+impl<'a, W: Write> RustGen<'a, W> {
+    pub fn new(emitter: &'a mut Emitter<W>) -> Self {
+        Self {
+            emitter,
+            declared: HashSet::new(),
+        }
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        self.emitter.line_to(Section::Includes, INCLUDES);
+        self.emitter.line_to(Section::Functions, PRELUDE);
+        self.emitter.begin_main("fn main() {");
+        self.emitter.line_to(
+            Section::Declarations,
+            "let mut number_reader = NumberReader { pending: String::new() };",
+        );
+
+        // Rust has no goto, so a program with labels runs its blocks from a state machine
+        if has_label(&program.statements) {
+            self.dispatch(&Cfg::new(program));
+        } else {
+            for statement in &program.statements {
+                self.statement(statement);
+            }
+        }
+
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Emit a statement other than a LABEL or GOTO
+    pub fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(Printable::String(text)) => {
+                self.emitter.emit_line(&format!("println!(\"{}\");", escape(text)))
+            }
+            StatementKind::Print(Printable::Expression(expression)) => {
+                let value = self.convert(expression, Type::F32);
+                self.emitter
+                    .emit_line(&format!("println!(\"{{}}\", format_number({}));", value));
+            }
+            StatementKind::If { condition, body } => {
+                let condition = self.convert(condition, Type::Bool);
+                self.block(&format!("if {}", condition), body);
+            }
+            StatementKind::While { condition, body } => {
+                let condition = self.convert(condition, Type::Bool);
+                self.block(&format!("while {}", condition), body);
+            }
+            StatementKind::Let { name, value } => {
+                let name = self.variable(&name.name);
+                let value = self.convert(value, Type::F32);
+                self.emitter.emit_line(&format!("{} = {};", name, value));
+            }
+            StatementKind::Input(name) => {
+                let name = self.variable(&name.name);
+                self.emitter.emit_line(&format!("{} = number_reader.read();", name));
+            }
+            StatementKind::Label { .. } | StatementKind::Goto(_) => {
+                unreachable!("programs with labels are emitted from their control flow graph")
+            }
+        }
+    }
+
+    fn block(&mut self, head: &str, body: &[Statement]) {
+        self.emitter.emit_line(&format!("{} {{", head));
+        self.emitter.indent();
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Emit a loop matching on the block to run next, with an arm per reachable block
+    fn dispatch(&mut self, cfg: &Cfg) {
+        let reachable = cfg.reachable();
+
+        self.emitter.emit_line(&format!("let mut next_block = {};", cfg.entry));
+        self.emitter.emit_line("loop {");
+        self.emitter.indent();
+        self.emitter.emit_line("match next_block {");
+        self.emitter.indent();
+
+        for (id, block) in cfg.blocks.iter().enumerate().filter(|(id, _)| reachable[*id]) {
+            match &block.label {
+                Some(label) => self.emitter.emit_line(&format!("{} => {{ // {}", id, label)),
+                None => self.emitter.emit_line(&format!("{} => {{", id)),
+            }
+            self.emitter.indent();
+
+            for statement in &block.statements {
+                self.statement(statement);
+            }
+            match &block.terminator {
+                Terminator::Jump(target) | Terminator::Goto { target, .. } => {
+                    self.emitter.emit_line(&format!("next_block = {};", target));
+                }
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    let condition = self.convert(condition, Type::Bool);
+                    self.emitter.emit_line(&format!(
+                        "next_block = if {} {{ {} }} else {{ {} }};",
+                        condition, then_block, else_block
+                    ));
+                }
+                Terminator::Return => self.emitter.emit_line("return;"),
+            }
+
+            self.emitter.dedent();
+            self.emitter.emit_line("}");
+        }
+
+        self.emitter.emit_line("_ => unreachable!(),");
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+        self.emitter.dedent();
+        self.emitter.emit_line("}");
+    }
+
+    /// Return an expression as Rust converted to a type, the way C converts on assignment
+    fn convert(&mut self, expression: &Expression, to: Type) -> String {
+        let (code, from) = self.expression(expression);
+        cast(code, from, to)
+    }
+
+    /// Return an expression as Rust, along with its type. Anything but a literal, a variable or a
+    /// negation comes back in parentheses.
+    fn expression(&mut self, expression: &Expression) -> (String, Type) {
+        match &expression.kind {
+            ExpressionKind::Number(value) => (format!("{:?}", value), Type::F64),
+            ExpressionKind::Ident(name) => (self.variable(name), Type::F32),
+            ExpressionKind::Unary(op, operand) => {
+                let (operand, ty) = self.expression(operand);
+                (format!("{}{}", op.as_str(), operand), ty)
+            }
+            ExpressionKind::Binary(op, left, right) => {
+                let (left, left_type) = self.expression(left);
+                let (right, right_type) = self.expression(right);
+
+                // The usual arithmetic conversions: a float meeting a double becomes a double,
+                // and a comparison's result becomes whatever it meets
+                let ty = match (left_type, right_type) {
+                    (Type::F32 | Type::Bool, Type::F32) | (Type::F32, Type::Bool) => Type::F32,
+                    _ => Type::F64,
+                };
+                let left = cast(left, left_type, ty);
+                let right = cast(right, right_type, ty);
+
+                let code = format!("({} {} {})", left, op.as_str(), right);
+                match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => (code, ty),
+                    _ => (code, Type::Bool),
+                }
+            }
+        }
+    }
+
+    /// Return the Rust name of a variable, declaring it the first time it's seen
+    fn variable(&mut self, name: &str) -> String {
+        let rust_name = if KEYWORDS.contains(&name) {
+            format!("r#{}", name)
+        } else if RESERVED.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_owned()
+        };
+        if self.declared.insert(name.to_owned()) {
+            self.emitter
+                .line_to(Section::Declarations, &format!("let mut {}: f32 = 0.0;", rust_name));
+        }
+        rust_name
+    }
+}
+
+/// Convert code of one type to another. A number is true if it isn't zero, and NaN is true too.
+fn cast(code: String, from: Type, to: Type) -> String {
+    match (from, to) {
+        _ if from == to => code,
+        (Type::Bool, number) => format!("(u8::from({}) as {})", code, type_name(number)),
+        (_, Type::Bool) => format!("({} != 0.0)", code),
+        (_, number) => format!("({} as {})", code, type_name(number)),
+    }
+}
+
+fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::Bool => "bool",
+    }
+}
+
+/// Escape the braces of a string that goes in a format string
+fn escape(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, Emit, Options};
+
+    /// Return the code generated for the program from the start of its main function
+    fn main_of(source: &str) -> String {
+        let options = Options {
+            emit: Emit::Rust,
+            ..Options::default()
+        };
+        let code = String::from_utf8(compile_str(source, &options).unwrap().code).unwrap();
+        code[code.find("fn main").unwrap()..].to_owned()
+    }
+
+    #[test]
+    fn structured_program() {
+        let source = "\
+PRINT \"hi\"
+INPUT n
+LET i = 0
+WHILE i < n REPEAT
+    IF i == 2 THEN
+        PRINT i * -1
+    ENDIF
+    LET i = i + 1
+ENDWHILE
+";
+        assert_eq!(
+            main_of(source),
+            "\
+fn main() {
+    let mut number_reader = NumberReader { pending: String::new() };
+    let mut n: f32 = 0.0;
+    let mut i: f32 = 0.0;
+    println!(\"hi\");
+    n = number_reader.read();
+    i = (0.0 as f32);
+    while (i < n) {
+        if ((i as f64) == 2.0) {
+            println!(\"{}\", format_number((((i as f64) * -1.0) as f32)));
+        }
+        i = (((i as f64) + 1.0) as f32);
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn goto_program() {
+        let source = "\
+LET i = 0
+LABEL top
+LET i = i + 1
+IF i < 3 THEN
+    GOTO top
+ENDIF
+PRINT i
+";
+        assert_eq!(
+            main_of(source),
+            "\
+fn main() {
+    let mut number_reader = NumberReader { pending: String::new() };
+    let mut i: f32 = 0.0;
+    let mut next_block = 0;
+    loop {
+        match next_block {
+            0 => {
+                i = (0.0 as f32);
+                next_block = 1;
+            }
+            1 => { // top
+                i = (((i as f64) + 1.0) as f32);
+                next_block = if ((i as f64) < 3.0) { 2 } else { 3 };
+            }
+            2 => {
+                next_block = 1;
+            }
+            3 => {
+                println!(\"{}\", format_number(i));
+                return;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn reserved_names() {
+        let source = "\
+LET new = 1
+LET lambda = 2
+LET fn = 3
+LET self = 4
+PRINT new + lambda / fn - self
+IF new < lambda == fn > self THEN
+    PRINT \"chained\"
+ENDIF
+";
+        assert_eq!(
+            main_of(source),
+            "\
+fn main() {
+    let mut number_reader = NumberReader { pending: String::new() };
+    let mut new: f32 = 0.0;
+    let mut lambda: f32 = 0.0;
+    let mut r#fn: f32 = 0.0;
+    let mut self_: f32 = 0.0;
+    new = (1.0 as f32);
+    lambda = (2.0 as f32);
+    r#fn = (3.0 as f32);
+    self_ = (4.0 as f32);
+    println!(\"{}\", format_number(((new + (lambda / r#fn)) - self_)));
+    if ((u8::from(((u8::from((new < lambda)) as f32) == r#fn)) as f32) > self_) {
+        println!(\"chained\");
+    }
+}
+"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use super::json::Value;
+use super::span::{LineIndex, Span};
+
+/// Where a line of generated code came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    /// The line of the generated code, counting from 1
+    pub line: usize,
+    /// The statement the line was generated from
+    pub span: Span,
+    /// The line the statement starts on, counting from 1
+    pub source_line: usize,
+    /// The column the statement starts at, counting from 1
+    pub source_column: usize,
+}
+
+/// Maps lines of generated code back to the teeny statements they were generated from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    /// In order of line. Lines not generated from a statement have no mapping.
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Make the map from runs of lines, each from the line given to the next run's, with the span
+    /// of the statement they were generated from if there was one. The lines of the runs count
+    /// from `start`, the line of the output they start on counting from 0.
+    pub fn new(start: usize, runs: &[(usize, Option<Span>)], source: &str) -> Self {
+        let mut mappings = Vec::new();
+        let lines = LineIndex::new(source);
+
+        for (index, (first, span)) in runs.iter().enumerate() {
+            let Some(span) = span else { continue };
+            let end = runs.get(index + 1).map_or(first + 1, |(next, _)| *next);
+            let (source_line, source_column) = lines.line_col(span.start);
+            for line in *first..end {
+                mappings.push(Mapping {
+                    line: start + line + 1,
+                    span: *span,
+                    source_line,
+                    source_column,
+                });
+            }
+        }
+
+        Self { mappings }
+    }
+
+    /// Return where a line of the generated code, counting from 1, came from
+    pub fn find(&self, line: usize) -> Option<&Mapping> {
+        let index = self.mappings.binary_search_by_key(&line, |mapping| mapping.line).ok()?;
+        Some(&self.mappings[index])
+    }
+
+    /// Write the map as JSON, naming the generated file and the teeny file:
+    ///
+    /// ```text
+    /// {"version":1,"file":"out.c","source":"loop.teeny","mappings":[
+    ///     {"line":5,"start":0,"end":9,"sourceLine":1,"sourceColumn":1}, ...]}
+    /// ```
+    ///
+    /// `start` and `end` are the character offsets of the statement in the source.
+    pub fn to_json(&self, file: &str, source: &str) -> String {
+        let mappings = self
+            .mappings
+            .iter()
+            .map(|mapping| {
+                Value::object(vec![
+                    ("line", mapping.line.into()),
+                    ("start", mapping.span.start.into()),
+                    ("end", mapping.span.end.into()),
+                    ("sourceLine", mapping.source_line.into()),
+                    ("sourceColumn", mapping.source_column.into()),
+                ])
+            })
+            .collect();
+
+        Value::object(vec![
+            ("version", 1.into()),
+            ("file", file.into()),
+            ("source", source.into()),
+            ("mappings", Value::Array(mappings)),
+        ])
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_runs_of_lines_to_statements() {
+        let source = "LET n = 0\n  PRINT n\n";
+        // Two lines of a header, a line from each statement, then a line from neither
+        let runs = [(0, Some(Span::new(0, 9))), (1, Some(Span::new(12, 19))), (2, None)];
+        let map = SourceMap::new(2, &runs, source);
+
+        assert_eq!(map.find(2), None);
+        assert_eq!(map.find(3).map(|mapping| mapping.span), Some(Span::new(0, 9)));
+        let print = map.find(4).unwrap();
+        assert_eq!((print.source_line, print.source_column), (2, 3));
+        assert_eq!(map.find(5), None);
+
+        assert_eq!(
+            map.to_json("out.c", "print.teeny"),
+            concat!(
+                r#"{"version":1,"file":"out.c","source":"print.teeny","mappings":["#,
+                r#"{"line":3,"start":0,"end":9,"sourceLine":1,"sourceColumn":1},"#,
+                r#"{"line":4,"start":12,"end":19,"sourceLine":2,"sourceColumn":3}]}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,61 @@
+/// A range of character positions in the source, `end` exclusive.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Return a span covering both this span and the other one
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Return the 1-based line and column of a character position in the source.
+pub fn line_col(source: &str, pos: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut col = 1;
+
+    for c in source.chars().take(pos) {
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+
+    (line, col)
+}
+
+/// Where each line of a source starts, to find the lines and columns of many positions in it
+/// without counting from the start of the source every time
+#[derive(Clone, Debug, Default)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(
+            source
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| *c == '\n')
+                .map(|(pos, _)| pos + 1),
+        );
+        Self { starts }
+    }
+
+    /// Return the 1-based line and column of a character position, as `line_col` does
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= pos);
+        (line, pos - self.starts[line - 1] + 1)
+    }
+}
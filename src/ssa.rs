@@ -0,0 +1,407 @@
+use super::ir::{Block, BlockId, Function, Inst, Terminator, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Convert a function from loads and stores of variables into SSA form.
+///
+/// Phi nodes are placed on the iterated dominance frontier of every block assigning a variable,
+/// then a walk of the dominator tree renames each load to the definition reaching it.
+/// Every store becomes a copy into a fresh value named after the variable, which keeps dumps
+/// readable and leaves the copies for copy propagation to clean up.
+/// Blocks that can't be reached are removed first, since nothing dominates them.
+pub fn construct(function: &mut Function) {
+    function.remove_unreachable_blocks();
+
+    let idom = function.dominators();
+    let frontiers = dominance_frontiers(function, &idom);
+
+    // Blocks assigning each variable, in a fixed order so the output is deterministic
+    let mut assigned_in: BTreeMap<String, BTreeSet<BlockId>> = BTreeMap::new();
+    for (id, block) in function.blocks.iter().enumerate() {
+        for inst in &block.insts {
+            if let Inst::Store { var, .. } = inst {
+                assigned_in.entry(var.clone()).or_default().insert(id);
+            }
+        }
+    }
+
+    let mut phi_vars = HashMap::new();
+    for (var, blocks) in &assigned_in {
+        let mut has_phi = BTreeSet::new();
+        let mut worklist: Vec<BlockId> = blocks.iter().copied().collect();
+
+        while let Some(block) = worklist.pop() {
+            for &frontier in &frontiers[block] {
+                if !has_phi.insert(frontier) {
+                    continue;
+                }
+
+                let dst = function.new_value();
+                function.names.insert(dst, var.clone());
+                let insts = &mut function.blocks[frontier].insts;
+                let position = insts.iter().take_while(|inst| matches!(inst, Inst::Phi { .. })).count();
+                insts.insert(position, Inst::Phi { dst, args: Vec::new() });
+                phi_vars.insert(dst, var.clone());
+
+                // The phi is a new assignment of the variable, so its frontier needs phis too
+                if !blocks.contains(&frontier) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    let mut children = vec![Vec::new(); function.blocks.len()];
+    for (block, parent) in idom.iter().enumerate() {
+        if let Some(parent) = *parent {
+            if parent != block {
+                children[parent].push(block);
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        function,
+        phi_vars,
+        definitions: HashMap::new(),
+        loads: HashMap::new(),
+        undefined: None,
+    };
+
+    // Walk the dominator tree depth first, undoing each block's definitions when leaving it
+    let mut stack = vec![(0, None)];
+    while let Some((block, defined)) = stack.pop() {
+        match defined {
+            Some(defined) => {
+                for var in defined {
+                    renamer.definitions.get_mut(&var).unwrap().pop();
+                }
+            }
+            None => {
+                let defined = renamer.block(block);
+                stack.push((block, Some(defined)));
+                for &child in children[block].iter().rev() {
+                    stack.push((child, None));
+                }
+            }
+        }
+    }
+
+    // Variables read on a path with no assignment read zero, like the C backend's variables
+    if let Some(undefined) = renamer.undefined {
+        function.blocks[0].insts.insert(
+            0,
+            Inst::Const {
+                dst: undefined,
+                value: 0.0,
+            },
+        );
+    }
+}
+
+/// Return the dominance frontier of every block: the blocks where its dominance ends
+fn dominance_frontiers(function: &Function, idom: &[Option<BlockId>]) -> Vec<BTreeSet<BlockId>> {
+    let predecessors = function.predecessors();
+    let mut frontiers = vec![BTreeSet::new(); function.blocks.len()];
+
+    for (block, predecessors) in predecessors.iter().enumerate() {
+        if predecessors.len() < 2 {
+            continue;
+        }
+
+        for &predecessor in predecessors {
+            let mut runner = predecessor;
+            while Some(runner) != idom[block] {
+                frontiers[runner].insert(block);
+                runner = idom[runner].unwrap();
+            }
+        }
+    }
+
+    frontiers
+}
+
+struct Renamer<'a> {
+    function: &'a mut Function,
+    /// The phi nodes placed for each variable
+    phi_vars: HashMap<Value, String>,
+    /// Stack of the definitions of each variable along the current dominator tree path
+    definitions: HashMap<String, Vec<Value>>,
+    /// The value each load has been replaced with
+    loads: HashMap<Value, Value>,
+    /// A zero constant standing in for variables read before any assignment
+    undefined: Option<Value>,
+}
+
+impl Renamer<'_> {
+    /// Rename the loads and stores of a block and fill in its successors' phi nodes.
+    /// Return the variables it defined, to be popped once its dominator subtree is done.
+    fn block(&mut self, block: BlockId) -> Vec<String> {
+        let mut defined = Vec::new();
+        let mut insts = Vec::new();
+
+        for mut inst in std::mem::take(&mut self.function.blocks[block].insts) {
+            match inst {
+                Inst::Phi { dst, .. } => {
+                    let var = self.phi_vars[&dst].clone();
+                    self.definitions.entry(var.clone()).or_default().push(dst);
+                    defined.push(var);
+                    insts.push(inst);
+                }
+                Inst::Load { dst, var } => {
+                    let current = self.current(&var);
+                    self.loads.insert(dst, current);
+                }
+                Inst::Store { var, src } => {
+                    let dst = self.function.new_value();
+                    self.function.names.insert(dst, var.clone());
+                    insts.push(Inst::Copy {
+                        dst,
+                        src: self.renamed(src),
+                    });
+                    self.definitions.entry(var.clone()).or_default().push(dst);
+                    defined.push(var);
+                }
+                _ => {
+                    for value in inst.uses_mut() {
+                        *value = self.renamed(*value);
+                    }
+                    insts.push(inst);
+                }
+            }
+        }
+
+        let mut terminator = self.function.blocks[block].terminator.clone();
+        for value in terminator.uses_mut() {
+            *value = self.renamed(*value);
+        }
+        self.function.blocks[block].insts = insts;
+        self.function.blocks[block].terminator = terminator;
+
+        for successor in self.function.blocks[block].terminator.successors() {
+            for index in 0..self.function.blocks[successor].insts.len() {
+                let Inst::Phi { dst, .. } = self.function.blocks[successor].insts[index] else {
+                    continue;
+                };
+                let Some(var) = self.phi_vars.get(&dst).cloned() else {
+                    continue;
+                };
+
+                let current = self.current(&var);
+                if let Inst::Phi { args, .. } = &mut self.function.blocks[successor].insts[index] {
+                    args.push((block, current));
+                }
+            }
+        }
+
+        defined
+    }
+
+    /// Return the definition of the variable reaching the current block
+    fn current(&mut self, var: &str) -> Value {
+        if let Some(&value) = self.definitions.get(var).and_then(|stack| stack.last()) {
+            return value;
+        }
+
+        match self.undefined {
+            Some(undefined) => undefined,
+            None => {
+                let undefined = self.function.new_value();
+                self.undefined = Some(undefined);
+                undefined
+            }
+        }
+    }
+
+    /// Return the value to use in place of a value that may have been a load
+    fn renamed(&self, value: Value) -> Value {
+        *self.loads.get(&value).unwrap_or(&value)
+    }
+}
+
+/// Convert a function out of SSA form, replacing every phi by copies at the end of its predecessors.
+///
+/// Edges from a block with several successors into a block with phis are split first, so the
+/// copies only run on the edge they belong to. The copies of each edge happen at once, so they are
+/// ordered to never overwrite a value another copy still reads, going through a temporary to break cycles.
+pub fn destruct(function: &mut Function) {
+    split_critical_edges(function);
+
+    for block in 0..function.blocks.len() {
+        let phis: Vec<(Value, Vec<(BlockId, Value)>)> = function.blocks[block]
+            .insts
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::Phi { dst, args } => Some((*dst, args.clone())),
+                _ => None,
+            })
+            .collect();
+        if phis.is_empty() {
+            continue;
+        }
+
+        function.blocks[block]
+            .insts
+            .retain(|inst| !matches!(inst, Inst::Phi { .. }));
+
+        let mut predecessors: Vec<BlockId> = phis[0].1.iter().map(|(predecessor, _)| *predecessor).collect();
+        predecessors.dedup();
+        for predecessor in predecessors {
+            let copies = phis
+                .iter()
+                .map(|(dst, args)| {
+                    let (_, src) = args.iter().find(|(from, _)| *from == predecessor).unwrap();
+                    (*dst, *src)
+                })
+                .collect();
+            let sequential = sequentialize(function, copies);
+            function.blocks[predecessor].insts.extend(sequential);
+        }
+    }
+}
+
+/// Give every edge from a block with several successors into a block with phis a block of its own
+fn split_critical_edges(function: &mut Function) {
+    let predecessors = function.predecessors();
+
+    for block in 0..function.blocks.len() {
+        if function.blocks[block].terminator.successors().len() < 2 {
+            continue;
+        }
+
+        let mut terminator = function.blocks[block].terminator.clone();
+        for successor in terminator.successors_mut() {
+            let has_phis = matches!(function.blocks[*successor].insts.first(), Some(Inst::Phi { .. }));
+            if predecessors[*successor].len() < 2 || !has_phis {
+                continue;
+            }
+
+            let split = function.blocks.len();
+            function.blocks.push(Block {
+                insts: Vec::new(),
+                terminator: Terminator::Jump(*successor),
+            });
+
+            // A branch with both targets the same block has two arguments from it, one per edge
+            for inst in &mut function.blocks[*successor].insts {
+                if let Inst::Phi { args, .. } = inst {
+                    if let Some(arg) = args.iter_mut().find(|(from, _)| *from == block) {
+                        arg.0 = split;
+                    }
+                }
+            }
+            *successor = split;
+        }
+        function.blocks[block].terminator = terminator;
+    }
+}
+
+/// Order copies that all happen at once into a sequence with the same effect
+fn sequentialize(function: &mut Function, mut copies: Vec<(Value, Value)>) -> Vec<Inst> {
+    copies.retain(|(dst, src)| dst != src);
+    let mut sequence = Vec::new();
+
+    while !copies.is_empty() {
+        // A copy is safe once no other pending copy still reads the value it overwrites
+        match copies
+            .iter()
+            .position(|(dst, _)| copies.iter().all(|(_, src)| src != dst))
+        {
+            Some(index) => {
+                let (dst, src) = copies.remove(index);
+                sequence.push(Inst::Copy { dst, src });
+            }
+            None => {
+                // Every remaining copy is part of a cycle: save one value and read it from the copy
+                let (_, src) = copies[0];
+                let temporary = function.new_value();
+                sequence.push(Inst::Copy { dst: temporary, src });
+                for copy in &mut copies {
+                    if copy.1 == src {
+                        copy.1 = temporary;
+                    }
+                }
+            }
+        }
+    }
+
+    sequence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::Cfg;
+    use crate::{lower, parse, passes};
+
+    /// A loop swapping two variables, whose phis copy each other once the copies are propagated
+    const SWAP: &str =
+        "LET a = 1\nLET b = 2\nWHILE a < b REPEAT\n    LET t = a\n    LET a = b\n    LET b = t\nENDWHILE\n";
+
+    fn ssa(source: &str) -> Function {
+        let mut function = lower::lower(&Cfg::new(&parse(source).unwrap()));
+        construct(&mut function);
+        function
+    }
+
+    #[test]
+    fn construct_places_phis() {
+        // Variables assigned in the loop get a phi at its head, and t, read before it's assigned
+        // as far as the phi can tell, starts at zero
+        assert_eq!(
+            ssa(SWAP).to_string(),
+            "\
+b0:
+    %13 = const 0
+    %0 = const 1
+    %a.11 = copy %0
+    %1 = const 2
+    %b.12 = copy %1
+    jump b1
+b1:
+    %a.8 = phi [b0: %a.11], [b2: %a.15]
+    %b.9 = phi [b0: %b.12], [b2: %b.16]
+    %t.10 = phi [b0: %13], [b2: %t.14]
+    %4 = lt %a.8, %b.9
+    branch %4, b2, b3
+b2:
+    %t.14 = copy %a.8
+    %a.15 = copy %b.9
+    %b.16 = copy %t.14
+    jump b1
+b3:
+    return
+"
+        );
+    }
+
+    #[test]
+    fn destruct_breaks_copy_cycles() {
+        let mut function = ssa(SWAP);
+        passes::propagate_copies(&mut function);
+        destruct(&mut function);
+        assert_eq!(
+            function.to_string(),
+            "\
+b0:
+    %13 = const 0
+    %a.0 = const 1
+    %b.1 = const 2
+    %a.8 = copy %a.0
+    %b.9 = copy %b.1
+    %t.10 = copy %13
+    jump b1
+b1:
+    %4 = lt %a.8, %b.9
+    branch %4, b2, b3
+b2:
+    %t.10 = copy %a.8
+    %17 = copy %b.9
+    %b.9 = copy %a.8
+    %a.8 = copy %17
+    jump b1
+b3:
+    return
+"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use super::span::Span;
+use std::collections::HashMap;
+
+pub type SymbolId = usize;
+pub type ScopeId = usize;
+
+/// Variables and labels live in separate namespaces, so `LABEL x` and `LET x = 1` don't clash.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Variable,
+    Label,
+}
+
+/// Every variable holds a float. Labels have no type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Type {
+    Float,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UseKind {
+    /// The value of a variable is read, or a label is the target of a GOTO
+    Read,
+    /// A variable is assigned again after its declaration
+    Write,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Use {
+    pub span: Span,
+    pub kind: UseKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub ty: Option<Type>,
+    /// Where the symbol is declared: the first assignment of a variable, or the LABEL statement
+    pub declared: Span,
+    pub scope: ScopeId,
+    pub uses: Vec<Use>,
+}
+
+/// The scope of the whole program, which owns every variable and label. IF and WHILE bodies
+/// don't get a scope of their own, since a variable first assigned in one is visible after it.
+pub const PROGRAM_SCOPE: ScopeId = 0;
+
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    names: HashMap<(SymbolKind, String), SymbolId>,
+}
+
+/// Records every symbol of a program along with the scope it was declared in
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Declare a symbol in the given scope.
+    /// If the scope already has a symbol of that kind and name, return it as the error.
+    pub fn declare(
+        &mut self,
+        scope: ScopeId,
+        kind: SymbolKind,
+        name: &str,
+        ty: Option<Type>,
+        declared: Span,
+    ) -> Result<SymbolId, SymbolId> {
+        let key = (kind, name.to_owned());
+        if let Some(&existing) = self.scopes[scope].names.get(&key) {
+            return Err(existing);
+        }
+
+        let id = self.symbols.len();
+        self.symbols.push(Symbol {
+            name: name.to_owned(),
+            kind,
+            ty,
+            declared,
+            scope,
+            uses: Vec::new(),
+        });
+        self.scopes[scope].names.insert(key, id);
+        Ok(id)
+    }
+
+    /// Find the symbol a name refers to in the given scope
+    pub fn lookup(&self, scope: ScopeId, kind: SymbolKind, name: &str) -> Option<SymbolId> {
+        self.scopes[scope].names.get(&(kind, name.to_owned())).copied()
+    }
+
+    pub fn add_use(&mut self, symbol: SymbolId, span: Span, kind: UseKind) {
+        self.symbols[symbol].uses.push(Use { span, kind });
+    }
+
+    pub fn symbol(&self, symbol: SymbolId) -> &Symbol {
+        &self.symbols[symbol]
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,3 +1,5 @@
+use super::span::Span;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(i32)]
 pub enum TokenType {
@@ -7,6 +9,8 @@ pub enum TokenType {
     Number,
     Ident,
     String,
+    /// A comment, only produced by a lexer keeping comments
+    Comment,
     // Keywords
     Label = 101,
     Goto,
@@ -38,9 +42,26 @@ pub enum TokenType {
 pub struct Token {
     pub text: String,
     pub kind: TokenType,
+    pub span: Span,
+    /// Text of the `##` doc comments directly preceding this token, if any.
+    pub doc: Option<String>,
 }
 
 impl Token {
+    pub fn new(text: String, kind: TokenType) -> Self {
+        Self {
+            text,
+            kind,
+            span: Span::default(),
+            doc: None,
+        }
+    }
+
+    /// Every keyword `check_if_keyword` recognizes
+    pub const KEYWORDS: [&'static str; 12] = [
+        "LABEL", "GOTO", "PRINT", "PRINTLN", "INPUT", "LET", "IF", "THEN", "ENDIF", "WHILE", "REPEAT", "ENDWHILE",
+    ];
+
     /// Return the token type of a given string keyword
     pub fn check_if_keyword(token_text: &str) -> TokenType {
         match token_text {
@@ -66,9 +87,6 @@ impl Token {
 /// it has the Default trait.
 impl Default for Token {
     fn default() -> Self {
-        Self {
-            text: String::new(),
-            kind: TokenType::Unknown,
-        }
+        Self::new(String::new(), TokenType::Unknown)
     }
 }
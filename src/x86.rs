@@ -0,0 +1,314 @@
+use std::fmt;
+
+/// An SSE register, xmm0 to xmm15
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Xmm(pub u8);
+
+/// The general purpose registers the generated code and the runtime use
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gpr {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R12,
+}
+
+/// Scalar double operations of the form `dst = dst op src`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SseOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Xor,
+}
+
+/// Conditions for conditional jumps
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Equal,
+    NotEqual,
+    /// Unsigned less than, or less than for ucomisd
+    Below,
+    BelowEqual,
+    Above,
+    AboveEqual,
+    /// Signed less than
+    Less,
+    GreaterEqual,
+    /// Set when a comparison was unordered, because one side was NaN
+    Parity,
+}
+
+/// An x86-64 machine instruction.
+///
+/// Calls into the runtime are kept abstract, since the assembly backend calls the C library
+/// while other targets bring their own runtime. They clobber every SSE register.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MInst {
+    Label(usize),
+    Push(Gpr),
+    MovGpr {
+        dst: Gpr,
+        src: Gpr,
+    },
+    SubImm {
+        dst: Gpr,
+        imm: u32,
+    },
+    /// `xor dst, dst`
+    Zero(Gpr),
+    /// Load a 64 bit immediate
+    MovImm {
+        dst: Gpr,
+        imm: u64,
+    },
+    /// Move the bits of a general purpose register into an SSE register
+    MovqToXmm {
+        dst: Xmm,
+        src: Gpr,
+    },
+    MovsdReg {
+        dst: Xmm,
+        src: Xmm,
+    },
+    /// Load from the stack frame, at an offset from rbp
+    MovsdLoad {
+        dst: Xmm,
+        offset: i32,
+    },
+    /// Store to the stack frame, at an offset from rbp
+    MovsdStore {
+        offset: i32,
+        src: Xmm,
+    },
+    Sse {
+        op: SseOp,
+        dst: Xmm,
+        src: Xmm,
+    },
+    /// Set dst to all ones if the predicate holds between dst and src, or to zero if it doesn't
+    Cmpsd {
+        dst: Xmm,
+        src: Xmm,
+        predicate: u8,
+    },
+    Ucomisd {
+        left: Xmm,
+        right: Xmm,
+    },
+    /// Round a double to single precision, in the low 32 bits of dst
+    Cvtsd2ss {
+        dst: Xmm,
+        src: Xmm,
+    },
+    /// Widen a single precision float in the low 32 bits of src back to a double
+    Cvtss2sd {
+        dst: Xmm,
+        src: Xmm,
+    },
+    Jcc {
+        cond: Cond,
+        target: usize,
+    },
+    Jmp(usize),
+    Leave,
+    Ret,
+    /// Print the number in xmm0 on a line of its own
+    PrintNumber,
+    /// Print one of the program's strings on a line of its own
+    PrintString(usize),
+    /// Read a number from standard input into xmm0, reading zero if the input isn't a number
+    Input,
+}
+
+/// A compiled program: the body of `main` and the strings it prints
+#[derive(Debug)]
+pub struct Program {
+    pub code: Vec<MInst>,
+    pub strings: Vec<String>,
+}
+
+impl Gpr {
+    fn name(&self) -> &'static str {
+        match self {
+            Gpr::Rax => "rax",
+            Gpr::Rcx => "rcx",
+            Gpr::Rdx => "rdx",
+            Gpr::Rbx => "rbx",
+            Gpr::Rsp => "rsp",
+            Gpr::Rbp => "rbp",
+            Gpr::Rsi => "rsi",
+            Gpr::Rdi => "rdi",
+            Gpr::R8 => "r8",
+            Gpr::R9 => "r9",
+            Gpr::R10 => "r10",
+            Gpr::R12 => "r12",
+        }
+    }
+
+    /// The register's number in instruction encodings
+    pub fn number(&self) -> u8 {
+        match self {
+            Gpr::Rax => 0,
+            Gpr::Rcx => 1,
+            Gpr::Rdx => 2,
+            Gpr::Rbx => 3,
+            Gpr::Rsp => 4,
+            Gpr::Rbp => 5,
+            Gpr::Rsi => 6,
+            Gpr::Rdi => 7,
+            Gpr::R8 => 8,
+            Gpr::R9 => 9,
+            Gpr::R10 => 10,
+            Gpr::R12 => 12,
+        }
+    }
+}
+
+impl Cond {
+    /// The condition's number in the encodings of jcc and setcc
+    pub fn number(&self) -> u8 {
+        match self {
+            Cond::Below => 0x2,
+            Cond::AboveEqual => 0x3,
+            Cond::Equal => 0x4,
+            Cond::NotEqual => 0x5,
+            Cond::BelowEqual => 0x6,
+            Cond::Above => 0x7,
+            Cond::Parity => 0xa,
+            Cond::Less => 0xc,
+            Cond::GreaterEqual => 0xd,
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Cond::Equal => "je",
+            Cond::NotEqual => "jne",
+            Cond::Below => "jb",
+            Cond::BelowEqual => "jbe",
+            Cond::Above => "ja",
+            Cond::AboveEqual => "jae",
+            Cond::Less => "jl",
+            Cond::GreaterEqual => "jge",
+            Cond::Parity => "jp",
+        }
+    }
+}
+
+impl fmt::Display for Xmm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "xmm{}", self.0)
+    }
+}
+
+/// Print the program as GNU assembler source in Intel syntax, calling the C library for input and output
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    .intel_syntax noprefix")?;
+        writeln!(f, "    .section .rodata")?;
+        for (index, string) in self.strings.iter().enumerate() {
+            writeln!(f, ".Lstring{}:", index)?;
+            writeln!(f, "    .string \"{}\"", escape(string))?;
+        }
+        writeln!(f, ".Lnumber_format:")?;
+        writeln!(f, "    .string \"%.2f\\n\"")?;
+        writeln!(f, ".Linput_format:")?;
+        writeln!(f, "    .string \"%lf\"")?;
+        writeln!(f, ".Lskip_format:")?;
+        writeln!(f, "    .string \"%*s\"")?;
+        writeln!(f)?;
+        writeln!(f, "    .text")?;
+        writeln!(f, "    .globl main")?;
+        writeln!(f, "main:")?;
+
+        let mut inputs = 0;
+        for inst in &self.code {
+            match inst {
+                MInst::Label(label) => writeln!(f, ".L{}:", label)?,
+                MInst::Push(reg) => writeln!(f, "    push {}", reg.name())?,
+                MInst::MovGpr { dst, src } => writeln!(f, "    mov {}, {}", dst.name(), src.name())?,
+                MInst::SubImm { dst, imm } => writeln!(f, "    sub {}, {}", dst.name(), imm)?,
+                MInst::Zero(Gpr::Rax) => writeln!(f, "    xor eax, eax")?,
+                MInst::Zero(reg) => writeln!(f, "    xor {0}, {0}", reg.name())?,
+                MInst::MovImm { dst, imm } => writeln!(f, "    movabs {}, {:#x}", dst.name(), imm)?,
+                MInst::MovqToXmm { dst, src } => writeln!(f, "    movq {}, {}", dst, src.name())?,
+                MInst::MovsdReg { dst, src } => writeln!(f, "    movsd {}, {}", dst, src)?,
+                MInst::MovsdLoad { dst, offset } => writeln!(f, "    movsd {}, qword ptr [rbp{:+}]", dst, offset)?,
+                MInst::MovsdStore { offset, src } => writeln!(f, "    movsd qword ptr [rbp{:+}], {}", offset, src)?,
+                MInst::Sse { op, dst, src } => {
+                    let mnemonic = match op {
+                        SseOp::Add => "addsd",
+                        SseOp::Sub => "subsd",
+                        SseOp::Mul => "mulsd",
+                        SseOp::Div => "divsd",
+                        SseOp::And => "andpd",
+                        SseOp::Xor => "xorpd",
+                    };
+                    writeln!(f, "    {} {}, {}", mnemonic, dst, src)?
+                }
+                MInst::Cmpsd { dst, src, predicate } => writeln!(f, "    cmpsd {}, {}, {}", dst, src, predicate)?,
+                MInst::Ucomisd { left, right } => writeln!(f, "    ucomisd {}, {}", left, right)?,
+                MInst::Cvtsd2ss { dst, src } => writeln!(f, "    cvtsd2ss {}, {}", dst, src)?,
+                MInst::Cvtss2sd { dst, src } => writeln!(f, "    cvtss2sd {}, {}", dst, src)?,
+                MInst::Jcc { cond, target } => writeln!(f, "    {} .L{}", cond.mnemonic(), target)?,
+                MInst::Jmp(target) => writeln!(f, "    jmp .L{}", target)?,
+                MInst::Leave => writeln!(f, "    leave")?,
+                MInst::Ret => writeln!(f, "    ret")?,
+                MInst::PrintNumber => {
+                    writeln!(f, "    lea rdi, [rip + .Lnumber_format]")?;
+                    writeln!(f, "    mov eax, 1")?;
+                    writeln!(f, "    call printf@PLT")?
+                }
+                MInst::PrintString(index) => {
+                    writeln!(f, "    lea rdi, [rip + .Lstring{}]", index)?;
+                    writeln!(f, "    call puts@PLT")?
+                }
+                MInst::Input => {
+                    // Read into a slot below the frame, keeping the stack aligned for the calls
+                    writeln!(f, "    sub rsp, 16")?;
+                    writeln!(f, "    lea rdi, [rip + .Linput_format]")?;
+                    writeln!(f, "    mov rsi, rsp")?;
+                    writeln!(f, "    xor eax, eax")?;
+                    writeln!(f, "    call scanf@PLT")?;
+                    writeln!(f, "    cmp eax, 1")?;
+                    writeln!(f, "    je .Linput{}", inputs)?;
+                    writeln!(f, "    mov qword ptr [rsp], 0")?;
+                    writeln!(f, "    lea rdi, [rip + .Lskip_format]")?;
+                    writeln!(f, "    xor eax, eax")?;
+                    writeln!(f, "    call scanf@PLT")?;
+                    writeln!(f, ".Linput{}:", inputs)?;
+                    writeln!(f, "    movsd xmm0, qword ptr [rsp]")?;
+                    writeln!(f, "    add rsp, 16")?;
+                    inputs += 1;
+                }
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "    .section .note.GNU-stack,\"\",@progbits")
+    }
+}
+
+/// Escape a string for a `.string` directive, writing anything but printable ASCII as octal bytes
+fn escape(string: &str) -> String {
+    let mut escaped = String::new();
+    for byte in string.bytes() {
+        match byte {
+            b'"' | b'\\' => escaped.push_str(&format!("\\{}", byte as char)),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}